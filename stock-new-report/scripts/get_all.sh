@@ -1 +1,20 @@
-curl -X GET -d @report.json http://127.0.0.1:8080/items
+# /items returns one page at a time as {"items": [...], "next-cursor": ...};
+# follow the cursor until it runs out and print every report as one array.
+# Needs jq.
+URL=http://127.0.0.1:8080/items
+LIMIT=${LIMIT:-500}
+CURSOR=""
+ALL="[]"
+
+while : ; do
+    if [ -z "$CURSOR" ]; then
+        PAGE=`curl -s -G --data-urlencode "limit=$LIMIT" $URL`
+    else
+        PAGE=`curl -s -G --data-urlencode "limit=$LIMIT" --data-urlencode "cursor=$CURSOR" $URL`
+    fi
+    ALL=`echo "$ALL" "$PAGE" | jq -s '.[0] + .[1].items'` || exit 1
+    CURSOR=`echo "$PAGE" | jq -r '.["next-cursor"] // empty'`
+    [ -z "$CURSOR" ] && break
+done
+
+echo "$ALL"
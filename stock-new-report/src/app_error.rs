@@ -0,0 +1,189 @@
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use log::error;
+use serde::Serialize;
+use std::fmt;
+
+use crate::currency::ConversionError;
+use crate::report_computer::CustomMetricError;
+use crate::report_growth::GrowthError;
+use crate::report_model::ReportError;
+use crate::report_store::StoreError;
+use crate::report_validator::YearFindings;
+use crate::valuation::ValuationError;
+
+/// Every failure a handler can return. Each variant maps to one stable `code`
+/// string in the JSON body, so clients can branch on it instead of on text.
+#[derive(Debug)]
+pub enum AppError {
+    TickerNotFound(String),
+    YearNotFound { ticker: String, year: i32 },
+    QuarterNotFound { ticker: String, year: i32, quarter: u8 },
+    /// The last four quarters are missing or not consecutive.
+    TtmUnavailable(String),
+    RevisionNotFound { ticker: String, revision: i32 },
+    CustomMetricNotFound(String),
+    /// The name is no figure, ratio or metric the explain endpoint knows.
+    MetricNotFound(String),
+    TickerExists(String),
+    VersionConflict { ticker: String, expected_version: i32 },
+    DuplicateYear(i32),
+    DuplicateQuarter { year: i32, quarter: u8 },
+    InvalidInput(String),
+    /// The submitted figures break an accounting identity; the findings say where.
+    ValidationFailed(Vec<YearFindings>),
+    /// Details are logged where the error happens and never sent to clients.
+    Internal,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: &'a str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    findings: Option<&'a [YearFindings]>,
+}
+
+impl AppError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::TickerNotFound(_) => "ticker-not-found",
+            AppError::YearNotFound { .. } => "year-not-found",
+            AppError::QuarterNotFound { .. } => "quarter-not-found",
+            AppError::TtmUnavailable(_) => "ttm-unavailable",
+            AppError::RevisionNotFound { .. } => "revision-not-found",
+            AppError::CustomMetricNotFound(_) => "custom-metric-not-found",
+            AppError::MetricNotFound(_) => "metric-not-found",
+            AppError::TickerExists(_) => "ticker-already-exists",
+            AppError::VersionConflict { .. } => "version-conflict",
+            AppError::DuplicateYear(_) => "duplicate-year",
+            AppError::DuplicateQuarter { .. } => "duplicate-quarter",
+            AppError::InvalidInput(_) => "invalid-input",
+            AppError::ValidationFailed(_) => "validation-failed",
+            AppError::Internal => "internal-error",
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::TickerNotFound(ticker) => write!(f, "Ticker {} does not exist", ticker),
+            AppError::YearNotFound { ticker, year } => write!(f, "Ticker {} has no report for {}", ticker, year),
+            AppError::QuarterNotFound { ticker, year, quarter } => {
+                write!(f, "Ticker {} has no report for {} Q{}", ticker, year, quarter)
+            }
+            AppError::TtmUnavailable(ticker) => {
+                write!(f, "Ticker {} needs four consecutive quarters for trailing figures", ticker)
+            }
+            AppError::RevisionNotFound { ticker, revision } => {
+                write!(f, "Revision {} of {} does not exist", revision, ticker)
+            }
+            AppError::CustomMetricNotFound(id) => write!(f, "Custom metric {} does not exist", id),
+            AppError::MetricNotFound(metric) => write!(f, "There is no figure or metric called {}", metric),
+            AppError::TickerExists(ticker) => write!(f, "Ticker {} already exists", ticker),
+            AppError::VersionConflict { ticker, expected_version } => write!(
+                f,
+                "Report for {} was modified concurrently (expected version {})",
+                ticker, expected_version
+            ),
+            AppError::DuplicateYear(year) => write!(f, "A report for {} already exists", year),
+            AppError::DuplicateQuarter { year, quarter } => {
+                write!(f, "A report for {} Q{} already exists", year, quarter)
+            }
+            AppError::InvalidInput(reason) => write!(f, "{}", reason),
+            AppError::ValidationFailed(years) => {
+                let years: Vec<String> = years.iter().map(|year| year.period()).collect();
+                write!(f, "Report failed validation for {}", years.join(", "))
+            }
+            AppError::Internal => write!(f, "Internal server error"),
+        }
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::TickerNotFound(_)
+            | AppError::YearNotFound { .. }
+            | AppError::QuarterNotFound { .. }
+            | AppError::TtmUnavailable(_)
+            | AppError::RevisionNotFound { .. }
+            | AppError::CustomMetricNotFound(_)
+            | AppError::MetricNotFound(_) => StatusCode::NOT_FOUND,
+            AppError::TickerExists(_)
+            | AppError::VersionConflict { .. }
+            | AppError::DuplicateYear(_)
+            | AppError::DuplicateQuarter { .. } => StatusCode::CONFLICT,
+            AppError::InvalidInput(_) | AppError::ValidationFailed(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(ErrorBody {
+            code: self.code(),
+            message: self.to_string(),
+            findings: match self {
+                AppError::ValidationFailed(years) => Some(years),
+                _ => None,
+            },
+        })
+    }
+}
+
+impl From<StoreError> for AppError {
+    fn from(err: StoreError) -> AppError {
+        match err {
+            StoreError::AlreadyExists(ticker) => AppError::TickerExists(ticker),
+            StoreError::VersionConflict { ticker, expected_version } => {
+                AppError::VersionConflict { ticker, expected_version }
+            }
+            StoreError::Backend(reason) => {
+                error!("Storage error: {}", reason);
+                AppError::Internal
+            }
+        }
+    }
+}
+
+impl From<ReportError> for AppError {
+    fn from(err: ReportError) -> AppError {
+        match err {
+            ReportError::DuplicateYear(year) => AppError::DuplicateYear(year),
+            ReportError::DuplicateQuarter { year, quarter } => AppError::DuplicateQuarter { year, quarter },
+            ReportError::InvalidQuarter(_)
+            | ReportError::InvalidCurrency(_)
+            | ReportError::InvalidCorporateAction(_) => AppError::InvalidInput(err.to_string()),
+        }
+    }
+}
+
+impl From<CustomMetricError> for AppError {
+    fn from(err: CustomMetricError) -> AppError {
+        AppError::InvalidInput(err.to_string())
+    }
+}
+
+impl From<GrowthError> for AppError {
+    fn from(err: GrowthError) -> AppError {
+        AppError::InvalidInput(err.to_string())
+    }
+}
+
+impl From<ValuationError> for AppError {
+    fn from(err: ValuationError) -> AppError {
+        AppError::InvalidInput(err.to_string())
+    }
+}
+
+impl From<ConversionError> for AppError {
+    fn from(err: ConversionError) -> AppError {
+        AppError::InvalidInput(err.to_string())
+    }
+}
+
+/// Turns extractor failures (malformed JSON, bad query or path parameters)
+/// into the same JSON error shape as everything else.
+pub fn invalid_input<E: fmt::Display>(err: E, _req: &actix_web::HttpRequest) -> actix_web::Error {
+    AppError::InvalidInput(err.to_string()).into()
+}
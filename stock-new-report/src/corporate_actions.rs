@@ -0,0 +1,248 @@
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::report_model::{AnnualStockReport, CashFlowStatement, FiscalPeriod, IncomeStatement, ReportError};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ActionKind {
+    /// `ratio` new shares per old share, e.g. 2 for a 2-for-1 split.
+    Split,
+    /// `ratio` old shares per new share, e.g. 10 for a 1-for-10 reverse split.
+    ReverseSplit,
+    /// `ratio` new shares per share held, e.g. 0.05 for a 5% stock dividend.
+    StockDividend,
+    /// `ratio` is the part of the company's value that was spun off, e.g. 0.2.
+    SpinOff,
+}
+
+impl ActionKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ActionKind::Split => "split",
+            ActionKind::ReverseSplit => "reverse-split",
+            ActionKind::StockDividend => "stock-dividend",
+            ActionKind::SpinOff => "spin-off",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<ActionKind> {
+        [ActionKind::Split, ActionKind::ReverseSplit, ActionKind::StockDividend, ActionKind::SpinOff]
+            .into_iter()
+            .find(|kind| kind.as_str() == name)
+    }
+}
+
+/// An event that changes what one share stands for. Stored figures stay as
+/// reported; periods that ended before `date` are adjusted when presented.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct CorporateAction {
+    /// First day the shares traded on the new basis.
+    pub date: NaiveDate,
+    pub kind: ActionKind,
+    pub ratio: f64,
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+impl CorporateAction {
+    /// How many shares of today one earlier share became. A spin-off leaves
+    /// the count alone but takes `ratio` of the value away, so an earlier
+    /// share is worth `1 / (1 - ratio)` of today's. Restating the count that
+    /// way keeps per-share figures derived from amounts, like
+    /// `fcf-per-share`, on the same basis as the restated reported ones.
+    fn share_factor(&self) -> f64 {
+        match self.kind {
+            ActionKind::Split => self.ratio,
+            ActionKind::ReverseSplit => 1.0 / self.ratio,
+            ActionKind::StockDividend => 1.0 + self.ratio,
+            ActionKind::SpinOff => 1.0 / (1.0 - self.ratio),
+        }
+    }
+
+    fn check(&self) -> Result<(), ReportError> {
+        let valid = match self.kind {
+            ActionKind::SpinOff => self.ratio > 0.0 && self.ratio < 1.0,
+            _ => self.ratio.is_finite() && self.ratio > 0.0,
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(ReportError::InvalidCorporateAction(format!(
+                "{} on {} has ratio {}; {}",
+                self.kind.as_str(),
+                self.date,
+                self.ratio,
+                match self.kind {
+                    ActionKind::SpinOff => "a spin-off takes a fraction between 0 and 1",
+                    _ => "it must be a positive number",
+                }
+            )))
+        }
+    }
+}
+
+/// Checks every action and orders them by date.
+pub fn normalize(actions: &mut [CorporateAction]) -> Result<(), ReportError> {
+    actions.iter().try_for_each(CorporateAction::check)?;
+    actions.sort_by_key(|action| action.date);
+    Ok(())
+}
+
+/// Combined `(share, per-share)` factors of every action after `period_end`.
+fn factors_after(actions: &[CorporateAction], period_end: NaiveDate) -> (f64, f64) {
+    let shares: f64 = actions
+        .iter()
+        .filter(|action| action.date > period_end)
+        .map(CorporateAction::share_factor)
+        .product();
+    (shares, 1.0 / shares)
+}
+
+/// Last day of the period, falling back to the calendar year or quarter when
+/// the period has no end date.
+fn period_end(period: &FiscalPeriod, year: i32, quarter: Option<u8>) -> Option<NaiveDate> {
+    if period.end.is_some() {
+        return period.end;
+    }
+    let (month, day) = match quarter {
+        Some(1) => (3, 31),
+        Some(2) => (6, 30),
+        Some(3) => (9, 30),
+        _ => (12, 31),
+    };
+    NaiveDate::from_ymd_opt(year, month, day)
+}
+
+fn adjust_income_statement(in_state: &mut IncomeStatement, (shares, per_share): (f64, f64)) {
+    in_state.eps_basic *= per_share;
+    in_state.eps_diluted = in_state.eps_diluted.map(|eps| eps * per_share);
+    in_state.shares_outstanding_basic *= shares;
+    in_state.shares_outstanding_diluted = in_state.shares_outstanding_diluted.map(|count| count * shares);
+}
+
+fn adjust_cash_flow_statement(cfs: &mut CashFlowStatement, (_, per_share): (f64, f64)) {
+    cfs.dividends_per_share *= per_share;
+    cfs.fcf_per_share = cfs.fcf_per_share.map(|fcf| fcf * per_share);
+}
+
+/// Restates every per-share figure and share count on today's share basis and
+/// recomputes what depends on them, so growth rates don't jump at a split.
+pub fn split_adjust(report: &mut AnnualStockReport) {
+    if report.corporate_actions.is_empty() {
+        return;
+    }
+    let actions = &report.corporate_actions;
+
+    for year in report.data.iter_mut() {
+        let Some(end) = period_end(&year.fiscal_period, year.year, None) else {
+            continue;
+        };
+        let factors = factors_after(actions, end);
+        adjust_income_statement(&mut year.income_statement, factors);
+        adjust_cash_flow_statement(&mut year.cash_flow_statement, factors);
+        year.financial_ratios.avg_share_price *= factors.1;
+    }
+    for quarter in report.quarters.iter_mut() {
+        let Some(end) = period_end(&quarter.fiscal_period, quarter.year, Some(quarter.quarter)) else {
+            continue;
+        };
+        let factors = factors_after(actions, end);
+        adjust_income_statement(&mut quarter.income_statement, factors);
+        adjust_cash_flow_statement(&mut quarter.cash_flow_statement, factors);
+        quarter.avg_share_price *= factors.1;
+    }
+
+    report.compute_optional_if_required();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report_model::tests::sample_report;
+
+    fn action(date: &str, kind: ActionKind, ratio: f64) -> CorporateAction {
+        CorporateAction {
+            date: date.parse().unwrap(),
+            kind,
+            ratio,
+            note: None,
+        }
+    }
+
+    #[test]
+    fn normalize_checks_ratios_and_sorts() {
+        let mut actions = vec![
+            action("2012-06-01", ActionKind::Split, 2.0),
+            action("2011-06-01", ActionKind::StockDividend, 0.05),
+        ];
+        normalize(&mut actions).unwrap();
+        assert_eq!(actions[0].kind, ActionKind::StockDividend);
+
+        assert!(normalize(&mut [action("2012-06-01", ActionKind::Split, 0.0)]).is_err());
+        assert!(normalize(&mut [action("2012-06-01", ActionKind::SpinOff, 1.0)]).is_err());
+        assert!(normalize(&mut [action("2012-06-01", ActionKind::ReverseSplit, f64::INFINITY)]).is_err());
+    }
+
+    #[test]
+    fn restates_years_before_the_split() {
+        let mut report = sample_report();
+        report.corporate_actions = vec![action("2012-06-01", ActionKind::Split, 2.0)];
+        split_adjust(&mut report);
+
+        let before = &report.data[1];
+        assert_eq!(before.income_statement.eps_basic, 4.08 / 2.0);
+        assert_eq!(before.income_statement.shares_outstanding_basic, 1576.0 * 2.0);
+        assert_eq!(before.cash_flow_statement.dividends_per_share, 2.03 / 2.0);
+        assert_eq!(before.financial_ratios.avg_share_price, 65.21 / 2.0);
+        // Amounts don't change, and derived per-share figures follow the share count
+        assert_eq!(before.income_statement.net_income, 6443.0);
+        assert_eq!(before.cash_flow_statement.fcf_per_share, Some((8944.0 - 3339.0) / (1576.0 * 2.0)));
+
+        let after = &report.data[2];
+        assert_eq!(after.income_statement.eps_basic, 3.96);
+    }
+
+    #[test]
+    fn combines_actions_and_spin_offs() {
+        let mut report = sample_report();
+        report.corporate_actions = vec![
+            action("2011-03-01", ActionKind::ReverseSplit, 4.0),
+            action("2012-03-01", ActionKind::SpinOff, 0.25),
+        ];
+        split_adjust(&mut report);
+
+        let first = &report.data[0].income_statement;
+        assert!((first.shares_outstanding_basic - 1590.0 / 4.0 / 0.75).abs() < 1e-9);
+        assert!((first.eps_basic - 3.97 * 4.0 * 0.75).abs() < 1e-12);
+        let second = &report.data[1].income_statement;
+        assert!((second.shares_outstanding_basic - 1576.0 / 0.75).abs() < 1e-9);
+        assert!((second.eps_basic - 4.08 * 0.75).abs() < 1e-12);
+    }
+
+    #[test]
+    fn spin_offs_restate_derived_per_share_figures() {
+        let as_reported = sample_report();
+        let mut report = sample_report();
+        report.corporate_actions = vec![action("2012-03-01", ActionKind::SpinOff, 0.25)];
+        split_adjust(&mut report);
+
+        let (reported, before) = (&as_reported.data[1], &report.data[1]);
+        let fcf_per_share = before.cash_flow_statement.fcf_per_share.unwrap();
+        assert!((fcf_per_share - (8944.0 - 3339.0) / 1576.0 * 0.75).abs() < 1e-12);
+        let book_value_per_share = reported.financial_ratios.book_value_per_share.unwrap() * 0.75;
+        assert!((before.financial_ratios.book_value_per_share.unwrap() - book_value_per_share).abs() < 1e-12);
+        // Ratios of per-share figures and of the market cap come out as reported
+        for (restated, reported) in [
+            (before.financial_ratios.fcf_payout_ratio, reported.financial_ratios.fcf_payout_ratio),
+            (before.financial_ratios.price_to_book, reported.financial_ratios.price_to_book),
+            (before.financial_ratios.fcf_yield, reported.financial_ratios.fcf_yield),
+        ] {
+            assert!((restated.unwrap() - reported.unwrap()).abs() < 1e-12);
+        }
+
+        let after = &report.data[2].cash_flow_statement;
+        assert_eq!(after.fcf_per_share, as_reported.data[2].cash_flow_statement.fcf_per_share);
+    }
+}
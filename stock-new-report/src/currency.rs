@@ -0,0 +1,505 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::RwLock;
+
+use crate::report_computer::{self, Unit};
+use crate::report_model::{
+    AnnualStockReport, BalanceSheet, CashFlowStatement, FinancialRatios, IncomeStatement, QuarterlyReport, Report,
+    TrailingRatios, TrailingTwelveMonths,
+};
+
+/// The multiple every amount and share count of a report is expressed in.
+/// Per-share figures and prices are always in plain currency units.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnitScale {
+    Units,
+    Thousands,
+    Millions,
+    Billions,
+}
+
+impl UnitScale {
+    pub fn factor(self) -> f64 {
+        match self {
+            UnitScale::Units => 1.0,
+            UnitScale::Thousands => 1e3,
+            UnitScale::Millions => 1e6,
+            UnitScale::Billions => 1e9,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            UnitScale::Units => "units",
+            UnitScale::Thousands => "thousands",
+            UnitScale::Millions => "millions",
+            UnitScale::Billions => "billions",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<UnitScale> {
+        [UnitScale::Units, UnitScale::Thousands, UnitScale::Millions, UnitScale::Billions]
+            .into_iter()
+            .find(|scale| scale.as_str() == name)
+    }
+}
+
+/// Upper-cased `code` if it looks like an ISO 4217 code, e.g. `usd` -> `USD`.
+pub fn normalize_currency(code: &str) -> Option<String> {
+    if code.len() == 3 && code.chars().all(|c| c.is_ascii_alphabetic()) {
+        Some(code.to_ascii_uppercase())
+    } else {
+        None
+    }
+}
+
+#[derive(Debug)]
+pub enum ConversionError {
+    InvalidCurrency(String),
+    UnknownCurrency(String),
+    /// The report was stored without the metadata the conversion needs.
+    MissingCurrency(String),
+    MissingUnitScale(String),
+    InvalidRate { currency: String, rate: f64 },
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConversionError::InvalidCurrency(code) => write!(f, "{:?} is not a three-letter currency code", code),
+            ConversionError::UnknownCurrency(code) => write!(f, "There is no FX rate for {}", code),
+            ConversionError::MissingCurrency(ticker) => {
+                write!(f, "Ticker {} does not record its currency, so it cannot be converted", ticker)
+            }
+            ConversionError::MissingUnitScale(ticker) => {
+                write!(f, "Ticker {} does not record its unit scale, so it cannot be rescaled", ticker)
+            }
+            ConversionError::InvalidRate { currency, rate } => {
+                write!(f, "FX rate {} for {} must be a positive number", rate, currency)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+/// Spot rates quoted against `base`: one unit of `base` buys `rates[code]`
+/// units of `code`. A single rate is applied to every year of a report, so
+/// converted figures compare companies, not a company's history in another
+/// currency.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct FxRates {
+    pub base: String,
+    /// Free-form note on where the rates come from, e.g. the quote date.
+    #[serde(default)]
+    pub as_of: Option<String>,
+    #[serde(default)]
+    pub rates: BTreeMap<String, f64>,
+}
+
+impl Default for FxRates {
+    fn default() -> FxRates {
+        FxRates {
+            base: String::from("USD"),
+            as_of: None,
+            rates: BTreeMap::new(),
+        }
+    }
+}
+
+impl FxRates {
+    /// Checks every code and rate and upper-cases the codes.
+    pub fn normalized(self) -> Result<FxRates, ConversionError> {
+        let base = normalize_currency(&self.base).ok_or(ConversionError::InvalidCurrency(self.base))?;
+        let mut rates = BTreeMap::new();
+        for (code, rate) in self.rates {
+            let code = normalize_currency(&code).ok_or(ConversionError::InvalidCurrency(code))?;
+            if !(rate.is_finite() && rate > 0.0) {
+                return Err(ConversionError::InvalidRate { currency: code, rate });
+            }
+            rates.insert(code, rate);
+        }
+        Ok(FxRates {
+            base,
+            as_of: self.as_of,
+            rates,
+        })
+    }
+
+    /// Units of `code` per unit of the base currency.
+    fn rate(&self, code: &str) -> Result<f64, ConversionError> {
+        if code == self.base {
+            return Ok(1.0);
+        }
+        self.rates
+            .get(code)
+            .copied()
+            .ok_or_else(|| ConversionError::UnknownCurrency(code.to_string()))
+    }
+
+    /// Multiplier taking an amount in `from` to `to`.
+    pub fn cross_rate(&self, from: &str, to: &str) -> Result<f64, ConversionError> {
+        if from == to {
+            return Ok(1.0);
+        }
+        Ok(self.rate(to)? / self.rate(from)?)
+    }
+}
+
+/// The FX table the server converts with. Loaded from the configured file at
+/// startup and replaceable at runtime through the API.
+pub struct FxTable {
+    rates: RwLock<FxRates>,
+}
+
+impl FxTable {
+    pub fn new(rates: FxRates) -> FxTable {
+        FxTable {
+            rates: RwLock::new(rates),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<FxTable, String> {
+        let content = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let rates: FxRates = serde_json::from_str(&content).map_err(|err| err.to_string())?;
+        let rates = rates.normalized().map_err(|err| err.to_string())?;
+        Ok(FxTable::new(rates))
+    }
+
+    pub fn snapshot(&self) -> FxRates {
+        match self.rates.read() {
+            Ok(rates) => rates.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    pub fn replace(&self, rates: FxRates) {
+        match self.rates.write() {
+            Ok(mut current) => *current = rates,
+            Err(poisoned) => *poisoned.into_inner() = rates,
+        }
+    }
+}
+
+/// Target currency and scale asked for by a client. Either may be left out
+/// to keep the report's own.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct ConversionTarget {
+    pub currency: Option<String>,
+    pub unit_scale: Option<UnitScale>,
+}
+
+impl ConversionTarget {
+    pub fn is_empty(&self) -> bool {
+        self.currency.is_none() && self.unit_scale.is_none()
+    }
+
+    /// The checks of `Conversion::plan` that do not depend on a report, so a
+    /// bad target can be told apart from a report that cannot be converted.
+    pub fn check(&self, rates: &FxRates) -> Result<(), ConversionError> {
+        if let Some(to) = &self.currency {
+            let to = normalize_currency(to).ok_or_else(|| ConversionError::InvalidCurrency(to.clone()))?;
+            rates.rate(&to)?;
+        }
+        Ok(())
+    }
+}
+
+/// Factors turning one report's figures into the target currency and scale.
+#[derive(Debug, Clone)]
+pub struct Conversion {
+    currency: Option<String>,
+    unit_scale: Option<UnitScale>,
+    fx: f64,
+    scale: f64,
+}
+
+impl Conversion {
+    pub fn plan(
+        report: &AnnualStockReport,
+        target: &ConversionTarget,
+        rates: &FxRates,
+    ) -> Result<Conversion, ConversionError> {
+        let (currency, fx) = match &target.currency {
+            Some(to) => {
+                let to = normalize_currency(to).ok_or_else(|| ConversionError::InvalidCurrency(to.clone()))?;
+                let from = report
+                    .currency
+                    .as_deref()
+                    .ok_or_else(|| ConversionError::MissingCurrency(report.ticker.clone()))?;
+                let fx = rates.cross_rate(from, &to)?;
+                (Some(to), fx)
+            }
+            None => (report.currency.clone(), 1.0),
+        };
+
+        let (unit_scale, scale) = match target.unit_scale {
+            Some(to) => {
+                let from = report
+                    .unit_scale
+                    .ok_or_else(|| ConversionError::MissingUnitScale(report.ticker.clone()))?;
+                (Some(to), from.factor() / to.factor())
+            }
+            None => (report.unit_scale, 1.0),
+        };
+
+        Ok(Conversion {
+            currency,
+            unit_scale,
+            fx,
+            scale,
+        })
+    }
+
+    fn amount(&self, value: f64) -> f64 {
+        value * self.fx * self.scale
+    }
+
+    fn amount_opt(&self, value: Option<f64>) -> Option<f64> {
+        value.map(|value| self.amount(value))
+    }
+
+    fn per_share(&self, value: f64) -> f64 {
+        value * self.fx
+    }
+
+    fn per_share_opt(&self, value: Option<f64>) -> Option<f64> {
+        value.map(|value| self.per_share(value))
+    }
+
+    fn shares(&self, value: f64) -> f64 {
+        value * self.scale
+    }
+
+    fn shares_opt(&self, value: Option<f64>) -> Option<f64> {
+        value.map(|value| self.shares(value))
+    }
+}
+
+/// Rewrites every amount, share count and per-share figure in place. Ratios,
+/// margins and growth rates are unit-free and left alone.
+pub trait Convert {
+    fn convert(&mut self, conversion: &Conversion);
+}
+
+impl Convert for IncomeStatement {
+    fn convert(&mut self, c: &Conversion) {
+        self.revenue = c.amount(self.revenue);
+        self.total_cogs = c.amount(self.total_cogs);
+        self.gross_profit = c.amount_opt(self.gross_profit);
+        self.operating_expense = c.amount(self.operating_expense);
+        self.operating_income = c.amount_opt(self.operating_income);
+        self.interest_expense = c.amount(self.interest_expense);
+        self.net_income = c.amount(self.net_income);
+        self.eps_basic = c.per_share(self.eps_basic);
+        self.shares_outstanding_basic = c.shares(self.shares_outstanding_basic);
+        self.research_and_development = c.amount_opt(self.research_and_development);
+        self.selling_general_administrative = c.amount_opt(self.selling_general_administrative);
+        self.depreciation_and_amortization = c.amount_opt(self.depreciation_and_amortization);
+        self.pretax_income = c.amount_opt(self.pretax_income);
+        self.income_tax = c.amount_opt(self.income_tax);
+        self.stock_based_compensation = c.amount_opt(self.stock_based_compensation);
+        self.eps_diluted = c.per_share_opt(self.eps_diluted);
+        self.shares_outstanding_diluted = c.shares_opt(self.shares_outstanding_diluted);
+        self.ebit = c.amount_opt(self.ebit);
+        self.ebitda = c.amount_opt(self.ebitda);
+    }
+}
+
+impl Convert for BalanceSheet {
+    fn convert(&mut self, c: &Conversion) {
+        self.cash_and_equivalents = c.amount(self.cash_and_equivalents);
+        self.total_assets = c.amount(self.total_assets);
+        self.short_term_debt = c.amount(self.short_term_debt);
+        self.long_term_debt = c.amount(self.long_term_debt);
+        self.total_liabilities = c.amount(self.total_liabilities);
+        self.total_debt = c.amount_opt(self.total_debt);
+        self.total_equity = c.amount_opt(self.total_equity);
+        self.current_assets = c.amount_opt(self.current_assets);
+        self.current_liabilities = c.amount_opt(self.current_liabilities);
+        self.inventory = c.amount_opt(self.inventory);
+        self.receivables = c.amount_opt(self.receivables);
+        self.payables = c.amount_opt(self.payables);
+        self.goodwill = c.amount_opt(self.goodwill);
+        self.intangibles = c.amount_opt(self.intangibles);
+        self.retained_earnings = c.amount_opt(self.retained_earnings);
+        self.net_debt = c.amount_opt(self.net_debt);
+        self.tangible_book_value = c.amount_opt(self.tangible_book_value);
+    }
+}
+
+impl Convert for CashFlowStatement {
+    fn convert(&mut self, c: &Conversion) {
+        self.operating_cash_flow = c.amount(self.operating_cash_flow);
+        self.investing_cash_flow = c.amount(self.investing_cash_flow);
+        self.capital_expenditure = c.amount(self.capital_expenditure);
+        self.financing_cash_flow = c.amount(self.financing_cash_flow);
+        self.dividends_paid = c.amount(self.dividends_paid);
+        self.dividends_per_share = c.per_share(self.dividends_per_share);
+        self.free_cash_flow = c.amount_opt(self.free_cash_flow);
+        self.fcf_per_share = c.per_share_opt(self.fcf_per_share);
+        self.share_repurchases = c.amount_opt(self.share_repurchases);
+        self.share_issuance = c.amount_opt(self.share_issuance);
+        self.debt_issued = c.amount_opt(self.debt_issued);
+        self.debt_repaid = c.amount_opt(self.debt_repaid);
+        self.acquisitions = c.amount_opt(self.acquisitions);
+    }
+}
+
+impl Convert for FinancialRatios {
+    fn convert(&mut self, c: &Conversion) {
+        self.avg_share_price = c.per_share(self.avg_share_price);
+        self.book_value_per_share = c.per_share_opt(self.book_value_per_share);
+        self.tangible_book_value_per_share = c.per_share_opt(self.tangible_book_value_per_share);
+    }
+}
+
+impl Convert for TrailingRatios {
+    fn convert(&mut self, c: &Conversion) {
+        self.avg_share_price = c.per_share(self.avg_share_price);
+    }
+}
+
+/// Registry metrics are converted by the unit they declare.
+impl Convert for BTreeMap<String, Option<f64>> {
+    fn convert(&mut self, c: &Conversion) {
+        let registry = report_computer::registry();
+        for (id, value) in self.iter_mut() {
+            *value = match registry.get(id).map(|metric| metric.unit) {
+                Some(Unit::Amount) => c.amount_opt(*value),
+                Some(Unit::PerShare) => c.per_share_opt(*value),
+                Some(Unit::Ratio) | None => *value,
+            };
+        }
+    }
+}
+
+impl Convert for Report {
+    fn convert(&mut self, c: &Conversion) {
+        self.income_statement.convert(c);
+        self.balance_sheet.convert(c);
+        self.cash_flow_statement.convert(c);
+        self.financial_ratios.convert(c);
+        self.metrics.convert(c);
+    }
+}
+
+impl Convert for QuarterlyReport {
+    fn convert(&mut self, c: &Conversion) {
+        self.income_statement.convert(c);
+        self.balance_sheet.convert(c);
+        self.cash_flow_statement.convert(c);
+        self.avg_share_price = c.per_share(self.avg_share_price);
+    }
+}
+
+impl Convert for TrailingTwelveMonths {
+    fn convert(&mut self, c: &Conversion) {
+        self.income_statement.convert(c);
+        self.balance_sheet.convert(c);
+        self.cash_flow_statement.convert(c);
+        self.financial_ratios.convert(c);
+        self.metrics.convert(c);
+    }
+}
+
+impl Convert for AnnualStockReport {
+    fn convert(&mut self, c: &Conversion) {
+        self.data.iter_mut().for_each(|year| year.convert(c));
+        self.quarters.iter_mut().for_each(|quarter| quarter.convert(c));
+        if let Some(ttm) = self.ttm.as_mut() {
+            ttm.convert(c);
+        }
+        self.currency = c.currency.clone();
+        self.unit_scale = c.unit_scale;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report_model::tests::sample_report;
+
+    fn rates() -> FxRates {
+        FxRates {
+            base: String::from("usd"),
+            as_of: None,
+            rates: BTreeMap::from([(String::from("eur"), 0.5), (String::from("gbp"), 0.25)]),
+        }
+        .normalized()
+        .unwrap()
+    }
+
+    fn target(currency: Option<&str>, unit_scale: Option<UnitScale>) -> ConversionTarget {
+        ConversionTarget {
+            currency: currency.map(str::to_string),
+            unit_scale,
+        }
+    }
+
+    #[test]
+    fn normalizes_codes_and_checks_rates() {
+        assert_eq!(normalize_currency("chf"), Some(String::from("CHF")));
+        assert_eq!(normalize_currency("EURO"), None);
+        assert_eq!(rates().base, "USD");
+
+        let negative = FxRates {
+            rates: BTreeMap::from([(String::from("EUR"), -1.0)]),
+            ..FxRates::default()
+        };
+        assert!(matches!(negative.normalized(), Err(ConversionError::InvalidRate { .. })));
+    }
+
+    #[test]
+    fn cross_rates_go_through_the_base() {
+        let rates = rates();
+        assert_eq!(rates.cross_rate("USD", "EUR").unwrap(), 0.5);
+        assert_eq!(rates.cross_rate("EUR", "GBP").unwrap(), 0.5);
+        assert_eq!(rates.cross_rate("JPY", "JPY").unwrap(), 1.0);
+        assert!(matches!(rates.cross_rate("USD", "JPY"), Err(ConversionError::UnknownCurrency(_))));
+    }
+
+    #[test]
+    fn converts_amounts_shares_and_per_share_figures() {
+        let mut report = sample_report();
+        let conversion = Conversion::plan(&report, &target(Some("eur"), Some(UnitScale::Billions)), &rates()).unwrap();
+        report.convert(&conversion);
+
+        assert_eq!(report.currency.as_deref(), Some("EUR"));
+        assert_eq!(report.unit_scale, Some(UnitScale::Billions));
+        let first = &report.data[0];
+        assert!((first.income_statement.revenue - 57838.0 * 0.5 / 1e3).abs() < 1e-9);
+        assert!((first.income_statement.shares_outstanding_basic - 1590.0 / 1e3).abs() < 1e-12);
+        assert_eq!(first.income_statement.eps_basic, 3.97 * 0.5);
+        assert_eq!(first.financial_ratios.avg_share_price, 64.41 * 0.5);
+        // Unit-free figures stay as they are
+        assert_eq!(first.income_statement.net_profit_margin, sample_report().data[0].income_statement.net_profit_margin);
+    }
+
+    #[test]
+    fn needs_the_report_metadata() {
+        let mut report = sample_report();
+        report.currency = None;
+        report.unit_scale = None;
+        assert!(matches!(
+            Conversion::plan(&report, &target(Some("EUR"), None), &rates()),
+            Err(ConversionError::MissingCurrency(_))
+        ));
+        assert!(matches!(
+            Conversion::plan(&report, &target(None, Some(UnitScale::Units)), &rates()),
+            Err(ConversionError::MissingUnitScale(_))
+        ));
+    }
+
+    #[test]
+    fn target_checks_do_not_need_a_report() {
+        assert!(target(Some("EUR"), None).check(&rates()).is_ok());
+        assert!(target(None, Some(UnitScale::Units)).check(&rates()).is_ok());
+        assert!(matches!(target(Some("EU"), None).check(&rates()), Err(ConversionError::InvalidCurrency(_))));
+        assert!(matches!(target(Some("JPY"), None).check(&rates()), Err(ConversionError::UnknownCurrency(_))));
+    }
+}
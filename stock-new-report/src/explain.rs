@@ -0,0 +1,335 @@
+//! Shows how one figure of a year was arrived at: the formula, every input it
+//! read and from which year, and the intermediate results, so a number can be
+//! audited without reading the code.
+
+use serde::Serialize;
+
+use crate::formula::{History, Trace};
+use crate::metric_math::{growth, safe_div};
+use crate::report_computer::{self, CustomMetric, Values};
+use crate::report_growth;
+use crate::report_model::{self, AnnualStockReport, Provenance, Report};
+
+/// Window lengths, in years, of the `dgrN` ratios.
+const DGR_WINDOWS: [i32; 6] = [1, 3, 5, 10, 15, 20];
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExplanationKind {
+    /// A statement figure, reported or filled in by the statement itself.
+    Statement,
+    /// A built-in metric from the registry.
+    Metric,
+    CustomMetric,
+    /// One of the `dgrN` ratios or `dividend-growth-rate`.
+    DividendGrowth,
+    /// A field of one of the `*-yoy` blocks, requested as `<field>-yoy`.
+    YearOverYear,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Input {
+    pub name: String,
+    /// The fiscal year the figure was read from.
+    pub year: i32,
+    pub value: Option<f64>,
+    /// Set for derived statement figures.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Step {
+    pub expression: String,
+    /// The fiscal year the expression was evaluated for.
+    pub year: i32,
+    pub value: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Explanation {
+    pub ticker: String,
+    pub year: i32,
+    pub metric: String,
+    pub kind: ExplanationKind,
+    /// Empty for a figure taken from the filing as is.
+    pub formula: Option<String>,
+    pub value: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+    /// The earlier year a growth figure compares against.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_year: Option<i32>,
+    pub inputs: Vec<Input>,
+    /// Intermediate results, innermost first; the last one is the value.
+    pub steps: Vec<Step>,
+}
+
+/// Provenance of a derived statement figure of `report`, if it is one.
+fn provenance_of(report: &Report, name: &str) -> Option<Provenance> {
+    [
+        &report.income_statement.provenance,
+        &report.balance_sheet.provenance,
+        &report.cash_flow_statement.provenance,
+    ]
+    .into_iter()
+    .find_map(|provenance| provenance.get(name).copied())
+}
+
+struct Explainer<'a> {
+    report: &'a AnnualStockReport,
+    history: History,
+}
+
+impl Explainer<'_> {
+    fn input(&self, name: &str, year: i32) -> Input {
+        Input {
+            name: name.to_string(),
+            year,
+            value: self.history.get(&year).and_then(|values| values.get(name)),
+            provenance: self.report.get_year(year).and_then(|report| provenance_of(report, name)),
+        }
+    }
+
+    fn value(&self, name: &str, year: i32) -> Option<f64> {
+        self.history.get(&year).and_then(|values| values.get(name))
+    }
+
+    fn explanation(&self, year: &Report, metric: &str, kind: ExplanationKind) -> Explanation {
+        Explanation {
+            ticker: self.report.ticker.clone(),
+            year: year.year,
+            metric: metric.to_string(),
+            kind,
+            formula: None,
+            value: None,
+            provenance: None,
+            base_year: None,
+            inputs: Vec::new(),
+            steps: Vec::new(),
+        }
+    }
+
+    fn custom(&self, year: &Report, metric: &CustomMetric) -> Explanation {
+        let mut explanation = self.explanation(year, &metric.id, ExplanationKind::CustomMetric);
+        explanation.formula = Some(metric.formula.clone());
+        if let Ok(expr) = metric.parse() {
+            let mut trace = Trace::default();
+            explanation.value = expr.evaluate_traced(&self.history, year.year, &mut trace);
+            explanation.inputs = trace.reads.iter().map(|(name, at, _)| self.input(name, *at)).collect();
+            explanation.steps = trace
+                .steps
+                .into_iter()
+                .map(|(expression, year, value)| Step { expression, year, value })
+                .collect();
+        }
+        explanation
+    }
+
+    /// Walks the metrics `id` builds on, depth first, so every step comes
+    /// after the steps it uses.
+    fn registry_steps(&self, id: &str, year: i32, explanation: &mut Explanation) {
+        let Some(metric) = report_computer::registry().get(id) else {
+            if !explanation.inputs.iter().any(|input| input.name == id) {
+                explanation.inputs.push(self.input(id, year));
+            }
+            return;
+        };
+        if explanation.steps.iter().any(|step| step.expression.starts_with(&format!("{} = ", id))) {
+            return;
+        }
+        for input in metric.inputs {
+            self.registry_steps(input, year, explanation);
+        }
+        explanation.steps.push(Step {
+            expression: format!("{} = {}", id, metric.formula),
+            year,
+            value: self.value(id, year),
+        });
+    }
+
+    fn registry(&self, year: &Report, id: &str, formula: &str) -> Explanation {
+        let mut explanation = self.explanation(year, id, ExplanationKind::Metric);
+        explanation.formula = Some(formula.to_string());
+        explanation.value = self.value(id, year.year);
+        self.registry_steps(id, year.year, &mut explanation);
+        explanation
+    }
+
+    fn dividend_growth(&self, year: &Report, id: &str, window: i32) -> Explanation {
+        let mut explanation = self.explanation(year, id, ExplanationKind::DividendGrowth);
+        let base = self.report.get_year(year.year - window);
+        explanation.value = self.value(id, year.year);
+        explanation.base_year = base.map(|base| base.year);
+        explanation.inputs.push(self.input("dividends-per-share", year.year));
+
+        if id == "dividend-growth-rate" {
+            explanation.formula = Some(String::from(
+                "(dividends-per-share - dividends-per-share[-1]) / |dividends-per-share[-1]|",
+            ));
+            if let Some(base) = base {
+                explanation.inputs.push(self.input("dividends-per-share", base.year));
+                explanation.steps.push(Step {
+                    expression: format!("growth of dividends-per-share against {}", base.year),
+                    year: year.year,
+                    value: explanation.value,
+                });
+            }
+            return explanation;
+        }
+
+        explanation.formula = Some(format!(
+            "(dividends-per-share / dividends-per-share[-{}])^(1 / years elapsed) - 1",
+            window
+        ));
+        let Some(base) = base else {
+            return explanation;
+        };
+        explanation.inputs.push(self.input("dividends-per-share", base.year));
+
+        let current = self.value("dividends-per-share", year.year);
+        let base_dividend = self.value("dividends-per-share", base.year);
+        let (elapsed, elapsed_expression) = match year.fiscal_period.years_since(&base.fiscal_period) {
+            Some(elapsed) => (
+                elapsed,
+                format!(
+                    "days from the end of {} to the end of {} / 365.25",
+                    base.year, year.year
+                ),
+            ),
+            None => ((year.year - base.year) as f64, format!("{} - {}", year.year, base.year)),
+        };
+        let ratio = current.zip(base_dividend).and_then(|(current, base)| safe_div(current, base));
+        explanation.steps = vec![
+            Step {
+                expression: format!("dividends-per-share / dividends-per-share of {}", base.year),
+                year: year.year,
+                value: ratio,
+            },
+            Step {
+                expression: format!("years elapsed = {}", elapsed_expression),
+                year: year.year,
+                value: Some(elapsed),
+            },
+            Step {
+                expression: String::from(
+                    "ratio^(1 / years elapsed) - 1, empty unless the base dividend is positive and the current one is not negative",
+                ),
+                year: year.year,
+                value: report_growth::compound(current, base_dividend, elapsed).ok(),
+            },
+        ];
+        explanation
+    }
+
+    fn year_over_year(&self, year: &Report, id: &str, field: &str) -> Option<Explanation> {
+        let blocks = [
+            ("income-statement", serde_json::to_value(year.income_statement_yoy).ok()),
+            ("balance-sheet", serde_json::to_value(year.balance_sheet_yoy).ok()),
+            ("cash-flow-statement", serde_json::to_value(year.cash_flow_statement_yoy).ok()),
+            ("metrics", serde_json::to_value(&year.metrics_yoy).ok()),
+        ];
+        let is_metric = year.metrics.contains_key(field);
+        let statement = match blocks.iter().find(|(_, block)| block.as_ref().is_some_and(|block| block.get(field).is_some())) {
+            Some((statement, _)) => *statement,
+            // Without a base year the blocks are empty; the field may still exist
+            None if is_metric || Values::is_known(field) => "",
+            None => return None,
+        };
+        let flow = match statement {
+            "metrics" => report_computer::registry().get(field).is_some_and(|metric| metric.flow),
+            "income-statement" | "cash-flow-statement" => report_model::is_flow(field),
+            _ => false,
+        };
+
+        let mut explanation = self.explanation(year, id, ExplanationKind::YearOverYear);
+        explanation.formula = Some(format!("({0} - {0}[-1]) / |{0}[-1]|", field));
+        explanation.value = blocks
+            .iter()
+            .find_map(|(_, block)| block.as_ref()?.get(field)?.as_f64());
+        explanation.base_year = year.yoy_base_year;
+        explanation.inputs.push(self.input(field, year.year));
+        let Some(base) = year.yoy_base_year.and_then(|base| self.report.get_year(base)) else {
+            return Some(explanation);
+        };
+        explanation.inputs.push(self.input(field, base.year));
+
+        let mut current = self.value(field, year.year);
+        if let (true, Some(length_ratio)) = (flow, year.fiscal_period.length_ratio(&base.fiscal_period)) {
+            current = current.map(|current| current * length_ratio);
+            explanation.steps.push(Step {
+                expression: format!(
+                    "{} * {} weeks / {} weeks, scaled to the length of {}",
+                    field,
+                    base.fiscal_period.weeks.unwrap_or_default(),
+                    year.fiscal_period.weeks.unwrap_or_default(),
+                    base.year
+                ),
+                year: year.year,
+                value: current,
+            });
+        }
+        explanation.steps.push(Step {
+            expression: format!("growth of {} against {}", field, base.year),
+            year: year.year,
+            value: current.zip(self.value(field, base.year)).and_then(|(current, base)| growth(current, base)),
+        });
+        Some(explanation)
+    }
+
+    fn statement(&self, year: &Report, id: &str) -> Explanation {
+        let mut explanation = self.explanation(year, id, ExplanationKind::Statement);
+        explanation.value = self.value(id, year.year);
+        explanation.provenance = provenance_of(year, id);
+        // A reported or overridden figure didn't come from the formula
+        if let (Some(figure), None | Some(Provenance::Computed)) = (report_model::derived_figure(id), explanation.provenance) {
+            explanation.formula = Some(figure.formula.to_string());
+            explanation.inputs = figure.inputs.iter().map(|input| self.input(input, year.year)).collect();
+        }
+        explanation
+    }
+}
+
+/// Explains `metric` for `year` of `report`, both as presented to the client.
+/// `metric` may be a statement field, a built-in or custom metric, `dgrN`,
+/// `dividend-growth-rate` or `<field>-yoy`; snake case is accepted too.
+/// Returns `None` for a name that is none of these.
+pub fn explain(
+    report: &AnnualStockReport,
+    year: &Report,
+    metric: &str,
+    custom_metrics: &[CustomMetric],
+) -> Option<Explanation> {
+    let id = metric.to_ascii_lowercase().replace('_', "-");
+    let explainer = Explainer {
+        report,
+        history: report_computer::history(report),
+    };
+
+    if let Some(custom) = custom_metrics.iter().find(|custom| custom.id == id) {
+        return Some(explainer.custom(year, custom));
+    }
+    if id == "dividend-growth-rate" {
+        return Some(explainer.dividend_growth(year, &id, 1));
+    }
+    if let Some(window) = id
+        .strip_prefix("dgr")
+        .and_then(|window| window.parse::<i32>().ok())
+        .filter(|window| DGR_WINDOWS.contains(window))
+    {
+        return Some(explainer.dividend_growth(year, &id, window));
+    }
+    if let Some(metric) = report_computer::registry().get(&id) {
+        return Some(explainer.registry(year, &id, metric.formula));
+    }
+    if let Some(field) = id.strip_suffix("-yoy") {
+        return explainer.year_over_year(year, &id, field);
+    }
+    if Values::is_known(&id) {
+        return Some(explainer.statement(year, &id));
+    }
+    None
+}
@@ -0,0 +1,636 @@
+//! The expression language custom metrics are written in.
+//!
+//! A formula reads the figures of a year by their kebab-case names, e.g.
+//! `capital-expenditure / revenue`. Supported are numbers, `+ - * / ^`,
+//! parentheses, and:
+//!
+//! - `name[-n]`: the figure `n` fiscal years earlier, e.g. `net-income[-1]`;
+//! - `cagr(x, n)`: compound annual growth of `x` over the last `n` years;
+//! - `growth(x, n)`: total change of `x` over the last `n` years;
+//! - `abs(x)`, `min(x, y)` and `max(x, y)`.
+//!
+//! Names may contain `-`, so subtraction needs a space before the operator:
+//! `revenue - total-cogs`, not `revenue-total-cogs`. Anything undefined, a
+//! missing year, a zero denominator or a negative CAGR base, makes the
+//! whole formula empty rather than an error.
+//!
+//! Formulas run for every year of every ticker on every read, so their size
+//! is capped: at most `MAX_LENGTH` characters and `MAX_DEPTH` levels of
+//! parentheses, function calls, signs and powers.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::metric_math::{cagr, finite, growth, safe_div};
+use crate::report_computer::Values;
+
+pub const MAX_LENGTH: usize = 1000;
+pub const MAX_DEPTH: usize = 32;
+
+#[derive(Debug)]
+pub struct FormulaError {
+    /// Byte offset into the source where the problem was found.
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for FormulaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (at position {})", self.message, self.position)
+    }
+}
+
+impl std::error::Error for FormulaError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    Abs,
+    Min,
+    Max,
+    Cagr,
+    Growth,
+}
+
+impl Op {
+    fn symbol(self) -> &'static str {
+        match self {
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+            Op::Pow => "^",
+        }
+    }
+
+    fn precedence(self) -> u8 {
+        match self {
+            Op::Add | Op::Sub => 1,
+            Op::Mul | Op::Div => 2,
+            Op::Pow => 3,
+        }
+    }
+}
+
+impl Function {
+    fn name(self) -> &'static str {
+        match self {
+            Function::Abs => "abs",
+            Function::Min => "min",
+            Function::Max => "max",
+            Function::Cagr => "cagr",
+            Function::Growth => "growth",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Function> {
+        match name {
+            "abs" => Some(Function::Abs),
+            "min" => Some(Function::Min),
+            "max" => Some(Function::Max),
+            "cagr" => Some(Function::Cagr),
+            "growth" => Some(Function::Growth),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    /// A figure of the year `lag` years before the one being evaluated.
+    Field { name: String, lag: u32 },
+    Neg(Box<Expr>),
+    Binary { op: Op, lhs: Box<Expr>, rhs: Box<Expr> },
+    /// Ordinary functions of their arguments.
+    Call { function: Function, args: Vec<Expr> },
+    /// `cagr` and `growth`: `value` now against `value` `years` years ago.
+    Change { function: Function, value: Box<Expr>, years: u32 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Op(Op),
+    Minus,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+}
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, FormulaError> {
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let c = bytes[idx] as char;
+        let start = idx;
+        if c.is_ascii_whitespace() {
+            idx += 1;
+            continue;
+        }
+        if c.is_ascii_digit() || c == '.' {
+            while idx < bytes.len() && (bytes[idx].is_ascii_digit() || bytes[idx] == b'.') {
+                idx += 1;
+            }
+            let number = source[start..idx].parse().map_err(|_| FormulaError {
+                position: start,
+                message: format!("{:?} is not a number", &source[start..idx]),
+            })?;
+            tokens.push((start, Token::Number(number)));
+            continue;
+        }
+        if c.is_ascii_alphabetic() {
+            // A dash belongs to the name when another letter or digit follows it
+            while idx < bytes.len()
+                && (bytes[idx].is_ascii_alphanumeric()
+                    || (bytes[idx] == b'-' && bytes.get(idx + 1).is_some_and(u8::is_ascii_alphanumeric)))
+            {
+                idx += 1;
+            }
+            tokens.push((start, Token::Name(source[start..idx].to_ascii_lowercase())));
+            continue;
+        }
+        let token = match c {
+            '+' => Token::Op(Op::Add),
+            '-' => Token::Minus,
+            '*' => Token::Op(Op::Mul),
+            '/' => Token::Op(Op::Div),
+            '^' => Token::Op(Op::Pow),
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            ',' => Token::Comma,
+            _ => {
+                return Err(FormulaError {
+                    position: start,
+                    message: format!("Unexpected character {:?}", c),
+                })
+            }
+        };
+        tokens.push((start, token));
+        idx += c.len_utf8();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    end: usize,
+    /// Nesting of `unary`, which every recursion of the grammar goes through.
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.next).map_or(self.end, |(position, _)| *position)
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, FormulaError> {
+        Err(FormulaError {
+            position: self.position(),
+            message: message.into(),
+        })
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).map(|(_, token)| token.clone());
+        self.next += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token, what: &str) -> Result<(), FormulaError> {
+        if self.peek() == Some(&expected) {
+            self.next += 1;
+            Ok(())
+        } else {
+            self.error(format!("Expected {}", what))
+        }
+    }
+
+    /// `term (('+' | '-') term)*`
+    fn expression(&mut self) -> Result<Expr, FormulaError> {
+        let mut lhs = self.term()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Op(Op::Add)) => Op::Add,
+                Some(Token::Minus) => Op::Sub,
+                _ => return Ok(lhs),
+            };
+            self.next += 1;
+            let rhs = self.term()?;
+            lhs = Expr::Binary { op, lhs: Box::new(lhs), rhs: Box::new(rhs) };
+        }
+    }
+
+    /// `unary (('*' | '/') unary)*`
+    fn term(&mut self) -> Result<Expr, FormulaError> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Op(op @ (Op::Mul | Op::Div))) => *op,
+                _ => return Ok(lhs),
+            };
+            self.next += 1;
+            let rhs = self.unary()?;
+            lhs = Expr::Binary { op, lhs: Box::new(lhs), rhs: Box::new(rhs) };
+        }
+    }
+
+    /// `'-' unary | primary ('^' unary)?`, so `-x^2` is `-(x^2)` and `^` groups to the right.
+    fn unary(&mut self) -> Result<Expr, FormulaError> {
+        if self.depth == MAX_DEPTH {
+            return self.error(format!("The formula nests more than {} levels deep", MAX_DEPTH));
+        }
+        self.depth += 1;
+        let expr = self.unary_inner();
+        self.depth -= 1;
+        expr
+    }
+
+    fn unary_inner(&mut self) -> Result<Expr, FormulaError> {
+        if self.peek() == Some(&Token::Minus) {
+            self.next += 1;
+            return Ok(match self.unary()? {
+                Expr::Number(number) => Expr::Number(-number),
+                inner => Expr::Neg(Box::new(inner)),
+            });
+        }
+        let base = self.primary()?;
+        if self.peek() == Some(&Token::Op(Op::Pow)) {
+            self.next += 1;
+            let exponent = self.unary()?;
+            return Ok(Expr::Binary { op: Op::Pow, lhs: Box::new(base), rhs: Box::new(exponent) });
+        }
+        Ok(base)
+    }
+
+    fn primary(&mut self) -> Result<Expr, FormulaError> {
+        let position = self.position();
+        match self.advance() {
+            Some(Token::Number(number)) => Ok(Expr::Number(number)),
+            Some(Token::LParen) => {
+                let inner = self.expression()?;
+                self.expect(Token::RParen, "a closing parenthesis")?;
+                Ok(inner)
+            }
+            Some(Token::Name(name)) if self.peek() == Some(&Token::LParen) => {
+                self.next += 1;
+                self.call(&name, position)
+            }
+            Some(Token::Name(name)) => {
+                let lag = if self.peek() == Some(&Token::LBracket) {
+                    self.next += 1;
+                    self.expect(Token::Minus, "a negative year offset such as [-1]")?;
+                    let lag = self.years()?;
+                    self.expect(Token::RBracket, "a closing bracket")?;
+                    lag
+                } else {
+                    0
+                };
+                Ok(Expr::Field { name, lag })
+            }
+            _ => Err(FormulaError {
+                position,
+                message: String::from("Expected a number, a name or an opening parenthesis"),
+            }),
+        }
+    }
+
+    /// A whole number of years, at least 1.
+    fn years(&mut self) -> Result<u32, FormulaError> {
+        match self.peek() {
+            Some(Token::Number(number)) if number.fract() == 0.0 && *number >= 1.0 && *number <= 100.0 => {
+                let years = *number as u32;
+                self.next += 1;
+                Ok(years)
+            }
+            _ => self.error("Expected a whole number of years between 1 and 100"),
+        }
+    }
+
+    fn call(&mut self, name: &str, position: usize) -> Result<Expr, FormulaError> {
+        let function = Function::parse(name).ok_or_else(|| FormulaError {
+            position,
+            message: format!("Unknown function {}; expected abs, min, max, cagr or growth", name),
+        })?;
+
+        if matches!(function, Function::Cagr | Function::Growth) {
+            let value = self.expression()?;
+            self.expect(Token::Comma, "a comma before the number of years")?;
+            let years = self.years()?;
+            self.expect(Token::RParen, "a closing parenthesis")?;
+            return Ok(Expr::Change { function, value: Box::new(value), years });
+        }
+
+        let mut args = vec![self.expression()?];
+        while self.peek() == Some(&Token::Comma) {
+            self.next += 1;
+            args.push(self.expression()?);
+        }
+        self.expect(Token::RParen, "a closing parenthesis")?;
+
+        let arity = match function {
+            Function::Abs => 1,
+            _ => 2,
+        };
+        if args.len() != arity {
+            return Err(FormulaError {
+                position,
+                message: format!("{} takes {} argument(s), got {}", name, arity, args.len()),
+            });
+        }
+        Ok(Expr::Call { function, args })
+    }
+}
+
+/// The figures of every fiscal year of one ticker, which formulas evaluate over.
+pub type History = BTreeMap<i32, Values>;
+
+/// Results of `cagr` and `growth` by node and year. Each reads its argument
+/// in two years, so without it nested calls would cost twice as much per level.
+type Memo = HashMap<(usize, i32), Option<f64>>;
+
+/// What an evaluation looked at, in the order it did.
+#[derive(Debug, Default)]
+pub struct Trace {
+    /// Every figure read, once each, as `(name, year, value)`.
+    pub reads: Vec<(String, i32, Option<f64>)>,
+    /// Every sub-expression that is not a plain number or figure, innermost
+    /// first, as `(expression, year, value)`.
+    pub steps: Vec<(String, i32, Option<f64>)>,
+}
+
+impl fmt::Display for Expr {
+    /// Canonical text of the expression, with only the parentheses it needs.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Number(number) => write!(f, "{}", number),
+            Expr::Field { name, lag: 0 } => write!(f, "{}", name),
+            Expr::Field { name, lag } => write!(f, "{}[-{}]", name, lag),
+            Expr::Neg(inner) => match inner.as_ref() {
+                Expr::Binary { .. } => write!(f, "-({})", inner),
+                _ => write!(f, "-{}", inner),
+            },
+            Expr::Binary { op, lhs, rhs } => {
+                // Operators group to the left, except `^`, which groups to the right
+                let needs_parens = |child: &Expr, right: bool| match child {
+                    Expr::Binary { op: inner, .. } => {
+                        inner.precedence() < op.precedence()
+                            || (inner.precedence() == op.precedence() && right != (*op == Op::Pow))
+                    }
+                    Expr::Neg(_) => *op == Op::Pow && !right,
+                    _ => false,
+                };
+                for (child, right) in [(lhs, false), (rhs, true)] {
+                    if right {
+                        write!(f, " {} ", op.symbol())?;
+                    }
+                    if needs_parens(child, right) {
+                        write!(f, "({})", child)?;
+                    } else {
+                        write!(f, "{}", child)?;
+                    }
+                }
+                Ok(())
+            }
+            Expr::Call { function, args } => {
+                let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+                write!(f, "{}({})", function.name(), args.join(", "))
+            }
+            Expr::Change { function, value, years } => write!(f, "{}({}, {})", function.name(), value, years),
+        }
+    }
+}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Expr, FormulaError> {
+        if source.chars().count() > MAX_LENGTH {
+            return Err(FormulaError {
+                position: 0,
+                message: format!("The formula is longer than {} characters", MAX_LENGTH),
+            });
+        }
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            next: 0,
+            end: source.len(),
+            depth: 0,
+        };
+        if parser.peek().is_none() {
+            return parser.error("The formula is empty");
+        }
+        let expr = parser.expression()?;
+        if parser.peek().is_some() {
+            return parser.error("Unexpected input after the end of the formula");
+        }
+        Ok(expr)
+    }
+
+    /// Every figure the formula reads, once each.
+    pub fn fields(&self) -> Vec<&str> {
+        let mut fields = Vec::new();
+        self.collect_fields(&mut fields);
+        fields.sort_unstable();
+        fields.dedup();
+        fields
+    }
+
+    fn collect_fields<'a>(&'a self, fields: &mut Vec<&'a str>) {
+        match self {
+            Expr::Number(_) => {}
+            Expr::Field { name, .. } => fields.push(name),
+            Expr::Neg(inner) => inner.collect_fields(fields),
+            Expr::Binary { lhs, rhs, .. } => {
+                lhs.collect_fields(fields);
+                rhs.collect_fields(fields);
+            }
+            Expr::Call { args, .. } => args.iter().for_each(|arg| arg.collect_fields(fields)),
+            Expr::Change { value, .. } => value.collect_fields(fields),
+        }
+    }
+
+    /// Value for fiscal `year`, or `None` when anything it needs is undefined.
+    pub fn evaluate(&self, history: &History, year: i32) -> Option<f64> {
+        self.eval(history, year, &mut Memo::new(), None)
+    }
+
+    /// Same as `evaluate`, recording every figure read and every intermediate
+    /// result in `trace`.
+    pub fn evaluate_traced(&self, history: &History, year: i32, trace: &mut Trace) -> Option<f64> {
+        self.eval(history, year, &mut Memo::new(), Some(trace))
+    }
+
+    // Every operand is evaluated even when an earlier one is undefined, so a
+    // trace shows all the inputs rather than stopping at the first gap
+    fn eval(&self, history: &History, year: i32, memo: &mut Memo, mut trace: Option<&mut Trace>) -> Option<f64> {
+        let value = match self {
+            Expr::Number(number) => return Some(*number),
+            Expr::Field { name, lag } => {
+                let read_year = year - *lag as i32;
+                let value = history.get(&read_year).and_then(|values| values.get(name));
+                if let Some(trace) = trace {
+                    if !trace.reads.iter().any(|(read, at, _)| read == name && *at == read_year) {
+                        trace.reads.push((name.clone(), read_year, value));
+                    }
+                }
+                return value;
+            }
+            Expr::Neg(inner) => inner.eval(history, year, memo, trace.as_deref_mut()).map(|value| -value),
+            Expr::Binary { op, lhs, rhs } => {
+                let lhs = lhs.eval(history, year, memo, trace.as_deref_mut());
+                let rhs = rhs.eval(history, year, memo, trace.as_deref_mut());
+                lhs.zip(rhs).and_then(|(lhs, rhs)| match op {
+                    Op::Add => finite(lhs + rhs),
+                    Op::Sub => finite(lhs - rhs),
+                    Op::Mul => finite(lhs * rhs),
+                    Op::Div => safe_div(lhs, rhs),
+                    Op::Pow => finite(lhs.powf(rhs)),
+                })
+            }
+            Expr::Call { function, args } => {
+                let args: Vec<Option<f64>> = args.iter().map(|arg| arg.eval(history, year, memo, trace.as_deref_mut())).collect();
+                let first = args[0];
+                let second = args.get(1).copied().flatten();
+                match function {
+                    Function::Abs => first.map(f64::abs),
+                    Function::Min => first.zip(second).map(|(lhs, rhs)| lhs.min(rhs)),
+                    Function::Max => first.zip(second).map(|(lhs, rhs)| lhs.max(rhs)),
+                    Function::Cagr | Function::Growth => None,
+                }
+            }
+            Expr::Change { function, value, years } => {
+                let key = (self as *const Expr as usize, year);
+                if let Some(value) = memo.get(&key) {
+                    return *value;
+                }
+                let current = value.eval(history, year, memo, trace.as_deref_mut());
+                let base = value.eval(history, year - *years as i32, memo, trace.as_deref_mut());
+                let change = current.zip(base).and_then(|(current, base)| match function {
+                    Function::Cagr => cagr(current, base, *years as f64),
+                    _ => growth(current, base),
+                });
+                memo.insert(key, change);
+                change
+            }
+        };
+        if let Some(trace) = trace {
+            trace.steps.push((self.to_string(), year, value));
+        }
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report_computer;
+    use crate::report_model::tests::sample_report;
+
+    fn evaluate(source: &str, year: i32) -> Option<f64> {
+        let history = report_computer::history(&sample_report());
+        Expr::parse(source).expect("formula parses").evaluate(&history, year)
+    }
+
+    fn close(actual: Option<f64>, expected: f64) -> bool {
+        actual.is_some_and(|actual| (actual - expected).abs() < 1e-9)
+    }
+
+    #[test]
+    fn precedence_and_signs() {
+        assert_eq!(evaluate("1 + 2 * 3", 2010), Some(7.0));
+        assert_eq!(evaluate("(1 + 2) * 3", 2010), Some(9.0));
+        assert_eq!(evaluate("-2^2", 2010), Some(-4.0));
+        assert_eq!(evaluate("2^3^2", 2010), Some(512.0));
+        assert_eq!(evaluate("min(3, abs(-5)) - max(1, 2)", 2010), Some(1.0));
+    }
+
+    #[test]
+    fn reads_fields_and_earlier_years() {
+        assert_eq!(evaluate("revenue - total-cogs", 2010), Some(57838.0 - 26575.0));
+        assert_eq!(evaluate("REVENUE[-1]", 2011), Some(57838.0));
+        assert!(close(evaluate("growth(revenue, 1)", 2011), (66504.0 - 57838.0) / 57838.0));
+        assert!(close(evaluate("cagr(dividends-per-share, 2)", 2012), (2.13_f64 / 1.89).sqrt() - 1.0));
+    }
+
+    #[test]
+    fn undefined_rather_than_an_error() {
+        assert_eq!(evaluate("revenue[-1]", 2010), None);
+        assert_eq!(evaluate("revenue / 0", 2010), None);
+        assert_eq!(evaluate("cagr(revenue - revenue, 1)", 2011), None);
+        assert_eq!(evaluate("no-such-figure", 2010), None);
+    }
+
+    #[test]
+    fn lists_the_fields_it_reads() {
+        let expr = Expr::parse("revenue / revenue[-1] + cagr(net-income, 3)").unwrap();
+        assert_eq!(expr.fields(), ["net-income", "revenue"]);
+    }
+
+    #[test]
+    fn reports_where_parsing_failed() {
+        let cases = [
+            ("", 0),
+            ("revenue +", 9),
+            ("(revenue", 8),
+            ("revenue[1]", 8),
+            ("cagr(revenue, 0)", 14),
+            ("abs(1, 2)", 0),
+            ("foo(1)", 0),
+            ("revenue $", 8),
+            ("1 2", 2),
+        ];
+        for (source, position) in cases {
+            let err = Expr::parse(source).expect_err(source);
+            assert_eq!(err.position, position, "{}: {}", source, err);
+        }
+    }
+
+    #[test]
+    fn caps_size_and_depth() {
+        let long = format!("1{}", " + 1".repeat(MAX_LENGTH / 4));
+        assert!(long.len() > MAX_LENGTH && Expr::parse(&long).is_err());
+        let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(Expr::parse(&nested(MAX_DEPTH - 1)).is_ok());
+        assert!(Expr::parse(&nested(MAX_DEPTH + 1)).is_err());
+        assert!(Expr::parse(&"-".repeat(10_000)).is_err());
+    }
+
+    #[test]
+    fn nested_changes_stay_cheap() {
+        let mut source = String::from("revenue");
+        for _ in 0..(MAX_DEPTH - 2) {
+            source = format!("cagr({}, 1)", source);
+        }
+        let history = report_computer::history(&sample_report());
+        let expr = Expr::parse(&source).unwrap();
+        // Without the memo this would take 2^30 evaluations
+        assert_eq!(expr.evaluate(&history, 2012), None);
+    }
+
+    #[test]
+    fn traces_reads_and_steps() {
+        let history = report_computer::history(&sample_report());
+        let mut trace = Trace::default();
+        let value = Expr::parse("net-income / revenue").unwrap().evaluate_traced(&history, 2010, &mut trace);
+        assert_eq!(trace.reads.len(), 2);
+        assert_eq!(trace.steps.last().map(|step| step.2), Some(value));
+    }
+}
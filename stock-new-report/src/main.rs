@@ -1,247 +1,1058 @@
 use actix_cors::Cors;
-use actix_web::{middleware::Logger, get, post, delete, web, App, HttpServer, HttpResponse};
-use bson::doc;
-
-use mongodb::{options::{ClientOptions}, Client};
+use actix_web::{middleware::Logger, get, post, put, delete, web, App, HttpServer, HttpResponse};
 
+use chrono::Utc;
 use std::sync::Arc;
-use log::{info, error};
+use log::{info, warn, error};
 
+mod app_error;
+mod config;
+mod corporate_actions;
+mod currency;
+mod explain;
+mod formula;
+mod memory_store;
+mod metric_math;
+mod mongo_store;
+mod report_computer;
+mod report_growth;
+mod report_history;
 mod report_model;
+mod report_store;
+mod report_validator;
+mod sqlite_store;
+mod valuation;
+use app_error::AppError;
+use config::{redact_uri, AppConfig, StoreKind};
+use corporate_actions::CorporateAction;
+use currency::{Conversion, ConversionTarget, Convert, FxRates, FxTable, UnitScale};
+use memory_store::MemoryReportStore;
+use mongo_store::MongoReportStore;
+use report_computer::CustomMetric;
+use report_growth::GrowthSettings;
+use report_history::{Revision, RevisionSummary};
 use report_model::{
-    AnnualStockReport, Report
+    AnnualStockReport, DuplicateYearPolicy, QuarterlyReport, Report
 };
+use serde::{Deserialize, Serialize};
+use report_store::{Cursor, ListQuery, Projection, ReportStore, SortKey, SortOrder};
+use report_validator::YearFindings;
+use sqlite_store::SqliteReportStore;
+
+type HandlerResult = Result<HttpResponse, AppError>;
+
+/// Rejects incoming periods that break an accounting identity. Warnings alone
+/// don't block the write; they show up on the validate endpoint.
+fn reject_invalid(findings: Vec<YearFindings>) -> Result<(), AppError> {
+    if report_validator::has_errors(&findings) {
+        return Err(AppError::ValidationFailed(findings));
+    }
+    Ok(())
+}
 
-#[derive(Clone)]
-struct Database {
-    client: Client,
-    db_name: String,
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct ViewOptions {
+    /// Per-share figures as filed, without adjusting for later splits.
+    #[serde(default)]
+    as_reported: bool,
+    /// Comma-separated windows and figures for `cagr`, replacing the configured ones.
+    cagr_windows: Option<String>,
+    cagr_metrics: Option<String>,
+}
+
+/// Shapes a stored report for a read endpoint: split-adjusted unless the
+/// client asked for the figures as reported, then in the currency and scale
+/// it asked for, if any. Custom metrics and growth rates are evaluated last,
+/// on those figures; both only concern years, so quarterly reads skip them.
+fn present(
+    mut report: AnnualStockReport,
+    view: &ViewOptions,
+    target: &ConversionTarget,
+    fx: &FxTable,
+    custom_metrics: &[CustomMetric],
+    growth: Option<&GrowthSettings>,
+) -> Result<AnnualStockReport, AppError> {
+    if !view.as_reported {
+        corporate_actions::split_adjust(&mut report);
+    }
+    if !target.is_empty() {
+        let conversion = Conversion::plan(&report, target, &fx.snapshot())?;
+        report.convert(&conversion);
+    }
+    report_computer::evaluate_custom(&mut report, custom_metrics);
+    if let Some(growth) = growth {
+        let growth = growth.with_lists(view.cagr_windows.as_deref(), view.cagr_metrics.as_deref())?;
+        report_growth::evaluate(&mut report, &growth);
+    }
+    Ok(report)
+}
+
+/// Loads a ticker's report, turning "not stored" into a 404. Derived data is
+/// recomputed, as not every store keeps all of it, e.g. `ttm`, and documents
+/// stored before the years were kept in order are put back in order.
+async fn load_complete_report(store: &dyn ReportStore, ticker: &str) -> Result<AnnualStockReport, AppError> {
+    let mut report = store
+        .get(ticker)
+        .await?
+        .ok_or_else(|| AppError::TickerNotFound(ticker.to_string()))?;
+    report.normalize_loaded();
+    Ok(report)
+}
+
+#[derive(Deserialize)]
+struct AddReportOptions {
+    #[serde(rename = "on-duplicate", default)]
+    on_duplicate: DuplicateYearPolicy,
 }
 
 #[post("/add_report/{ticker}")]
 async fn srv_add_report(
     ticker: web::Path<String>,
     report: web::Json<Report>,
-    db: web::Data<Arc<Database>>
-) -> impl actix_web::Responder {
+    options: web::Query<AddReportOptions>,
+    store: web::Data<dyn ReportStore>
+) -> HandlerResult {
 
     info!("/add_report/{}", ticker.as_str());
 
-    let filter = doc! { "ticker": ticker.as_str() };
-
-    let collection = db
-        .client
-        .database(db.db_name.as_str())
-        .collection::<AnnualStockReport>("stock_reports");
+    let mut complete_report = load_complete_report(store.get_ref(), ticker.as_str()).await?;
+    let expected_version = complete_report.version;
+    let report = report.into_inner();
+    reject_invalid(report_validator::validate_input([&report]))?;
+    let description = format!("added year {}", report.year);
+    complete_report.add_new_report(report, options.on_duplicate)?;
+    complete_report.version = expected_version + 1;
 
-    match collection.find_one(filter.clone(), Option::None).await {
-        Err(err) => {
-            error!("Eror whilge getting tickr: {:?} with err={:?}", ticker, err);
-            HttpResponse::InternalServerError().body(format!("Failed to delete the ticker for: {:?}", ticker.as_str()))
-        },
-        Ok(stock) => {
-            if let Option::Some(mut complete_report) = stock{
-                complete_report.add_new_report(report.into_inner());
-
-                // delete the old one
-                match collection.delete_one(filter, None).await {
-                    Ok(result) => {
-                        if result.deleted_count != 1 {
-                            return HttpResponse::InternalServerError().body(format!("failed to update the report for: {:?}", ticker.as_str()));
-                        }
-                    },
-                    Err(err) => {
-                        return HttpResponse::InternalServerError().body(format!("Internal error: {:?}", err));
-                    }
-                }
-
-                // insert the new one
-                insert_complete_report(&collection, complete_report).await
+    replace_complete_report(store.get_ref(), complete_report, expected_version, description).await
+}
 
-                // TODO: handle the case when delete is with sucess but insert failes by inverting the 2 one or by doing it by transaction
-            }
-            else{
-                HttpResponse::Ok().body(format!("Ticker {} does not exists in db", ticker))
-            }
-        }
-    }
+/// Replaces the stored report and records the revision in a single operation,
+/// but only if nobody else has bumped its version since it was read. Otherwise
+/// the caller gets a 409.
+async fn replace_complete_report(
+    store: &dyn ReportStore,
+    complete_report: AnnualStockReport,
+    expected_version: i32,
+    description: String,
+) -> HandlerResult {
+    let revision = report_history::revision(&complete_report.ticker, Some(&complete_report), description);
+    store.replace(&complete_report, expected_version, &revision).await?;
+    Ok(HttpResponse::Ok().json(complete_report))
 }
 
-async fn insert_complete_report(db_collection: &mongodb::Collection<AnnualStockReport>, complete_report: AnnualStockReport) -> actix_web::HttpResponse {
-    let result = db_collection.insert_one(&complete_report, None).await;
-    match result {
-        Ok(insert_result) => {
-            // info!("Created id: {}", insert_result.inserted_id);
-            actix_web::HttpResponse::Created().json(complete_report)
-        },
-        Err(err) => {
-            error!("Error: {}", err);
-            actix_web::HttpResponse::InternalServerError().body("")
-        }
-    }
+async fn insert_complete_report(store: &dyn ReportStore, complete_report: AnnualStockReport, description: String) -> HandlerResult {
+    let revision = report_history::revision(&complete_report.ticker, Some(&complete_report), description);
+    store.create(&complete_report, &revision).await?;
+    Ok(HttpResponse::Created().json(complete_report))
 }
 
 #[post("/create_initial_report")]
 async fn srv_create_initial_report(
     item: web::Json<AnnualStockReport>,
-    db: web::Data<Arc<Database>>,
-) -> impl actix_web::Responder {
+    store: web::Data<dyn ReportStore>,
+) -> HandlerResult {
     info!("{:?}", item);
 
-    let db_collection = db
-        .client
-        .database(db.db_name.as_str())
-        .collection::<AnnualStockReport>("stock_reports");
-
-    insert_complete_report(&db_collection, AnnualStockReport::from(item)).await
+    let mut findings = report_validator::validate_input(&item.data);
+    findings.extend(report_validator::validate_quarter_input(&item.quarters));
+    reject_invalid(findings)?;
+    let complete_report = AnnualStockReport::from(item)?;
+    insert_complete_report(store.get_ref(), complete_report, String::from("created")).await
 }
 
 #[delete("/item/{ticker}")]
 async fn srv_delete_item(
     ticker: web::Path<String>,
-    db: web::Data<Arc<Database>>
-) -> impl actix_web::Responder 
+    store: web::Data<dyn ReportStore>
+) -> HandlerResult
+{
+    info!("{:?}", ticker.as_str());
+
+    let revision = report_history::revision(ticker.as_str(), None, String::from("deleted"));
+    let report = store
+        .delete(ticker.as_str(), &revision)
+        .await?
+        .ok_or_else(|| AppError::TickerNotFound(ticker.to_string()))?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[get("/item/{ticker}")]
+async fn srv_get_item(
+    ticker: web::Path<String>,
+    view: web::Query<ViewOptions>,
+    target: web::Query<ConversionTarget>,
+    store: web::Data<dyn ReportStore>,
+    fx: web::Data<FxTable>,
+    growth: web::Data<GrowthSettings>
+) -> HandlerResult
 {
     info!("{:?}", ticker.as_str());
-    let filter = doc! { "ticker": ticker.as_str() };
 
-    let collection = db
-        .client
-        .database(db.db_name.as_str())
-        .collection::<AnnualStockReport>("stock_reports");
+    let report = load_complete_report(store.get_ref(), ticker.as_str()).await?;
+    let custom_metrics = store.list_custom_metrics().await?;
+    let report = present(report, &view, &target, &fx, &custom_metrics, Some(&growth))?;
+    Ok(HttpResponse::Ok().json(report))
+}
 
-    match collection.find_one(filter.clone(), Option::None).await{
-        Err(err) => {
-            error!("Eror whilge getting tickr: {:?} with err={:?}", ticker, err);
-            HttpResponse::InternalServerError().body(format!("Failed to delete the ticker for: {:?}", ticker.as_str()))
-        },
-        Ok(stock) => {
-            if let Option::Some(report) = stock{
-                let result = collection.delete_one(filter, None).await.unwrap();
-                if result.deleted_count == 1 {
-                    HttpResponse::Ok().body(format!("I've just deleted the following report {:?}", report))
-                } else {
-                    HttpResponse::InternalServerError().body(format!("failed to delete the dicker for: {:?}", ticker.as_str()))
-                }
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 500;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct ListParams {
+    ticker_prefix: Option<String>,
+    year_from: Option<i32>,
+    year_to: Option<i32>,
+    has_year: Option<i32>,
+    #[serde(default)]
+    sort: SortKey,
+    #[serde(default)]
+    order: SortOrder,
+    limit: Option<usize>,
+    cursor: Option<String>,
+    #[serde(default)]
+    projection: Projection,
+}
+
+impl ListParams {
+    fn into_query(self) -> Result<ListQuery, AppError> {
+        let limit = self.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+        if limit == 0 || limit > MAX_PAGE_SIZE {
+            return Err(AppError::InvalidInput(format!("limit must be between 1 and {}", MAX_PAGE_SIZE)));
+        }
+        if let (Some(from), Some(to)) = (self.year_from, self.year_to) {
+            if from > to {
+                return Err(AppError::InvalidInput(format!("year-from {} is after year-to {}", from, to)));
             }
-            else{
-                HttpResponse::Ok().body(format!("Ticker {} does not exists in db", ticker))
+        }
+
+        let after = match self.cursor {
+            Some(encoded) => {
+                let cursor = Cursor::decode(&encoded)
+                    .ok_or_else(|| AppError::InvalidInput(String::from("cursor is not valid")))?;
+                if cursor.sort != self.sort || cursor.order != self.order {
+                    return Err(AppError::InvalidInput(String::from("cursor belongs to a different sort or order")));
+                }
+                Some(cursor)
+            },
+            None => None,
+        };
+
+        Ok(ListQuery {
+            ticker_prefix: self.ticker_prefix,
+            year_from: self.year_from,
+            year_to: self.year_to,
+            has_year: self.has_year,
+            sort: self.sort,
+            order: self.order,
+            after,
+            limit: Some(limit),
+            projection: self.projection,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct ReportSummary {
+    ticker: String,
+    #[serde(rename = "latest-update")]
+    latest_update: Option<i64>,
+    version: i32,
+    currency: Option<String>,
+    #[serde(rename = "unit-scale")]
+    unit_scale: Option<UnitScale>,
+}
+
+/// A listed report the requested conversion did not apply to, e.g. one
+/// stored without a currency. It is listed as stored, with the reason.
+#[derive(Serialize)]
+struct UnconvertedReport {
+    #[serde(flatten)]
+    report: AnnualStockReport,
+    #[serde(rename = "conversion-error")]
+    conversion_error: String,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum ListedItem {
+    Report(AnnualStockReport),
+    Unconverted(UnconvertedReport),
+    Summary(ReportSummary),
+}
+
+#[derive(Serialize)]
+struct ListResponse {
+    items: Vec<ListedItem>,
+    #[serde(rename = "next-cursor")]
+    next_cursor: Option<String>,
+}
+
+#[get("/items")]
+async fn srv_get_items(
+    params: web::Query<ListParams>,
+    view: web::Query<ViewOptions>,
+    target: web::Query<ConversionTarget>,
+    store: web::Data<dyn ReportStore>,
+    fx: web::Data<FxTable>,
+    growth: web::Data<GrowthSettings>
+) -> HandlerResult {
+    let query = params.into_inner().into_query()?;
+    let rates = fx.snapshot();
+    target.check(&rates)?;
+    let page = store.list(&query).await?;
+    let custom_metrics = store.list_custom_metrics().await?;
+    let unconverted = ConversionTarget::default();
+
+    let items = page
+        .items
+        .into_iter()
+        .map(|mut report| match query.projection {
+            Projection::Summary => Ok(ListedItem::Summary(ReportSummary {
+                ticker: report.ticker,
+                latest_update: report.latest_update,
+                version: report.version,
+                currency: report.currency,
+                unit_scale: report.unit_scale,
+            })),
+            projection => {
+                // Same as `load_complete_report`
+                report.normalize_loaded();
+                // The target itself was checked above, so this is about the report alone
+                let conversion_error = if target.is_empty() {
+                    None
+                } else {
+                    Conversion::plan(&report, &target, &rates).err()
+                };
+                let applied = if conversion_error.is_some() { &unconverted } else { &*target };
+                let mut report = present(report, &view, applied, &fx, &custom_metrics, Some(&growth))?;
+                report_store::apply_projection(&mut report, projection);
+                Ok(match conversion_error {
+                    Some(err) => ListedItem::Unconverted(UnconvertedReport {
+                        report,
+                        conversion_error: err.to_string(),
+                    }),
+                    None => ListedItem::Report(report),
+                })
             }
+        })
+        .collect::<Result<Vec<ListedItem>, AppError>>()?;
+
+    Ok(HttpResponse::Ok().json(ListResponse {
+        items,
+        next_cursor: page.next_cursor.map(|cursor| cursor.encode()),
+    }))
+}
+
+#[get("/item/{ticker}/year/{year}")]
+async fn srv_get_year(
+    path: web::Path<(String, i32)>,
+    view: web::Query<ViewOptions>,
+    target: web::Query<ConversionTarget>,
+    store: web::Data<dyn ReportStore>,
+    fx: web::Data<FxTable>,
+    growth: web::Data<GrowthSettings>
+) -> HandlerResult
+{
+    let (ticker, year) = path.into_inner();
+    info!("/item/{}/year/{}", ticker, year);
+
+    let complete_report = load_complete_report(store.get_ref(), &ticker).await?;
+    let custom_metrics = store.list_custom_metrics().await?;
+    let complete_report = present(complete_report, &view, &target, &fx, &custom_metrics, Some(&growth))?;
+    let report = complete_report
+        .get_year(year)
+        .ok_or(AppError::YearNotFound { ticker: ticker.clone(), year })?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[put("/item/{ticker}/year/{year}")]
+async fn srv_put_year(
+    path: web::Path<(String, i32)>,
+    report: web::Json<Report>,
+    store: web::Data<dyn ReportStore>
+) -> HandlerResult
+{
+    let (ticker, year) = path.into_inner();
+    info!("/item/{}/year/{}", ticker, year);
+
+    let report = report.into_inner();
+    if report.year != year {
+        return Err(AppError::InvalidInput(format!("Report is for {} but the path says {}", report.year, year)));
+    }
+    reject_invalid(report_validator::validate_input([&report]))?;
+
+    let mut complete_report = load_complete_report(store.get_ref(), &ticker).await?;
+    let expected_version = complete_report.version;
+    if !complete_report.replace_year(report) {
+        return Err(AppError::YearNotFound { ticker, year });
+    }
+    complete_report.version = expected_version + 1;
+
+    replace_complete_report(store.get_ref(), complete_report, expected_version, format!("edited year {}", year)).await
+}
+
+#[delete("/item/{ticker}/year/{year}")]
+async fn srv_delete_year(
+    path: web::Path<(String, i32)>,
+    store: web::Data<dyn ReportStore>
+) -> HandlerResult
+{
+    let (ticker, year) = path.into_inner();
+    info!("/item/{}/year/{}", ticker, year);
+
+    let mut complete_report = load_complete_report(store.get_ref(), &ticker).await?;
+    let expected_version = complete_report.version;
+    if complete_report.remove_year(year).is_none() {
+        return Err(AppError::YearNotFound { ticker, year });
+    }
+    complete_report.version = expected_version + 1;
+
+    replace_complete_report(store.get_ref(), complete_report, expected_version, format!("deleted year {}", year)).await
+}
+
+#[post("/item/{ticker}/quarters")]
+async fn srv_add_quarter(
+    ticker: web::Path<String>,
+    report: web::Json<QuarterlyReport>,
+    options: web::Query<AddReportOptions>,
+    store: web::Data<dyn ReportStore>
+) -> HandlerResult
+{
+    info!("/item/{}/quarters", ticker.as_str());
+
+    let report = report.into_inner();
+    reject_invalid(report_validator::validate_quarter_input([&report]))?;
+
+    let mut complete_report = load_complete_report(store.get_ref(), ticker.as_str()).await?;
+    let expected_version = complete_report.version;
+    let description = format!("added {} Q{}", report.year, report.quarter);
+    complete_report.add_quarter(report, options.on_duplicate)?;
+    complete_report.version = expected_version + 1;
+
+    replace_complete_report(store.get_ref(), complete_report, expected_version, description).await
+}
+
+#[get("/item/{ticker}/quarter/{year}/{quarter}")]
+async fn srv_get_quarter(
+    path: web::Path<(String, i32, u8)>,
+    view: web::Query<ViewOptions>,
+    target: web::Query<ConversionTarget>,
+    store: web::Data<dyn ReportStore>,
+    fx: web::Data<FxTable>
+) -> HandlerResult
+{
+    let (ticker, year, quarter) = path.into_inner();
+    info!("/item/{}/quarter/{}/{}", ticker, year, quarter);
+
+    let complete_report = load_complete_report(store.get_ref(), &ticker).await?;
+    let complete_report = present(complete_report, &view, &target, &fx, &[], None)?;
+    let report = complete_report
+        .get_quarter(year, quarter)
+        .ok_or(AppError::QuarterNotFound { ticker: ticker.clone(), year, quarter })?;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[put("/item/{ticker}/quarter/{year}/{quarter}")]
+async fn srv_put_quarter(
+    path: web::Path<(String, i32, u8)>,
+    report: web::Json<QuarterlyReport>,
+    store: web::Data<dyn ReportStore>
+) -> HandlerResult
+{
+    let (ticker, year, quarter) = path.into_inner();
+    info!("/item/{}/quarter/{}/{}", ticker, year, quarter);
+
+    let report = report.into_inner();
+    if report.period() != (year, quarter) {
+        return Err(AppError::InvalidInput(format!(
+            "Report is for {} Q{} but the path says {} Q{}",
+            report.year, report.quarter, year, quarter
+        )));
+    }
+    reject_invalid(report_validator::validate_quarter_input([&report]))?;
+
+    let mut complete_report = load_complete_report(store.get_ref(), &ticker).await?;
+    let expected_version = complete_report.version;
+    if !complete_report.replace_quarter(report) {
+        return Err(AppError::QuarterNotFound { ticker, year, quarter });
+    }
+    complete_report.version = expected_version + 1;
+
+    let description = format!("edited {} Q{}", year, quarter);
+    replace_complete_report(store.get_ref(), complete_report, expected_version, description).await
+}
+
+#[delete("/item/{ticker}/quarter/{year}/{quarter}")]
+async fn srv_delete_quarter(
+    path: web::Path<(String, i32, u8)>,
+    store: web::Data<dyn ReportStore>
+) -> HandlerResult
+{
+    let (ticker, year, quarter) = path.into_inner();
+    info!("/item/{}/quarter/{}/{}", ticker, year, quarter);
+
+    let mut complete_report = load_complete_report(store.get_ref(), &ticker).await?;
+    let expected_version = complete_report.version;
+    if complete_report.remove_quarter(year, quarter).is_none() {
+        return Err(AppError::QuarterNotFound { ticker, year, quarter });
+    }
+    complete_report.version = expected_version + 1;
+
+    let description = format!("deleted {} Q{}", year, quarter);
+    replace_complete_report(store.get_ref(), complete_report, expected_version, description).await
+}
+
+#[get("/item/{ticker}/ttm")]
+async fn srv_get_ttm(
+    ticker: web::Path<String>,
+    view: web::Query<ViewOptions>,
+    target: web::Query<ConversionTarget>,
+    store: web::Data<dyn ReportStore>,
+    fx: web::Data<FxTable>
+) -> HandlerResult
+{
+    info!("/item/{}/ttm", ticker.as_str());
+
+    let complete_report = load_complete_report(store.get_ref(), ticker.as_str()).await?;
+    let complete_report = present(complete_report, &view, &target, &fx, &[], None)?;
+    let ttm = complete_report
+        .ttm
+        .ok_or_else(|| AppError::TtmUnavailable(ticker.to_string()))?;
+    Ok(HttpResponse::Ok().json(ttm))
+}
+
+/// How one figure of a year was computed, on the same figures `GET /item`
+/// returns for the same view and conversion.
+#[get("/item/{ticker}/explain/{year}/{metric}")]
+async fn srv_explain(
+    path: web::Path<(String, i32, String)>,
+    view: web::Query<ViewOptions>,
+    target: web::Query<ConversionTarget>,
+    store: web::Data<dyn ReportStore>,
+    fx: web::Data<FxTable>
+) -> HandlerResult
+{
+    let (ticker, year, metric) = path.into_inner();
+    info!("/item/{}/explain/{}/{}", ticker, year, metric);
+
+    let complete_report = load_complete_report(store.get_ref(), &ticker).await?;
+    let custom_metrics = store.list_custom_metrics().await?;
+    let complete_report = present(complete_report, &view, &target, &fx, &custom_metrics, None)?;
+    let report = complete_report
+        .get_year(year)
+        .ok_or(AppError::YearNotFound { ticker: ticker.clone(), year })?;
+    let explanation = explain::explain(&complete_report, report, &metric, &custom_metrics)
+        .ok_or(AppError::MetricNotFound(metric))?;
+    Ok(HttpResponse::Ok().json(explanation))
+}
+
+/// Discounted cash flow value per share of a year, the latest by default,
+/// with every assumption it used. Runs on the figures `GET /item` returns for
+/// the same view and conversion, so the cash flow and price share a currency.
+#[post("/item/{ticker}/valuation/dcf")]
+async fn srv_dcf_valuation(
+    ticker: web::Path<String>,
+    request: web::Json<valuation::DcfRequest>,
+    view: web::Query<ViewOptions>,
+    target: web::Query<ConversionTarget>,
+    store: web::Data<dyn ReportStore>,
+    fx: web::Data<FxTable>
+) -> HandlerResult
+{
+    info!("/item/{}/valuation/dcf", ticker.as_str());
+
+    let complete_report = load_complete_report(store.get_ref(), ticker.as_str()).await?;
+    let complete_report = present(complete_report, &view, &target, &fx, &[], None)?;
+    let request = request.into_inner();
+    if let Some(year) = request.year {
+        if complete_report.get_year(year).is_none() {
+            return Err(AppError::YearNotFound { ticker: ticker.into_inner(), year });
         }
     }
+    let valuation = valuation::dcf(&complete_report, request)?;
+    Ok(HttpResponse::Ok().json(valuation))
 }
 
-#[get("/item/{ticker}")]
-async fn srv_get_item(
+#[get("/item/{ticker}/corporate-actions")]
+async fn srv_get_corporate_actions(
     ticker: web::Path<String>,
-    db: web::Data<Arc<Database>>
-) -> impl actix_web::Responder 
+    store: web::Data<dyn ReportStore>
+) -> HandlerResult
 {
-    info!("{:?}", ticker.as_str());
-    let filter = doc! { "ticker": ticker.as_str() };
+    info!("/item/{}/corporate-actions", ticker.as_str());
 
-    let report = db
-        .client
-        .database(db.db_name.as_str())
-        .collection::<AnnualStockReport>("stock_reports")
-        .find_one(filter, Option::None).await;
+    let complete_report = load_complete_report(store.get_ref(), ticker.as_str()).await?;
+    Ok(HttpResponse::Ok().json(complete_report.corporate_actions))
+}
 
-    match report {
-        Err(err) => {
-            error!("Failed to interact with db for getting ticker {:?}", err);
-            actix_web::HttpResponse::InternalServerError().body("Failed to extract all items from DB")
+/// Replaces the whole list; per-share history is re-adjusted on the next read.
+#[put("/item/{ticker}/corporate-actions")]
+async fn srv_put_corporate_actions(
+    ticker: web::Path<String>,
+    actions: web::Json<Vec<CorporateAction>>,
+    store: web::Data<dyn ReportStore>
+) -> HandlerResult
+{
+    info!("/item/{}/corporate-actions", ticker.as_str());
+
+    let mut actions = actions.into_inner();
+    corporate_actions::normalize(&mut actions)?;
+
+    let mut complete_report = load_complete_report(store.get_ref(), ticker.as_str()).await?;
+    let expected_version = complete_report.version;
+    complete_report.corporate_actions = actions;
+    complete_report.version = expected_version + 1;
+
+    let description = String::from("edited corporate actions");
+    replace_complete_report(store.get_ref(), complete_report, expected_version, description).await
+}
+
+#[get("/item/{ticker}/validate")]
+async fn srv_validate_item(
+    ticker: web::Path<String>,
+    store: web::Data<dyn ReportStore>
+) -> HandlerResult
+{
+    info!("/item/{}/validate", ticker.as_str());
+
+    let complete_report = load_complete_report(store.get_ref(), ticker.as_str()).await?;
+    Ok(HttpResponse::Ok().json(report_validator::validate_report(&complete_report)))
+}
+
+#[get("/item/{ticker}/revisions")]
+async fn srv_get_revisions(
+    ticker: web::Path<String>,
+    store: web::Data<dyn ReportStore>
+) -> HandlerResult
+{
+    info!("/item/{}/revisions", ticker.as_str());
+
+    let revisions = store.list_revisions(ticker.as_str()).await?;
+    let summaries: Vec<RevisionSummary> = revisions.iter().map(RevisionSummary::from).collect();
+    Ok(HttpResponse::Ok().json(summaries))
+}
+
+#[get("/item/{ticker}/revisions/{revision}")]
+async fn srv_get_revision(
+    path: web::Path<(String, i32)>,
+    store: web::Data<dyn ReportStore>
+) -> HandlerResult
+{
+    let (ticker, revision) = path.into_inner();
+    info!("/item/{}/revisions/{}", ticker, revision);
+
+    let found = store
+        .get_revision(&ticker, revision)
+        .await?
+        .ok_or(AppError::RevisionNotFound { ticker, revision })?;
+    Ok(HttpResponse::Ok().json(found))
+}
+
+#[get("/item/{ticker}/revisions/{from}/diff/{to}")]
+async fn srv_diff_revisions(
+    path: web::Path<(String, i32, i32)>,
+    store: web::Data<dyn ReportStore>
+) -> HandlerResult
+{
+    let (ticker, from, to) = path.into_inner();
+    info!("/item/{}/revisions/{}/diff/{}", ticker, from, to);
+
+    let revisions = store.list_revisions(&ticker).await?;
+    let find = |revision: i32| {
+        revisions
+            .iter()
+            .find(|entry| entry.revision == revision)
+            .ok_or_else(|| AppError::RevisionNotFound { ticker: ticker.clone(), revision })
+    };
+    let from_revision = find(from)?;
+    let to_revision = find(to)?;
+
+    Ok(HttpResponse::Ok().json(report_history::diff(
+        from_revision.report.as_ref(),
+        to_revision.report.as_ref(),
+    )))
+}
+
+#[post("/item/{ticker}/revisions/{revision}/restore")]
+async fn srv_restore_revision(
+    path: web::Path<(String, i32)>,
+    store: web::Data<dyn ReportStore>
+) -> HandlerResult
+{
+    let (ticker, revision) = path.into_inner();
+    info!("/item/{}/revisions/{}/restore", ticker, revision);
+
+    let mut restored = match store.get_revision(&ticker, revision).await? {
+        Some(Revision { report: Some(report), .. }) => report,
+        Some(_) => {
+            return Err(AppError::InvalidInput(format!("Revision {} of {} records a deletion, nothing to restore", revision, ticker)));
         },
-        Ok(opt_stock_report) => {
-            if let Option::Some(report) = opt_stock_report{
-                actix_web::HttpResponse::Ok().json(report)
-            }
-            else{
-                actix_web::HttpResponse::NotFound().body("")
-            }
+        None => return Err(AppError::RevisionNotFound { ticker, revision }),
+    };
+
+    let _ = restored.latest_update.insert(Utc::now().timestamp());
+    let description = format!("restored revision {}", revision);
+
+    match store.get(&ticker).await? {
+        Some(current) => {
+            restored.version = current.version + 1;
+            replace_complete_report(store.get_ref(), restored, current.version, description).await
         },
+        None => insert_complete_report(store.get_ref(), restored, description).await,
     }
+}
 
+/// Every metric the registry computes, with its inputs and formula.
+#[get("/metrics")]
+async fn srv_get_metrics() -> HandlerResult {
+    Ok(HttpResponse::Ok().json(report_computer::registry().metrics()))
 }
 
-#[get("/items")]
-async fn srv_get_items(db: web::Data<Arc<Database>>) -> impl actix_web::Responder {
-    let collection = db
-        .client
-        .database(&db.db_name)
-        .collection::<AnnualStockReport>("stock_reports");
-
-    // Retrieve all stock reports from the collection
-    let cursor = collection.find(doc! {}, None).await;
-
-    match cursor {
-        Ok(mut cursor) => {
-            let mut reports = Vec::new();
-
-            loop {
-                let result = cursor.advance().await;
-                if result.is_err() {
-                    return actix_web::HttpResponse::InternalServerError()
-                        .body("Failed to extract all items from DB");
-                }
+#[get("/custom-metrics")]
+async fn srv_get_custom_metrics(store: web::Data<dyn ReportStore>) -> HandlerResult {
+    Ok(HttpResponse::Ok().json(store.list_custom_metrics().await?))
+}
 
-                if !result.unwrap() {
-                    break;
-                }
+#[derive(Deserialize)]
+struct CustomMetricDefinition {
+    formula: String,
+    #[serde(default)]
+    description: Option<String>,
+}
 
-                let report = cursor.deserialize_current();
-                match report {
-                    Ok(report) => reports.push(report),
-                    Err(_) => error!("Nothing temporary"),
-                }
-            }
+/// Defines or redefines a custom metric; every year of every ticker gets it
+/// on the next read.
+#[put("/custom-metrics/{id}")]
+async fn srv_put_custom_metric(
+    id: web::Path<String>,
+    definition: web::Json<CustomMetricDefinition>,
+    store: web::Data<dyn ReportStore>
+) -> HandlerResult {
+    info!("/custom-metrics/{}", id.as_str());
 
-            actix_web::HttpResponse::Ok().json(reports)
-        }
-        Err(_) => {
-            return actix_web::HttpResponse::InternalServerError()
-                .body("Failed to interact with DB");
-        }
+    let definition = definition.into_inner();
+    let metric = CustomMetric {
+        id: id.into_inner(),
+        formula: definition.formula,
+        description: definition.description,
+    };
+    metric.parse()?;
+    store.put_custom_metric(&metric).await?;
+    Ok(HttpResponse::Ok().json(metric))
+}
+
+#[delete("/custom-metrics/{id}")]
+async fn srv_delete_custom_metric(id: web::Path<String>, store: web::Data<dyn ReportStore>) -> HandlerResult {
+    info!("/custom-metrics/{}", id.as_str());
+
+    let metric = store
+        .delete_custom_metric(id.as_str())
+        .await?
+        .ok_or_else(|| AppError::CustomMetricNotFound(id.to_string()))?;
+    Ok(HttpResponse::Ok().json(metric))
+}
+
+#[get("/fx-rates")]
+async fn srv_get_fx_rates(fx: web::Data<FxTable>) -> HandlerResult {
+    Ok(HttpResponse::Ok().json(fx.snapshot()))
+}
+
+/// Replaces the whole FX table until the next restart; edit the configured
+/// file to keep the rates.
+#[put("/fx-rates")]
+async fn srv_put_fx_rates(rates: web::Json<FxRates>, fx: web::Data<FxTable>) -> HandlerResult {
+    let rates = rates.into_inner().normalized()?;
+    info!("/fx-rates: {} rates against {}", rates.rates.len(), rates.base);
+
+    fx.replace(rates.clone());
+    Ok(HttpResponse::Ok().json(rates))
+}
+
+async fn open_store(config: &AppConfig) -> Result<Arc<dyn ReportStore>, String> {
+    match config.store {
+        // Runs the service without any database; nothing survives a restart
+        StoreKind::Memory => Ok(Arc::new(MemoryReportStore::new())),
+        StoreKind::Sqlite => SqliteReportStore::open(&config.sqlite_path)
+            .map(|store| Arc::new(store) as Arc<dyn ReportStore>)
+            .map_err(|err| format!("cannot open SQLite database {}: {}", config.sqlite_path, err)),
+        StoreKind::Mongo => MongoReportStore::connect(&config.mongo_uri, &config.mongo_db_name)
+            .await
+            .map(|store| Arc::new(store) as Arc<dyn ReportStore>)
+            .map_err(|err| format!("cannot connect to MongoDB at {}: {}", redact_uri(&config.mongo_uri), err)),
+    }
+}
+
+fn cors(origins: &[String]) -> Cors {
+    let mut cors = Cors::default()
+        .allowed_methods(vec!["GET", "POST", "PUT", "DELETE"])
+        .allow_any_header()
+        .max_age(3600);
+    for origin in origins {
+        cors = if origin == "*" {
+            cors.allow_any_origin()
+        } else {
+            cors.allowed_origin(origin)
+        };
     }
+    cors
+}
+
+/// Every endpoint, and the extractor settings they share. The store, FX
+/// table and growth settings are added by the caller.
+fn routes(cfg: &mut web::ServiceConfig) {
+    cfg
+        // Malformed bodies and parameters get the same JSON errors as everything else
+        .app_data(web::JsonConfig::default().error_handler(app_error::invalid_input))
+        .app_data(web::QueryConfig::default().error_handler(app_error::invalid_input))
+        .app_data(web::PathConfig::default().error_handler(app_error::invalid_input))
+        .service(srv_create_initial_report)
+        .service(srv_get_items)
+        .service(srv_get_item)
+        .service(srv_delete_item)
+        .service(srv_add_report)
+        .service(srv_get_year)
+        .service(srv_put_year)
+        .service(srv_delete_year)
+        .service(srv_add_quarter)
+        .service(srv_get_quarter)
+        .service(srv_put_quarter)
+        .service(srv_delete_quarter)
+        .service(srv_get_ttm)
+        .service(srv_explain)
+        .service(srv_dcf_valuation)
+        .service(srv_get_corporate_actions)
+        .service(srv_put_corporate_actions)
+        .service(srv_validate_item)
+        .service(srv_get_revisions)
+        .service(srv_get_revision)
+        .service(srv_diff_revisions)
+        .service(srv_restore_revision)
+        .service(srv_get_metrics)
+        .service(srv_get_custom_metrics)
+        .service(srv_put_custom_metric)
+        .service(srv_delete_custom_metric)
+        .service(srv_get_fx_rates)
+        .service(srv_put_fx_rates);
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let client_options = ClientOptions::parse("mongodb://localhost:27017")
-        .await
-        .unwrap();
-    let db_name = String::from("anual-reports-db");
-    // Create a MongoDB client
-    let client = Client::with_options(client_options).unwrap();
-
-    // Check if the "mydb" database exists; if not, create it
-    let databases = client.list_database_names(None, None).await.unwrap();
-    if !databases.contains(&db_name.to_string()) {
-        client
-            .database(db_name.as_str())
-            .create_collection("stock_reports", None)
-            .await
-            .unwrap();
+    let config = match AppConfig::load() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Configuration error: {}", err);
+            std::process::exit(2);
+        }
+    };
+    info!("{:?}", config);
+    if config.cors_origins.is_empty() {
+        warn!("No cors-origins configured; browsers will be refused cross-origin requests");
     }
 
-    // Create a data structure to share the MongoDB client across Actix threads
-    let database = Database {
-        client: client,
-        db_name: db_name,
+    let store = match open_store(&config).await {
+        Ok(store) => store,
+        Err(err) => {
+            error!("{}", err);
+            eprintln!("Startup error: {}", err);
+            std::process::exit(1);
+        }
     };
+    let store = web::Data::from(store);
 
+    let fx = match &config.fx_rates_path {
+        Some(path) => match FxTable::load(path) {
+            Ok(fx) => fx,
+            Err(err) => {
+                error!("cannot load FX rates from {}: {}", path, err);
+                eprintln!("Startup error: cannot load FX rates from {}: {}", path, err);
+                std::process::exit(1);
+            }
+        },
+        None => FxTable::new(FxRates::default()),
+    };
+    let fx = web::Data::new(fx);
+    let growth = web::Data::new(config.growth.clone());
+    let cors_origins = config.cors_origins.clone();
 
     HttpServer::new(move || {
         App::new()
             .wrap(Logger::default()) // Use the Logger middleware to log requests
-            .wrap(Cors::permissive()) // Enable CORS with default options
-            .data(Arc::new(database.clone()))
-            .service(srv_create_initial_report)
-            .service(srv_get_items)
-            .service(srv_get_item)
-            .service(srv_delete_item)
-            .service(srv_add_report)
+            .wrap(cors(&cors_origins)) // Only the configured origins may call us from a browser
+            .app_data(store.clone())
+            .app_data(fx.clone())
+            .app_data(growth.clone())
+            .configure(routes)
     })
-    .bind("127.0.0.1:8080")?
+    .bind(config.bind_address)?
     .run()
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::dev::{Service, ServiceResponse};
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use serde_json::{json, Value};
+
+    fn sample(ticker: &str) -> Value {
+        let mut report: Value = serde_json::from_str(include_str!("../scripts/report.json")).unwrap();
+        report["ticker"] = json!(ticker);
+        report
+    }
+
+    async fn app() -> impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error> {
+        let store: Arc<dyn ReportStore> = Arc::new(MemoryReportStore::new());
+        test::init_service(
+            App::new()
+                .app_data(web::Data::from(store))
+                .app_data(web::Data::new(FxTable::new(FxRates::default())))
+                .app_data(web::Data::new(GrowthSettings::default()))
+                .configure(routes),
+        )
+        .await
+    }
+
+    async fn create(
+        app: &impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error>,
+        report: &Value,
+    ) -> ServiceResponse {
+        let request = test::TestRequest::post().uri("/create_initial_report").set_json(report);
+        test::call_service(app, request.to_request()).await
+    }
+
+    #[actix_web::test]
+    async fn creates_and_reads_back_a_report() {
+        let app = app().await;
+        assert_eq!(create(&app, &sample("PEP")).await.status(), StatusCode::CREATED);
+
+        let response = test::call_service(&app, test::TestRequest::get().uri("/item/PEP").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let report: Value = test::read_body_json(response).await;
+        assert_eq!(report["ticker"], "PEP");
+
+        let response = test::call_service(&app, test::TestRequest::get().uri("/item/KO").to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn duplicates_are_conflicts() {
+        let app = app().await;
+        create(&app, &sample("PEP")).await;
+        assert_eq!(create(&app, &sample("PEP")).await.status(), StatusCode::CONFLICT);
+
+        let mut twice = sample("KO");
+        let year = twice["data"][0].clone();
+        twice["data"].as_array_mut().unwrap().push(year.clone());
+        let response = create(&app, &twice).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["code"], "duplicate-year");
+
+        let request = test::TestRequest::post().uri("/add_report/PEP").set_json(&year);
+        let response = test::call_service(&app, request.to_request()).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    #[actix_web::test]
+    async fn items_are_paged() {
+        let app = app().await;
+        for ticker in ["KO", "MO", "PEP"] {
+            create(&app, &sample(ticker)).await;
+        }
+
+        let request = test::TestRequest::get().uri("/items?limit=2&projection=summary");
+        let first: Value = test::call_and_read_body_json(&app, request.to_request()).await;
+        assert_eq!(first["items"].as_array().unwrap().len(), 2);
+        let cursor = first["next-cursor"].as_str().expect("a second page");
+
+        let uri = format!("/items?limit=2&projection=summary&cursor={}", cursor);
+        let second: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(second["items"].as_array().unwrap().len(), 1);
+        assert!(second["next-cursor"].is_null());
+
+        let request = test::TestRequest::get().uri("/items?cursor=garbage");
+        let response = test::call_service(&app, request.to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[actix_web::test]
+    async fn unconvertible_reports_are_listed_as_stored() {
+        let app = app().await;
+        let request = test::TestRequest::put()
+            .uri("/fx-rates")
+            .set_json(json!({"base": "USD", "rates": {"EUR": 0.9}}));
+        assert_eq!(test::call_service(&app, request.to_request()).await.status(), StatusCode::OK);
+        let mut no_currency = sample("KO");
+        no_currency.as_object_mut().unwrap().remove("currency");
+        create(&app, &no_currency).await;
+
+        let request = test::TestRequest::get().uri("/items?currency=EUR");
+        let page: Value = test::call_and_read_body_json(&app, request.to_request()).await;
+        let item = &page["items"][0];
+        assert_eq!(item["ticker"], "KO");
+        assert!(item["conversion-error"].is_string());
+
+        let request = test::TestRequest::get().uri("/items?currency=XXX");
+        let response = test::call_service(&app, request.to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[actix_web::test]
+    async fn dcf_rejects_bad_assumptions() {
+        let app = app().await;
+        create(&app, &sample("PEP")).await;
+
+        let request = test::TestRequest::post()
+            .uri("/item/PEP/valuation/dcf")
+            .set_json(json!({"discount-rate": 0.02, "terminal-growth": 0.03, "stages": [{"years": 5, "growth": 0.05}]}));
+        let response = test::call_service(&app, request.to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[actix_web::test]
+    async fn deletion_is_recorded_and_can_be_undone() {
+        let app = app().await;
+        create(&app, &sample("PEP")).await;
+        let request = test::TestRequest::delete().uri("/item/PEP");
+        assert_eq!(test::call_service(&app, request.to_request()).await.status(), StatusCode::OK);
+
+        let request = test::TestRequest::get().uri("/item/PEP/revisions");
+        let revisions: Value = test::call_and_read_body_json(&app, request.to_request()).await;
+        assert_eq!(revisions.as_array().unwrap().len(), 2);
+
+        let request = test::TestRequest::post().uri("/item/PEP/revisions/2/restore");
+        let response = test::call_service(&app, request.to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let request = test::TestRequest::post().uri("/item/PEP/revisions/1/restore");
+        let response = test::call_service(&app, request.to_request()).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    #[actix_web::test]
+    async fn custom_metrics_show_up_on_every_year() {
+        let app = app().await;
+        create(&app, &sample("PEP")).await;
+
+        let request = test::TestRequest::put()
+            .uri("/custom-metrics/double-revenue")
+            .set_json(json!({"formula": "revenue * 2"}));
+        assert_eq!(test::call_service(&app, request.to_request()).await.status(), StatusCode::OK);
+        let request = test::TestRequest::put()
+            .uri("/custom-metrics/broken")
+            .set_json(json!({"formula": "revenue *"}));
+        assert_eq!(test::call_service(&app, request.to_request()).await.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let request = test::TestRequest::get().uri("/item/PEP");
+        let report: Value = test::call_and_read_body_json(&app, request.to_request()).await;
+        let year = &report["data"][0];
+        assert_eq!(
+            year["custom-metrics"]["double-revenue"].as_f64(),
+            year["income-statement"]["revenue"].as_f64().map(|revenue| revenue * 2.0)
+        );
+    }
+}
@@ -0,0 +1,294 @@
+use async_trait::async_trait;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+use crate::report_computer::CustomMetric;
+use crate::report_history::Revision;
+use crate::report_model::AnnualStockReport;
+use crate::report_store::{
+    apply_projection, ListQuery, ReportPage, ReportStore, SortKey, SortOrder, StoreError,
+};
+
+/// Keeps every report in process memory. Nothing survives a restart, which is
+/// the point: the service can run locally without any database.
+#[derive(Default)]
+pub struct MemoryReportStore {
+    reports: RwLock<BTreeMap<String, AnnualStockReport>>,
+    revisions: RwLock<BTreeMap<String, Vec<Revision>>>,
+    custom_metrics: RwLock<BTreeMap<String, CustomMetric>>,
+}
+
+impl MemoryReportStore {
+    pub fn new() -> MemoryReportStore {
+        MemoryReportStore::default()
+    }
+}
+
+fn matches(query: &ListQuery, report: &AnnualStockReport) -> bool {
+    if let Some(prefix) = &query.ticker_prefix {
+        if !report.ticker.starts_with(prefix.as_str()) {
+            return false;
+        }
+    }
+    if query.year_from.is_some() || query.year_to.is_some() {
+        let from = query.year_from.unwrap_or(i32::MIN);
+        let to = query.year_to.unwrap_or(i32::MAX);
+        if !report.data.iter().any(|year| year.year >= from && year.year <= to) {
+            return false;
+        }
+    }
+    if let Some(has_year) = query.has_year {
+        if report.get_year(has_year).is_none() {
+            return false;
+        }
+    }
+    true
+}
+
+/// Orders by the sort key, then by ticker, honouring the direction.
+fn compare(sort: SortKey, order: SortOrder, lhs: (Option<i64>, &str), rhs: (Option<i64>, &str)) -> Ordering {
+    let ordering = match sort {
+        SortKey::Ticker => lhs.1.cmp(rhs.1),
+        SortKey::LatestUpdate => lhs.cmp(&rhs),
+    };
+    match order {
+        SortOrder::Asc => ordering,
+        SortOrder::Desc => ordering.reverse(),
+    }
+}
+
+fn key(report: &AnnualStockReport) -> (Option<i64>, &str) {
+    (report.latest_update, report.ticker.as_str())
+}
+
+/// Numbers `revision` one past the latest of its ticker and appends it. The
+/// caller holds the report lock too, so the change and its revision land together.
+fn append_revision(revisions: &mut BTreeMap<String, Vec<Revision>>, revision: &Revision) {
+    let history = revisions.entry(revision.ticker.clone()).or_default();
+    let number = history.last().map_or(1, |last| last.revision + 1);
+    history.push(Revision {
+        revision: number,
+        ..revision.clone()
+    });
+}
+
+fn poisoned<T>(_: T) -> StoreError {
+    StoreError::Backend(String::from("in-memory store lock poisoned"))
+}
+
+#[async_trait]
+impl ReportStore for MemoryReportStore {
+    async fn get(&self, ticker: &str) -> Result<Option<AnnualStockReport>, StoreError> {
+        let reports = self.reports.read().map_err(poisoned)?;
+        Ok(reports.get(ticker).cloned())
+    }
+
+    async fn list(&self, query: &ListQuery) -> Result<ReportPage, StoreError> {
+        let reports = self.reports.read().map_err(poisoned)?;
+
+        let mut matching: Vec<&AnnualStockReport> = reports
+            .values()
+            .filter(|report| matches(query, report))
+            .filter(|report| match &query.after {
+                Some(cursor) => {
+                    compare(query.sort, query.order, key(report), (cursor.latest_update, cursor.ticker.as_str()))
+                        == Ordering::Greater
+                }
+                None => true,
+            })
+            .collect();
+        matching.sort_by(|lhs, rhs| compare(query.sort, query.order, key(lhs), key(rhs)));
+
+        let take = query.limit.map_or(usize::MAX, |limit| limit + 1);
+        let items = matching
+            .into_iter()
+            .take(take)
+            .map(|report| {
+                let mut report = report.clone();
+                apply_projection(&mut report, query.projection.stored());
+                report
+            })
+            .collect();
+        Ok(ReportPage::from_overfetch(items, query))
+    }
+
+    async fn create(&self, report: &AnnualStockReport, revision: &Revision) -> Result<(), StoreError> {
+        let mut reports = self.reports.write().map_err(poisoned)?;
+        let mut revisions = self.revisions.write().map_err(poisoned)?;
+        if reports.contains_key(&report.ticker) {
+            return Err(StoreError::AlreadyExists(report.ticker.clone()));
+        }
+        reports.insert(report.ticker.clone(), report.clone());
+        append_revision(&mut revisions, revision);
+        Ok(())
+    }
+
+    async fn replace(&self, report: &AnnualStockReport, expected_version: i32, revision: &Revision) -> Result<(), StoreError> {
+        let mut reports = self.reports.write().map_err(poisoned)?;
+        let mut revisions = self.revisions.write().map_err(poisoned)?;
+        match reports.get_mut(&report.ticker) {
+            Some(stored) if stored.version == expected_version => {
+                *stored = report.clone();
+                append_revision(&mut revisions, revision);
+                Ok(())
+            }
+            _ => Err(StoreError::VersionConflict {
+                ticker: report.ticker.clone(),
+                expected_version,
+            }),
+        }
+    }
+
+    async fn delete(&self, ticker: &str, revision: &Revision) -> Result<Option<AnnualStockReport>, StoreError> {
+        let mut reports = self.reports.write().map_err(poisoned)?;
+        let mut revisions = self.revisions.write().map_err(poisoned)?;
+        let removed = reports.remove(ticker);
+        if removed.is_some() {
+            append_revision(&mut revisions, revision);
+        }
+        Ok(removed)
+    }
+
+    async fn list_revisions(&self, ticker: &str) -> Result<Vec<Revision>, StoreError> {
+        let revisions = self.revisions.read().map_err(poisoned)?;
+        Ok(revisions.get(ticker).cloned().unwrap_or_default())
+    }
+
+    async fn get_revision(&self, ticker: &str, revision: i32) -> Result<Option<Revision>, StoreError> {
+        let revisions = self.revisions.read().map_err(poisoned)?;
+        Ok(revisions
+            .get(ticker)
+            .and_then(|history| history.iter().find(|entry| entry.revision == revision))
+            .cloned())
+    }
+
+    async fn list_custom_metrics(&self) -> Result<Vec<CustomMetric>, StoreError> {
+        let metrics = self.custom_metrics.read().map_err(poisoned)?;
+        Ok(metrics.values().cloned().collect())
+    }
+
+    async fn put_custom_metric(&self, metric: &CustomMetric) -> Result<(), StoreError> {
+        let mut metrics = self.custom_metrics.write().map_err(poisoned)?;
+        metrics.insert(metric.id.clone(), metric.clone());
+        Ok(())
+    }
+
+    async fn delete_custom_metric(&self, id: &str) -> Result<Option<CustomMetric>, StoreError> {
+        let mut metrics = self.custom_metrics.write().map_err(poisoned)?;
+        Ok(metrics.remove(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report_history;
+    use crate::report_model::tests::sample_report;
+    use crate::report_store::Projection;
+
+    fn report(ticker: &str, latest_update: i64) -> AnnualStockReport {
+        let mut report = sample_report();
+        report.ticker = ticker.to_string();
+        report.latest_update = Some(latest_update);
+        report
+    }
+
+    fn revision(ticker: &str, description: &str) -> Revision {
+        report_history::revision(ticker, None, description.to_string())
+    }
+
+    async fn history(store: &MemoryReportStore, ticker: &str) -> Vec<(i32, String)> {
+        let revisions = store.list_revisions(ticker).await.unwrap();
+        revisions.into_iter().map(|revision| (revision.revision, revision.description)).collect()
+    }
+
+    async fn store_with(tickers: &[(&str, i64)]) -> MemoryReportStore {
+        let store = MemoryReportStore::new();
+        for (ticker, latest_update) in tickers {
+            store.create(&report(ticker, *latest_update), &revision(ticker, "created")).await.unwrap();
+        }
+        store
+    }
+
+    fn tickers(page: &ReportPage) -> Vec<&str> {
+        page.items.iter().map(|report| report.ticker.as_str()).collect()
+    }
+
+    #[actix_web::test]
+    async fn create_rejects_a_second_copy() {
+        let store = store_with(&[("PEP", 1)]).await;
+        assert!(matches!(
+            store.create(&report("PEP", 2), &revision("PEP", "created again")).await,
+            Err(StoreError::AlreadyExists(_))
+        ));
+        assert_eq!(history(&store, "PEP").await, [(1, String::from("created"))]);
+    }
+
+    #[actix_web::test]
+    async fn replace_checks_the_version() {
+        let store = store_with(&[("PEP", 1)]).await;
+        let mut changed = store.get("PEP").await.unwrap().unwrap();
+        changed.version += 1;
+
+        assert!(matches!(
+            store.replace(&changed, changed.version, &revision("PEP", "lost")).await,
+            Err(StoreError::VersionConflict { .. })
+        ));
+        store.replace(&changed, changed.version - 1, &revision("PEP", "edited")).await.unwrap();
+        assert_eq!(store.get("PEP").await.unwrap().unwrap().version, changed.version);
+        assert_eq!(history(&store, "PEP").await, [(1, String::from("created")), (2, String::from("edited"))]);
+    }
+
+    #[actix_web::test]
+    async fn pages_follow_the_cursor() {
+        let store = store_with(&[("KO", 3), ("PEP", 1), ("MO", 2), ("PG", 2)]).await;
+        let mut query = ListQuery {
+            sort: SortKey::LatestUpdate,
+            order: SortOrder::Desc,
+            limit: Some(2),
+            ..ListQuery::default()
+        };
+
+        let first = store.list(&query).await.unwrap();
+        assert_eq!(tickers(&first), ["KO", "PG"]);
+        query.after = first.next_cursor;
+        let second = store.list(&query).await.unwrap();
+        assert_eq!(tickers(&second), ["MO", "PEP"]);
+        assert!(second.next_cursor.is_none());
+    }
+
+    #[actix_web::test]
+    async fn filters_and_projects() {
+        let store = store_with(&[("PEP", 1), ("PG", 2), ("KO", 3)]).await;
+        let query = ListQuery {
+            ticker_prefix: Some(String::from("P")),
+            has_year: Some(2011),
+            projection: Projection::Summary,
+            ..ListQuery::default()
+        };
+        let page = store.list(&query).await.unwrap();
+        assert_eq!(tickers(&page), ["PEP", "PG"]);
+        assert!(page.items.iter().all(|report| report.data.is_empty()));
+
+        let none = ListQuery {
+            year_from: Some(2013),
+            ..ListQuery::default()
+        };
+        assert!(store.list(&none).await.unwrap().items.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn numbers_revisions_per_ticker() {
+        let store = store_with(&[("PEP", 1), ("KO", 1)]).await;
+        assert!(store.delete("MO", &revision("MO", "deleted")).await.unwrap().is_none());
+        store.delete("PEP", &revision("PEP", "deleted")).await.unwrap();
+        store.create(&report("PEP", 2), &revision("PEP", "created")).await.unwrap();
+
+        let numbers = |history: Vec<(i32, String)>| history.into_iter().map(|(number, _)| number).collect::<Vec<_>>();
+        assert_eq!(numbers(history(&store, "PEP").await), [1, 2, 3]);
+        assert_eq!(numbers(history(&store, "KO").await), [1]);
+        assert!(history(&store, "MO").await.is_empty());
+        assert_eq!(store.get_revision("PEP", 2).await.unwrap().unwrap().description, "deleted");
+    }
+}
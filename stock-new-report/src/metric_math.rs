@@ -0,0 +1,80 @@
+//! Guarded arithmetic for derived metrics. Every helper returns `None` instead
+//! of a NaN or infinite value, which JSON cannot represent and which would
+//! otherwise poison every metric computed from it.
+
+/// `value` itself, unless it is NaN or infinite.
+pub fn finite(value: f64) -> Option<f64> {
+    if value.is_finite() {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+/// `numerator / denominator`, or `None` on a zero denominator.
+pub fn safe_div(numerator: f64, denominator: f64) -> Option<f64> {
+    if denominator == 0.0 {
+        return None;
+    }
+    finite(numerator / denominator)
+}
+
+/// Same as `safe_div` for inputs that may themselves be undefined.
+pub fn safe_div_opt(numerator: Option<f64>, denominator: Option<f64>) -> Option<f64> {
+    safe_div(numerator?, denominator?)
+}
+
+/// Relative change from `base` to `current`, e.g. 0.05 for +5%.
+///
+/// The change is measured against the size of the base, so a negative base
+/// moving towards zero reads as growth (-100 to -80 is +20%) rather than the
+/// sign-flipped result a plain ratio would give. A zero base is undefined.
+pub fn growth(current: f64, base: f64) -> Option<f64> {
+    safe_div(current - base, base.abs())
+}
+
+pub fn growth_opt(current: Option<f64>, base: Option<f64>) -> Option<f64> {
+    growth(current?, base?)
+}
+
+/// Compound annual growth rate over `years`. Undefined unless the base is
+/// positive and the end is not negative: there is no real root of a negative
+/// ratio, and a zero base has no growth rate at all. An end of zero, e.g. a
+/// dividend that was cut, is -100%.
+pub fn cagr(current: f64, base: f64, years: f64) -> Option<f64> {
+    if base <= 0.0 || current < 0.0 || years <= 0.0 {
+        return None;
+    }
+    finite((current / base).powf(1.0 / years) - 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn safe_div_has_no_infinities() {
+        assert_eq!(safe_div(1.0, 4.0), Some(0.25));
+        assert_eq!(safe_div(1.0, 0.0), None);
+        assert_eq!(safe_div(f64::MAX, f64::MIN_POSITIVE), None);
+        assert_eq!(safe_div_opt(Some(1.0), None), None);
+    }
+
+    #[test]
+    fn growth_is_measured_against_the_size_of_the_base() {
+        assert_eq!(growth(105.0, 100.0), Some(0.05));
+        assert_eq!(growth(-80.0, -100.0), Some(0.2));
+        assert_eq!(growth(5.0, 0.0), None);
+        assert_eq!(growth_opt(None, Some(1.0)), None);
+    }
+
+    #[test]
+    fn cagr_edge_cases() {
+        assert!((cagr(121.0, 100.0, 2.0).unwrap() - 0.1).abs() < 1e-12);
+        assert_eq!(cagr(0.0, 100.0, 3.0), Some(-1.0));
+        assert_eq!(cagr(100.0, 0.0, 3.0), None);
+        assert_eq!(cagr(100.0, -10.0, 3.0), None);
+        assert_eq!(cagr(-1.0, 10.0, 3.0), None);
+        assert_eq!(cagr(100.0, 10.0, 0.0), None);
+    }
+}
@@ -0,0 +1,361 @@
+use async_trait::async_trait;
+use bson::{doc, Bson, Document};
+use log::{error, warn};
+use mongodb::{
+    error::{ErrorKind, WriteFailure},
+    options::{ClientOptions, FindOneOptions, FindOptions, IndexOptions, ReplaceOptions},
+    Client, Collection, IndexModel,
+};
+
+use crate::report_computer::CustomMetric;
+use crate::report_history::Revision;
+use crate::report_model::AnnualStockReport;
+use crate::report_store::{
+    Cursor, ListQuery, Projection, ReportPage, ReportStore, SortKey, SortOrder, StoreError,
+};
+
+const COLLECTION_NAME: &str = "stock_reports";
+const REVISIONS_COLLECTION_NAME: &str = "stock_report_revisions";
+const CUSTOM_METRICS_COLLECTION_NAME: &str = "custom_metrics";
+const DUPLICATE_KEY_CODE: i32 = 11000;
+/// How often `insert_revision` retries after losing the race for a number.
+const REVISION_ATTEMPTS: usize = 10;
+
+pub struct MongoReportStore {
+    collection: Collection<AnnualStockReport>,
+    revisions: Collection<Revision>,
+    custom_metrics: Collection<CustomMetric>,
+}
+
+impl MongoReportStore {
+    pub async fn connect(uri: &str, db_name: &str) -> mongodb::error::Result<MongoReportStore> {
+        let client_options = ClientOptions::parse(uri).await?;
+        // Create a MongoDB client
+        let client = Client::with_options(client_options)?;
+
+        // Check if the database exists; if not, create it
+        let databases = client.list_database_names(None, None).await?;
+        if !databases.contains(&db_name.to_string()) {
+            client
+                .database(db_name)
+                .create_collection(COLLECTION_NAME, None)
+                .await?;
+        }
+
+        let collection = client
+            .database(db_name)
+            .collection::<AnnualStockReport>(COLLECTION_NAME);
+
+        let ticker_index = IndexModel::builder()
+            .keys(doc! { "ticker": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        if let Err(err) = collection.create_index(ticker_index, None).await {
+            warn!("Could not create the unique ticker index: {}", err);
+        }
+
+        let revisions = client
+            .database(db_name)
+            .collection::<Revision>(REVISIONS_COLLECTION_NAME);
+
+        let revision_index = IndexModel::builder()
+            .keys(doc! { "ticker": 1, "revision": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        if let Err(err) = revisions.create_index(revision_index, None).await {
+            warn!("Could not create the revision index: {}", err);
+        }
+
+        let custom_metrics = client
+            .database(db_name)
+            .collection::<CustomMetric>(CUSTOM_METRICS_COLLECTION_NAME);
+
+        let id_index = IndexModel::builder()
+            .keys(doc! { "id": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        if let Err(err) = custom_metrics.create_index(id_index, None).await {
+            warn!("Could not create the custom metric index: {}", err);
+        }
+
+        Ok(MongoReportStore {
+            collection,
+            revisions,
+            custom_metrics,
+        })
+    }
+
+    /// Appends `revision` numbered one past the ticker's latest and returns the
+    /// number. The unique (ticker, revision) index makes the insert the
+    /// arbiter: a writer that picked a number someone else took retries with
+    /// the next.
+    async fn insert_revision(&self, revision: &Revision) -> Result<i32, StoreError> {
+        let latest = FindOneOptions::builder()
+            .sort(doc! { "revision": -1 })
+            .projection(doc! { "revision": 1, "_id": 0 })
+            .build();
+
+        for _ in 0..REVISION_ATTEMPTS {
+            let last = self
+                .revisions
+                .clone_with_type::<Document>()
+                .find_one(doc! { "ticker": revision.ticker.as_str() }, latest.clone())
+                .await
+                .map_err(backend_error)?
+                .and_then(|found| found.get_i32("revision").ok());
+            let numbered = Revision {
+                revision: last.map_or(1, |last| last + 1),
+                ..revision.clone()
+            };
+
+            match self.revisions.insert_one(&numbered, None).await {
+                Ok(_) => return Ok(numbered.revision),
+                Err(err) if is_duplicate_key(&err) => continue,
+                Err(err) => return Err(backend_error(err)),
+            }
+        }
+        Err(StoreError::Backend(format!(
+            "Could not allocate a revision number for {} after {} attempts",
+            revision.ticker, REVISION_ATTEMPTS
+        )))
+    }
+
+    /// Removes the revision of a change that then failed. A standalone MongoDB
+    /// server can't write two collections in one transaction, so each change
+    /// is recorded first and withdrawn here if it doesn't go through; a change
+    /// that can't be recorded is never made.
+    async fn withdraw_revision(&self, ticker: &str, revision: i32) {
+        let filter = doc! { "ticker": ticker, "revision": revision };
+        if let Err(err) = self.revisions.delete_one(filter, None).await {
+            error!("Could not withdraw revision {} of {} after a failed write: {}", revision, ticker, err);
+        }
+    }
+}
+
+fn backend_error(err: mongodb::error::Error) -> StoreError {
+    error!("MongoDB error: {}", err);
+    StoreError::Backend(err.to_string())
+}
+
+fn escape_regex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\^$.|?*+()[]{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Everything strictly after `cursor` in the requested order. Missing
+/// `latest-update` values sort first, as MongoDB itself orders nulls.
+fn after_cursor(cursor: &Cursor, order: SortOrder) -> Document {
+    let after = match order {
+        SortOrder::Asc => "$gt",
+        SortOrder::Desc => "$lt",
+    };
+    let ticker_after = doc! { "ticker": { after: cursor.ticker.as_str() } };
+
+    match cursor.sort {
+        SortKey::Ticker => ticker_after,
+        SortKey::LatestUpdate => {
+            let same_update = match cursor.latest_update {
+                Some(update) => Bson::Int64(update),
+                None => Bson::Null,
+            };
+            let later_update = match (cursor.latest_update, order) {
+                (Some(update), _) => doc! { "latest-update": { after: update } },
+                (None, SortOrder::Asc) => doc! { "latest-update": { "$ne": Bson::Null } },
+                // Nothing sorts below null
+                (None, SortOrder::Desc) => doc! { "$expr": false },
+            };
+            doc! { "$or": [
+                later_update,
+                { "latest-update": same_update, "ticker": { after: cursor.ticker.as_str() } },
+            ] }
+        }
+    }
+}
+
+fn list_filter(query: &ListQuery) -> Document {
+    let mut clauses: Vec<Document> = Vec::new();
+
+    if let Some(prefix) = &query.ticker_prefix {
+        clauses.push(doc! { "ticker": { "$regex": format!("^{}", escape_regex(prefix)) } });
+    }
+    if query.year_from.is_some() || query.year_to.is_some() {
+        let mut range = Document::new();
+        if let Some(from) = query.year_from {
+            range.insert("$gte", from);
+        }
+        if let Some(to) = query.year_to {
+            range.insert("$lte", to);
+        }
+        clauses.push(doc! { "data": { "$elemMatch": { "year": range } } });
+    }
+    if let Some(year) = query.has_year {
+        clauses.push(doc! { "data.year": year });
+    }
+    if let Some(cursor) = &query.after {
+        clauses.push(after_cursor(cursor, query.order));
+    }
+
+    if clauses.is_empty() {
+        doc! {}
+    } else {
+        doc! { "$and": clauses }
+    }
+}
+
+fn list_options(query: &ListQuery) -> FindOptions {
+    let direction = match query.order {
+        SortOrder::Asc => 1,
+        SortOrder::Desc => -1,
+    };
+    let sort = match query.sort {
+        SortKey::Ticker => doc! { "ticker": direction },
+        SortKey::LatestUpdate => doc! { "latest-update": direction, "ticker": direction },
+    };
+    let projection = match query.projection.stored() {
+        Projection::Summary => Some(doc! { "data": 0, "quarters": 0, "ttm": 0 }),
+        _ => None,
+    };
+
+    FindOptions::builder()
+        .sort(sort)
+        .projection(projection)
+        // One extra document tells us whether there is a next page
+        .limit(query.limit.map(|limit| limit as i64 + 1))
+        .build()
+}
+
+fn is_duplicate_key(err: &mongodb::error::Error) -> bool {
+    matches!(
+        err.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(write_error)) if write_error.code == DUPLICATE_KEY_CODE
+    )
+}
+
+#[async_trait]
+impl ReportStore for MongoReportStore {
+    async fn get(&self, ticker: &str) -> Result<Option<AnnualStockReport>, StoreError> {
+        self.collection
+            .find_one(doc! { "ticker": ticker }, None)
+            .await
+            .map_err(backend_error)
+    }
+
+    async fn list(&self, query: &ListQuery) -> Result<ReportPage, StoreError> {
+        let mut cursor = self
+            .collection
+            .find(list_filter(query), list_options(query))
+            .await
+            .map_err(backend_error)?;
+
+        let mut reports = Vec::new();
+        while cursor.advance().await.map_err(backend_error)? {
+            match cursor.deserialize_current() {
+                Ok(report) => reports.push(report),
+                Err(err) => error!("Skipping a report that failed to deserialize: {}", err),
+            }
+        }
+        Ok(ReportPage::from_overfetch(reports, query))
+    }
+
+    async fn create(&self, report: &AnnualStockReport, revision: &Revision) -> Result<(), StoreError> {
+        let number = self.insert_revision(revision).await?;
+        let result = match self.collection.insert_one(report, None).await {
+            Ok(_) => Ok(()),
+            Err(err) if is_duplicate_key(&err) => Err(StoreError::AlreadyExists(report.ticker.clone())),
+            Err(err) => Err(backend_error(err)),
+        };
+        if result.is_err() {
+            self.withdraw_revision(&report.ticker, number).await;
+        }
+        result
+    }
+
+    async fn replace(&self, report: &AnnualStockReport, expected_version: i32, revision: &Revision) -> Result<(), StoreError> {
+        let number = self.insert_revision(revision).await?;
+        let filter = doc! { "ticker": report.ticker.as_str(), "version": expected_version };
+        let result = match self.collection.replace_one(filter, report, None).await {
+            Ok(result) if result.matched_count == 1 => Ok(()),
+            Ok(_) => Err(StoreError::VersionConflict {
+                ticker: report.ticker.clone(),
+                expected_version,
+            }),
+            Err(err) => Err(backend_error(err)),
+        };
+        if result.is_err() {
+            self.withdraw_revision(&report.ticker, number).await;
+        }
+        result
+    }
+
+    async fn delete(&self, ticker: &str, revision: &Revision) -> Result<Option<AnnualStockReport>, StoreError> {
+        let number = self.insert_revision(revision).await?;
+        let result = self
+            .collection
+            .find_one_and_delete(doc! { "ticker": ticker }, None)
+            .await
+            .map_err(backend_error);
+        if !matches!(result, Ok(Some(_))) {
+            self.withdraw_revision(ticker, number).await;
+        }
+        result
+    }
+
+    async fn list_revisions(&self, ticker: &str) -> Result<Vec<Revision>, StoreError> {
+        let options = FindOptions::builder().sort(doc! { "revision": 1 }).build();
+        let mut cursor = self
+            .revisions
+            .find(doc! { "ticker": ticker }, options)
+            .await
+            .map_err(backend_error)?;
+
+        let mut revisions = Vec::new();
+        while cursor.advance().await.map_err(backend_error)? {
+            revisions.push(cursor.deserialize_current().map_err(backend_error)?);
+        }
+        Ok(revisions)
+    }
+
+    async fn get_revision(&self, ticker: &str, revision: i32) -> Result<Option<Revision>, StoreError> {
+        self.revisions
+            .find_one(doc! { "ticker": ticker, "revision": revision }, None)
+            .await
+            .map_err(backend_error)
+    }
+
+    async fn list_custom_metrics(&self) -> Result<Vec<CustomMetric>, StoreError> {
+        let options = FindOptions::builder().sort(doc! { "id": 1 }).build();
+        let mut cursor = self
+            .custom_metrics
+            .find(doc! {}, options)
+            .await
+            .map_err(backend_error)?;
+
+        let mut metrics = Vec::new();
+        while cursor.advance().await.map_err(backend_error)? {
+            metrics.push(cursor.deserialize_current().map_err(backend_error)?);
+        }
+        Ok(metrics)
+    }
+
+    async fn put_custom_metric(&self, metric: &CustomMetric) -> Result<(), StoreError> {
+        let options = ReplaceOptions::builder().upsert(true).build();
+        self.custom_metrics
+            .replace_one(doc! { "id": metric.id.as_str() }, metric, options)
+            .await
+            .map(|_| ())
+            .map_err(backend_error)
+    }
+
+    async fn delete_custom_metric(&self, id: &str) -> Result<Option<CustomMetric>, StoreError> {
+        self.custom_metrics
+            .find_one_and_delete(doc! { "id": id }, None)
+            .await
+            .map_err(backend_error)
+    }
+}
@@ -1,3 +1,573 @@
+//! Registry of the metrics computed for every period from its statements.
+//!
+//! Statement figures, which a client may report or override, are settled by
+//! the statements themselves. Everything priced or compared across the
+//! statements is a registered `Metric`: it names the figures it reads, and the
+//! registry evaluates metrics in dependency order, so a metric may build on
+//! another. Adding a ratio means adding one entry to `builtin_metrics`.
 
-mod report_model;
-use report_model::StockReport;
\ No newline at end of file
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use crate::formula::{Expr, FormulaError, History};
+use crate::metric_math::{finite, growth_opt, safe_div};
+use crate::report_model::{AnnualStockReport, BalanceSheet, CashFlowStatement, FinancialRatios, IncomeStatement, Report};
+
+/// What a metric's value measures; conversions between currencies and unit
+/// scales depend on it.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Unit {
+    /// Money, in the report's currency and unit scale.
+    Amount,
+    /// Money per share, in plain currency units.
+    PerShare,
+    /// Unit-free: ratios, multiples, yields and rates.
+    Ratio,
+}
+
+/// Where a metric shows up in a response.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Placement {
+    /// Has a field of its own in `financial-ratios` (and in the trailing
+    /// ratios where it applies).
+    Ratios,
+    /// Listed under `metrics`, keyed by its id.
+    Metrics,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Metric {
+    /// Kebab-case, like the statement fields.
+    pub id: &'static str,
+    /// Statement fields and other metrics `compute` reads.
+    pub inputs: &'static [&'static str],
+    pub unit: Unit,
+    /// Accumulates over the period, so it is annualized when compared with a
+    /// period of a different length.
+    pub flow: bool,
+    /// How the value is derived, for people auditing it.
+    pub formula: &'static str,
+    pub placement: Placement,
+    /// Returns `None` when the metric is undefined, e.g. a zero denominator or
+    /// a missing optional line item.
+    #[serde(skip)]
+    pub compute: fn(&Values) -> Option<f64>,
+}
+
+/// The named figures of one period: every numeric statement field by its
+/// JSON name, the average share price and, once evaluated, every metric.
+/// Missing and undefined figures are simply absent.
+#[derive(Debug, Clone, Default)]
+pub struct Values(HashMap<String, f64>);
+
+impl Values {
+    pub fn of_period(
+        in_state: &IncomeStatement,
+        bl_sheet: &BalanceSheet,
+        cfs: &CashFlowStatement,
+        avg_share_price: f64,
+    ) -> Values {
+        let mut values = Values::default();
+        values.flatten(in_state);
+        values.flatten(bl_sheet);
+        values.flatten(cfs);
+        values.set("avg-share-price", Some(avg_share_price));
+        values
+    }
+
+    /// Everything known about a year: its statements, every registry metric
+    /// and its financial ratios, dividend growth rates included.
+    pub fn of_year(report: &Report) -> Values {
+        let mut values = Values::of_period(
+            &report.income_statement,
+            &report.balance_sheet,
+            &report.cash_flow_statement,
+            report.financial_ratios.avg_share_price,
+        );
+        registry().evaluate(&mut values);
+        values.flatten(&report.financial_ratios);
+        values
+    }
+
+    /// Whether `of_year` can hold a figure called `id`.
+    pub fn is_known(id: &str) -> bool {
+        static FIELDS: OnceLock<BTreeSet<String>> = OnceLock::new();
+        let fields = FIELDS.get_or_init(|| {
+            [
+                serde_json::to_value(IncomeStatement::default()),
+                serde_json::to_value(BalanceSheet::default()),
+                serde_json::to_value(CashFlowStatement::default()),
+                serde_json::to_value(FinancialRatios::default()),
+            ]
+            .into_iter()
+            .filter_map(|statement| match statement {
+                Ok(serde_json::Value::Object(fields)) => Some(fields.into_iter().map(|(name, _)| name)),
+                _ => None,
+            })
+            .flatten()
+            .filter(|name| name != "provenance")
+            .collect()
+        });
+        fields.contains(id) || registry().get(id).is_some()
+    }
+
+    /// Adds every numeric field of `item` under its JSON name.
+    fn flatten<T: Serialize>(&mut self, item: &T) {
+        if let Ok(serde_json::Value::Object(fields)) = serde_json::to_value(item) {
+            for (name, value) in fields {
+                self.set(&name, value.as_f64());
+            }
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<f64> {
+        self.0.get(id).copied()
+    }
+
+    fn set(&mut self, id: &str, value: Option<f64>) {
+        match value.and_then(finite) {
+            Some(value) => {
+                self.0.insert(id.to_string(), value);
+            }
+            None => {
+                self.0.remove(id);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RegistryError {
+    DuplicateMetric(String),
+    /// These metrics depend on each other in a loop.
+    Cycle(Vec<String>),
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryError::DuplicateMetric(id) => write!(f, "Metric {} is registered twice", id),
+            RegistryError::Cycle(ids) => write!(f, "Metrics {} depend on each other", ids.join(", ")),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+pub struct Registry {
+    /// In evaluation order: every metric comes after the metrics it reads.
+    metrics: Vec<Metric>,
+    by_id: HashMap<&'static str, usize>,
+}
+
+impl Registry {
+    pub fn new(metrics: Vec<Metric>) -> Result<Registry, RegistryError> {
+        let mut ids = HashMap::new();
+        for metric in metrics.iter() {
+            if ids.insert(metric.id, ()).is_some() {
+                return Err(RegistryError::DuplicateMetric(metric.id.to_string()));
+            }
+        }
+
+        // Repeatedly take the first metric whose metric inputs are all placed,
+        // so independent metrics keep their registration order
+        let mut pending = metrics;
+        let mut ordered: Vec<Metric> = Vec::with_capacity(pending.len());
+        while !pending.is_empty() {
+            let ready = pending.iter().position(|metric| {
+                metric
+                    .inputs
+                    .iter()
+                    .filter(|input| ids.contains_key(*input))
+                    .all(|input| ordered.iter().any(|placed| placed.id == *input))
+            });
+            match ready {
+                Some(idx) => ordered.push(pending.remove(idx)),
+                None => {
+                    return Err(RegistryError::Cycle(
+                        pending.iter().map(|metric| metric.id.to_string()).collect(),
+                    ))
+                }
+            }
+        }
+
+        let by_id = ordered.iter().enumerate().map(|(idx, metric)| (metric.id, idx)).collect();
+        Ok(Registry {
+            metrics: ordered,
+            by_id,
+        })
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Metric> {
+        self.by_id.get(id).map(|idx| &self.metrics[*idx])
+    }
+
+    /// Every metric, in evaluation order.
+    pub fn metrics(&self) -> &[Metric] {
+        &self.metrics
+    }
+
+    /// Adds every metric to `values`.
+    pub fn evaluate(&self, values: &mut Values) {
+        for metric in self.metrics.iter() {
+            let value = (metric.compute)(values);
+            values.set(metric.id, value);
+        }
+    }
+
+    /// The evaluated metrics that are published under `metrics`.
+    pub fn listed(&self, values: &Values) -> BTreeMap<String, Option<f64>> {
+        self.metrics
+            .iter()
+            .filter(|metric| metric.placement == Placement::Metrics)
+            .map(|metric| (metric.id.to_string(), values.get(metric.id)))
+            .collect()
+    }
+
+    /// Change of each listed metric against the base period. `length_ratio`
+    /// scales flow metrics to the length of the base period.
+    pub fn yoy(
+        &self,
+        current: &BTreeMap<String, Option<f64>>,
+        base: &BTreeMap<String, Option<f64>>,
+        length_ratio: f64,
+    ) -> BTreeMap<String, Option<f64>> {
+        current
+            .iter()
+            .map(|(id, value)| {
+                let flow = self.get(id).is_some_and(|metric| metric.flow);
+                let value = if flow { value.map(|value| value * length_ratio) } else { *value };
+                (id.clone(), growth_opt(value, base.get(id).copied().flatten()))
+            })
+            .collect()
+    }
+}
+
+/// A metric a client defined as a formula over the figures of a year; see
+/// `formula` for the language.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct CustomMetric {
+    pub id: String,
+    pub formula: String,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum CustomMetricError {
+    InvalidId(String),
+    /// The id is already a statement field or a built-in metric.
+    TakenId(String),
+    InvalidFormula { id: String, err: FormulaError },
+    UnknownField { id: String, field: String },
+}
+
+impl fmt::Display for CustomMetricError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CustomMetricError::InvalidId(id) => {
+                write!(f, "{:?} is not a valid metric id; use lower-case words joined by dashes", id)
+            }
+            CustomMetricError::TakenId(id) => write!(f, "{} is already a built-in figure", id),
+            CustomMetricError::InvalidFormula { id, err } => write!(f, "Formula of {}: {}", id, err),
+            CustomMetricError::UnknownField { id, field } if field.contains('-') => write!(
+                f,
+                "Formula of {} reads unknown figure {}; put spaces around - to subtract",
+                id, field
+            ),
+            CustomMetricError::UnknownField { id, field } => {
+                write!(f, "Formula of {} reads unknown figure {}", id, field)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CustomMetricError {}
+
+impl CustomMetric {
+    /// Checks the id and parses the formula, which may read any statement
+    /// field, financial ratio or built-in metric but no other custom metric.
+    pub fn parse(&self) -> Result<Expr, CustomMetricError> {
+        let kebab = self.id.split('-').all(|word| {
+            !word.is_empty() && word.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+        });
+        if !kebab || !self.id.starts_with(|c: char| c.is_ascii_lowercase()) {
+            return Err(CustomMetricError::InvalidId(self.id.clone()));
+        }
+        if Values::is_known(&self.id) {
+            return Err(CustomMetricError::TakenId(self.id.clone()));
+        }
+
+        let expr = Expr::parse(&self.formula).map_err(|err| CustomMetricError::InvalidFormula {
+            id: self.id.clone(),
+            err,
+        })?;
+        if let Some(field) = expr.fields().into_iter().find(|field| !Values::is_known(field)) {
+            return Err(CustomMetricError::UnknownField {
+                id: self.id.clone(),
+                field: field.to_string(),
+            });
+        }
+        Ok(expr)
+    }
+}
+
+/// The figures of every year of `report`, by year.
+pub fn history(report: &AnnualStockReport) -> History {
+    report.data.iter().map(|year| (year.year, Values::of_year(year))).collect()
+}
+
+/// Fills `custom-metrics` of every year. Prior-year references read the
+/// report's other years as they are, so evaluate after any adjustment or
+/// conversion.
+pub fn evaluate_custom(report: &mut AnnualStockReport, metrics: &[CustomMetric]) {
+    if metrics.is_empty() {
+        return;
+    }
+    // Definitions were checked when they were stored
+    let formulas: Vec<(&str, Expr)> = metrics
+        .iter()
+        .filter_map(|metric| Some((metric.id.as_str(), metric.parse().ok()?)))
+        .collect();
+    let history = history(report);
+
+    for year in report.data.iter_mut() {
+        year.custom_metrics = formulas
+            .iter()
+            .map(|(id, expr)| (id.to_string(), expr.evaluate(&history, year.year)))
+            .collect();
+    }
+}
+
+/// The metrics every period is evaluated with.
+pub fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(|| Registry::new(builtin_metrics()).expect("built-in metrics must not form a cycle"))
+}
+
+fn ratio(
+    id: &'static str,
+    inputs: &'static [&'static str],
+    formula: &'static str,
+    placement: Placement,
+    compute: fn(&Values) -> Option<f64>,
+) -> Metric {
+    Metric {
+        id,
+        inputs,
+        unit: Unit::Ratio,
+        flow: false,
+        formula,
+        placement,
+        compute,
+    }
+}
+
+fn builtin_metrics() -> Vec<Metric> {
+    use Placement::{Metrics, Ratios};
+
+    vec![
+        Metric {
+            id: "market-cap",
+            inputs: &["avg-share-price", "shares-outstanding-basic"],
+            unit: Unit::Amount,
+            flow: false,
+            formula: "avg-share-price * shares-outstanding-basic",
+            placement: Metrics,
+            compute: |v| Some(v.get("avg-share-price")? * v.get("shares-outstanding-basic")?),
+        },
+        Metric {
+            id: "net-buybacks",
+            inputs: &["share-repurchases", "share-issuance"],
+            unit: Unit::Amount,
+            flow: true,
+            // Empty when repurchases aren't reported
+            formula: "|share-repurchases| - |share-issuance|",
+            placement: Metrics,
+            compute: |v| Some(v.get("share-repurchases")?.abs() - v.get("share-issuance").map_or(0.0, f64::abs)),
+        },
+        Metric {
+            id: "cash-returned",
+            inputs: &["dividends-paid", "net-buybacks"],
+            unit: Unit::Amount,
+            flow: true,
+            formula: "|dividends-paid| + net-buybacks",
+            placement: Metrics,
+            compute: |v| Some(v.get("dividends-paid")?.abs() + v.get("net-buybacks")?),
+        },
+        Metric {
+            id: "enterprise-value",
+            inputs: &["market-cap", "net-debt"],
+            unit: Unit::Amount,
+            flow: false,
+            formula: "market-cap + net-debt",
+            placement: Metrics,
+            compute: |v| Some(v.get("market-cap")? + v.get("net-debt")?),
+        },
+        ratio(
+            "avg-yield",
+            &["dividends-per-share", "avg-share-price"],
+            "dividends-per-share / avg-share-price",
+            Ratios,
+            |v| safe_div(v.get("dividends-per-share")?, v.get("avg-share-price")?),
+        ),
+        ratio(
+            "eps-payout-ratio",
+            &["dividends-per-share", "eps-basic"],
+            "dividends-per-share / eps-basic",
+            Ratios,
+            |v| safe_div(v.get("dividends-per-share")?, v.get("eps-basic")?),
+        ),
+        ratio(
+            "fcf-payout-ratio",
+            &["dividends-per-share", "fcf-per-share"],
+            "dividends-per-share / fcf-per-share",
+            Ratios,
+            |v| safe_div(v.get("dividends-per-share")?, v.get("fcf-per-share")?),
+        ),
+        ratio(
+            "buyback-yield",
+            &["net-buybacks", "market-cap"],
+            "net-buybacks / market-cap",
+            Ratios,
+            |v| safe_div(v.get("net-buybacks")?, v.get("market-cap")?),
+        ),
+        ratio(
+            "net-payout-yield",
+            &["cash-returned", "market-cap"],
+            "cash-returned / market-cap",
+            Ratios,
+            |v| safe_div(v.get("cash-returned")?, v.get("market-cap")?),
+        ),
+        ratio(
+            "cash-returned-to-fcf",
+            &["cash-returned", "free-cash-flow"],
+            "cash-returned / free-cash-flow",
+            Ratios,
+            |v| safe_div(v.get("cash-returned")?, v.get("free-cash-flow")?),
+        ),
+        ratio(
+            "pe-ratio",
+            &["avg-share-price", "eps-basic"],
+            "avg-share-price / eps-basic",
+            Ratios,
+            |v| safe_div(v.get("avg-share-price")?, v.get("eps-basic")?),
+        ),
+        ratio(
+            "earnings-yield",
+            &["eps-basic", "avg-share-price"],
+            "eps-basic / avg-share-price",
+            Metrics,
+            |v| safe_div(v.get("eps-basic")?, v.get("avg-share-price")?),
+        ),
+        ratio(
+            "return-on-equity",
+            &["net-income", "total-equity"],
+            "net-income / total-equity",
+            Ratios,
+            |v| safe_div(v.get("net-income")?, v.get("total-equity")?),
+        ),
+        ratio(
+            "return-on-assets",
+            &["net-income", "total-assets"],
+            "net-income / total-assets",
+            Metrics,
+            |v| safe_div(v.get("net-income")?, v.get("total-assets")?),
+        ),
+        ratio(
+            "asset-turnover",
+            &["revenue", "total-assets"],
+            "revenue / total-assets",
+            Metrics,
+            |v| safe_div(v.get("revenue")?, v.get("total-assets")?),
+        ),
+        ratio(
+            "price-to-ebit",
+            &["avg-share-price", "operating-income"],
+            "avg-share-price / operating-income",
+            Ratios,
+            |v| safe_div(v.get("avg-share-price")?, v.get("operating-income")?),
+        ),
+        ratio(
+            "price-to-opcf",
+            &["avg-share-price", "operating-cash-flow"],
+            "avg-share-price / operating-cash-flow",
+            Ratios,
+            |v| safe_div(v.get("avg-share-price")?, v.get("operating-cash-flow")?),
+        ),
+        ratio(
+            "price-to-fcf",
+            &["avg-share-price", "free-cash-flow"],
+            "avg-share-price / free-cash-flow",
+            Ratios,
+            |v| safe_div(v.get("avg-share-price")?, v.get("free-cash-flow")?),
+        ),
+        ratio(
+            "fcf-yield",
+            &["fcf-per-share", "avg-share-price"],
+            "fcf-per-share / avg-share-price",
+            Ratios,
+            |v| safe_div(v.get("fcf-per-share")?, v.get("avg-share-price")?),
+        ),
+        ratio(
+            "ev-to-ebitda",
+            &["enterprise-value", "ebitda"],
+            "enterprise-value / ebitda",
+            Metrics,
+            |v| safe_div(v.get("enterprise-value")?, v.get("ebitda")?),
+        ),
+        ratio(
+            "interest-coverage",
+            &["ebit", "interest-expense"],
+            "ebit / |interest-expense|",
+            Metrics,
+            |v| safe_div(v.get("ebit")?, v.get("interest-expense")?.abs()),
+        ),
+        ratio(
+            "debt-to-equity",
+            &["total-debt", "total-equity"],
+            "total-debt / total-equity",
+            Metrics,
+            |v| safe_div(v.get("total-debt")?, v.get("total-equity")?),
+        ),
+        ratio(
+            "cash-conversion",
+            &["operating-cash-flow", "net-income"],
+            "operating-cash-flow / net-income",
+            Metrics,
+            |v| safe_div(v.get("operating-cash-flow")?, v.get("net-income")?),
+        ),
+        Metric {
+            id: "book-value-per-share",
+            inputs: &["total-equity", "shares-outstanding-basic"],
+            unit: Unit::PerShare,
+            flow: false,
+            formula: "total-equity / shares-outstanding-basic",
+            placement: Ratios,
+            compute: |v| safe_div(v.get("total-equity")?, v.get("shares-outstanding-basic")?),
+        },
+        Metric {
+            id: "tangible-book-value-per-share",
+            inputs: &["tangible-book-value", "shares-outstanding-basic"],
+            unit: Unit::PerShare,
+            flow: false,
+            formula: "tangible-book-value / shares-outstanding-basic",
+            placement: Ratios,
+            compute: |v| safe_div(v.get("tangible-book-value")?, v.get("shares-outstanding-basic")?),
+        },
+        ratio(
+            "price-to-book",
+            &["avg-share-price", "book-value-per-share"],
+            "avg-share-price / book-value-per-share",
+            Ratios,
+            |v| safe_div(v.get("avg-share-price")?, v.get("book-value-per-share")?),
+        ),
+    ]
+}
@@ -0,0 +1,292 @@
+//! Compound annual growth of any figure over several look-back windows, the
+//! way `dgrN` measures the dividend.
+//!
+//! A window of `n` years compares a year with the fiscal year `n` before it,
+//! which has to be on file. The rate is `(end / base)^(1 / years) - 1`, where
+//! `years` is the time between the two period ends when both are dated. It
+//! is only defined for a positive base and an end of zero or more; otherwise
+//! the result says why it is empty instead of giving a misleading number.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::formula::History;
+use crate::metric_math::cagr;
+use crate::report_computer::{self, Values};
+use crate::report_model::{AnnualStockReport, FiscalPeriod};
+
+pub const DEFAULT_WINDOWS: [u32; 6] = [1, 3, 5, 10, 15, 20];
+
+pub const DEFAULT_METRICS: [&str; 6] = [
+    "revenue",
+    "eps-basic",
+    "fcf-per-share",
+    "book-value-per-share",
+    "shares-outstanding-basic",
+    "dividends-per-share",
+];
+
+/// Longest window accepted, in years.
+const MAX_WINDOW: u32 = 100;
+
+/// Why a window has no growth rate.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Undefined {
+    /// There is no report for the base year.
+    MissingBaseYear,
+    /// The figure is empty in this year or in the base year.
+    MissingValue,
+    /// Growth from nothing has no rate, e.g. a dividend that started.
+    ZeroBase,
+    /// A loss or a negative balance at the start.
+    NegativeBase,
+    /// A loss or a negative balance at the end, after a positive start.
+    NegativeEnd,
+}
+
+impl fmt::Display for Undefined {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            Undefined::MissingBaseYear => "there is no report for the base year",
+            Undefined::MissingValue => "the figure is empty at one end",
+            Undefined::ZeroBase => "the figure starts at zero",
+            Undefined::NegativeBase => "the figure starts negative",
+            Undefined::NegativeEnd => "the figure ends negative",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct WindowGrowth {
+    pub years: u32,
+    pub base_year: Option<i32>,
+    /// Years between the two periods actually used in the rate.
+    pub elapsed: Option<f64>,
+    pub value: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub undefined: Option<Undefined>,
+}
+
+#[derive(Debug)]
+pub enum GrowthError {
+    InvalidWindow(String),
+    UnknownMetric(String),
+}
+
+impl fmt::Display for GrowthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GrowthError::InvalidWindow(window) => {
+                write!(f, "CAGR window {:?} must be a whole number of years between 1 and {}", window, MAX_WINDOW)
+            }
+            GrowthError::UnknownMetric(metric) => write!(f, "There is no figure or metric called {}", metric),
+        }
+    }
+}
+
+impl std::error::Error for GrowthError {}
+
+/// Which figures get a CAGR and over which windows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrowthSettings {
+    pub windows: Vec<u32>,
+    pub metrics: Vec<String>,
+}
+
+impl Default for GrowthSettings {
+    fn default() -> GrowthSettings {
+        GrowthSettings {
+            windows: DEFAULT_WINDOWS.to_vec(),
+            metrics: DEFAULT_METRICS.iter().map(|metric| metric.to_string()).collect(),
+        }
+    }
+}
+
+impl GrowthSettings {
+    /// Checks both lists, then sorts the windows and drops repeats.
+    pub fn new(windows: Vec<u32>, metrics: Vec<String>) -> Result<GrowthSettings, GrowthError> {
+        if let Some(window) = windows.iter().find(|window| !(1..=MAX_WINDOW).contains(*window)) {
+            return Err(GrowthError::InvalidWindow(window.to_string()));
+        }
+        if let Some(metric) = metrics.iter().find(|metric| !Values::is_known(metric)) {
+            return Err(GrowthError::UnknownMetric(metric.clone()));
+        }
+
+        let mut windows = windows;
+        windows.sort_unstable();
+        windows.dedup();
+        let mut unique = Vec::with_capacity(metrics.len());
+        for metric in metrics {
+            if !unique.contains(&metric) {
+                unique.push(metric);
+            }
+        }
+        Ok(GrowthSettings { windows, metrics: unique })
+    }
+
+    /// Parses comma-separated lists as given on a query string; a list left
+    /// out keeps the setting of `self`.
+    pub fn with_lists(&self, windows: Option<&str>, metrics: Option<&str>) -> Result<GrowthSettings, GrowthError> {
+        let items = |list: &str| -> Vec<String> {
+            list.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| item.to_ascii_lowercase().replace('_', "-"))
+                .collect()
+        };
+        let windows = match windows {
+            Some(list) => items(list)
+                .into_iter()
+                .map(|window| window.parse().map_err(|_| GrowthError::InvalidWindow(window)))
+                .collect::<Result<Vec<u32>, GrowthError>>()?,
+            None => self.windows.clone(),
+        };
+        let metrics = metrics.map_or_else(|| self.metrics.clone(), items);
+        GrowthSettings::new(windows, metrics)
+    }
+}
+
+/// Years from the end of `base` to the end of `current`: the true span when
+/// both periods are dated, which differs from the year count after a 53-week
+/// year or a change of year end, and the difference of the fiscal years otherwise.
+pub fn elapsed_years(current: (i32, &FiscalPeriod), base: (i32, &FiscalPeriod)) -> f64 {
+    current
+        .1
+        .years_since(base.1)
+        .unwrap_or((current.0 - base.0) as f64)
+}
+
+/// Compound annual growth from `base` to `current` over `years`, or why there is none.
+pub fn compound(current: Option<f64>, base: Option<f64>, years: f64) -> Result<f64, Undefined> {
+    let (current, base) = current.zip(base).ok_or(Undefined::MissingValue)?;
+    if base == 0.0 {
+        Err(Undefined::ZeroBase)
+    } else if base < 0.0 {
+        Err(Undefined::NegativeBase)
+    } else if current < 0.0 {
+        Err(Undefined::NegativeEnd)
+    } else {
+        cagr(current, base, years).ok_or(Undefined::MissingValue)
+    }
+}
+
+/// Growth of `metric` in `year` over each window of `settings`.
+fn windows_of(
+    report: &AnnualStockReport,
+    history: &History,
+    year: (i32, &FiscalPeriod),
+    metric: &str,
+    settings: &GrowthSettings,
+) -> Vec<WindowGrowth> {
+    let current = history.get(&year.0).and_then(|values| values.get(metric));
+    settings
+        .windows
+        .iter()
+        .map(|window| {
+            let Some(base) = report.get_year(year.0 - *window as i32) else {
+                return WindowGrowth {
+                    years: *window,
+                    base_year: None,
+                    elapsed: None,
+                    value: None,
+                    undefined: Some(Undefined::MissingBaseYear),
+                };
+            };
+            let elapsed = elapsed_years(year, (base.year, &base.fiscal_period));
+            let base_value = history.get(&base.year).and_then(|values| values.get(metric));
+            let rate = compound(current, base_value, elapsed);
+            WindowGrowth {
+                years: *window,
+                base_year: Some(base.year),
+                elapsed: Some(elapsed),
+                value: rate.ok(),
+                undefined: rate.err(),
+            }
+        })
+        .collect()
+}
+
+/// Fills `cagr` of every year with the growth of each configured figure.
+/// Runs on the report as presented, so per-share figures are split-adjusted.
+pub fn evaluate(report: &mut AnnualStockReport, settings: &GrowthSettings) {
+    if settings.windows.is_empty() || settings.metrics.is_empty() {
+        return;
+    }
+    let history = report_computer::history(report);
+    let growth: Vec<BTreeMap<String, Vec<WindowGrowth>>> = report
+        .data
+        .iter()
+        .map(|year| {
+            settings
+                .metrics
+                .iter()
+                .map(|metric| {
+                    let windows = windows_of(report, &history, (year.year, &year.fiscal_period), metric, settings);
+                    (metric.clone(), windows)
+                })
+                .collect()
+        })
+        .collect();
+    for (year, growth) in report.data.iter_mut().zip(growth) {
+        year.cagr = growth;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report_model::tests::sample_report;
+
+    #[test]
+    fn compound_says_why_it_is_undefined() {
+        assert!((compound(Some(121.0), Some(100.0), 2.0).unwrap() - 0.1).abs() < 1e-12);
+        assert_eq!(compound(Some(0.0), Some(100.0), 1.0), Ok(-1.0));
+        assert_eq!(compound(None, Some(100.0), 1.0), Err(Undefined::MissingValue));
+        assert_eq!(compound(Some(1.0), Some(0.0), 1.0), Err(Undefined::ZeroBase));
+        assert_eq!(compound(Some(1.0), Some(-1.0), 1.0), Err(Undefined::NegativeBase));
+        assert_eq!(compound(Some(-1.0), Some(1.0), 1.0), Err(Undefined::NegativeEnd));
+    }
+
+    #[test]
+    fn elapsed_years_prefer_the_period_dates() {
+        let period = |end: &str| FiscalPeriod {
+            end: Some(end.parse().unwrap()),
+            ..FiscalPeriod::default()
+        };
+        let (current, base) = (period("2012-12-29"), period("2011-12-31"));
+        assert!((elapsed_years((2012, &current), (2011, &base)) - 364.0 / 365.25).abs() < 1e-12);
+        let undated = FiscalPeriod::default();
+        assert_eq!(elapsed_years((2012, &undated), (2009, &undated)), 3.0);
+    }
+
+    #[test]
+    fn settings_are_checked_and_deduplicated() {
+        let settings = GrowthSettings::new(vec![5, 1, 5], vec![String::from("revenue"), String::from("revenue")]).unwrap();
+        assert_eq!(settings.windows, [1, 5]);
+        assert_eq!(settings.metrics, ["revenue"]);
+
+        assert!(GrowthSettings::new(vec![0], vec![]).is_err());
+        assert!(GrowthSettings::new(vec![1], vec![String::from("no-such-figure")]).is_err());
+
+        let overridden = settings.with_lists(Some("3"), Some("EPS_BASIC")).unwrap();
+        assert_eq!(overridden.windows, [3]);
+        assert_eq!(overridden.metrics, ["eps-basic"]);
+        assert!(settings.with_lists(Some("x"), None).is_err());
+    }
+
+    #[test]
+    fn fills_every_window_of_every_year() {
+        let mut report = sample_report();
+        let settings = GrowthSettings::new(vec![1, 2, 3], vec![String::from("revenue")]).unwrap();
+        evaluate(&mut report, &settings);
+
+        let windows = &report.data[2].cagr["revenue"];
+        assert_eq!(windows[0].base_year, Some(2011));
+        assert!((windows[1].value.unwrap() - ((65492.0_f64 / 57838.0).sqrt() - 1.0)).abs() < 1e-12);
+        assert_eq!(windows[2].undefined, Some(Undefined::MissingBaseYear));
+    }
+}
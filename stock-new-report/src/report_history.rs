@@ -0,0 +1,164 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::report_model::AnnualStockReport;
+
+/// One entry in a ticker's edit history. `report` is the full document as it
+/// looked right after the change, or `None` when the change removed the ticker.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Revision {
+    pub ticker: String,
+    pub revision: i32,
+    pub timestamp: i64,
+    pub description: String,
+    pub report: Option<AnnualStockReport>,
+}
+
+/// What the revision list returns: everything but the snapshot itself.
+#[derive(Debug, Serialize)]
+pub struct RevisionSummary {
+    pub revision: i32,
+    pub timestamp: i64,
+    pub description: String,
+    pub version: Option<i32>,
+    pub deleted: bool,
+}
+
+impl From<&Revision> for RevisionSummary {
+    fn from(revision: &Revision) -> RevisionSummary {
+        RevisionSummary {
+            revision: revision.revision,
+            timestamp: revision.timestamp,
+            description: revision.description.clone(),
+            version: revision.report.as_ref().map(|report| report.version),
+            deleted: revision.report.is_none(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FieldChange {
+    pub path: String,
+    pub from: Value,
+    pub to: Value,
+}
+
+/// A revision for `ticker`, to be handed to the store together with the change
+/// it records. The store assigns the number.
+pub fn revision(ticker: &str, report: Option<&AnnualStockReport>, description: String) -> Revision {
+    Revision {
+        ticker: ticker.to_string(),
+        // Assigned by the store
+        revision: 0,
+        timestamp: Utc::now().timestamp(),
+        description,
+        report: report.cloned(),
+    }
+}
+
+/// Field-by-field differences between two snapshots. Yearly reports are
+/// matched by `year` and quarterly ones by year and quarter rather than by
+/// position, so inserting a year in the middle shows up as one added year and
+/// not as every later year changing.
+pub fn diff(from: Option<&AnnualStockReport>, to: Option<&AnnualStockReport>) -> Vec<FieldChange> {
+    let from = from.map_or(Value::Null, |report| serde_json::to_value(report).unwrap_or(Value::Null));
+    let to = to.map_or(Value::Null, |report| serde_json::to_value(report).unwrap_or(Value::Null));
+
+    let mut changes = Vec::new();
+    diff_values(String::new(), &from, &to, &mut changes);
+    changes
+}
+
+fn join(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", prefix, key)
+    }
+}
+
+/// The year of a yearly report, or the year and quarter of a quarterly one.
+fn period_of(value: &Value) -> Option<(i64, Option<i64>)> {
+    let year = value.get("year").and_then(Value::as_i64)?;
+    Some((year, value.get("quarter").and_then(Value::as_i64)))
+}
+
+fn period_label((year, quarter): (i64, Option<i64>)) -> String {
+    match quarter {
+        Some(quarter) => format!("{} Q{}", year, quarter),
+        None => year.to_string(),
+    }
+}
+
+fn diff_values(path: String, from: &Value, to: &Value, changes: &mut Vec<FieldChange>) {
+    match (from, to) {
+        (Value::Object(from_map), Value::Object(to_map)) => {
+            for (key, from_value) in from_map.iter() {
+                let to_value = to_map.get(key).unwrap_or(&Value::Null);
+                diff_values(join(&path, key), from_value, to_value, changes);
+            }
+            for (key, to_value) in to_map.iter() {
+                if !from_map.contains_key(key) {
+                    diff_values(join(&path, key), &Value::Null, to_value, changes);
+                }
+            }
+        }
+        (Value::Array(from_items), Value::Array(to_items))
+            if from_items.iter().chain(to_items.iter()).all(|item| period_of(item).is_some()) =>
+        {
+            let mut periods: Vec<(i64, Option<i64>)> =
+                from_items.iter().chain(to_items.iter()).filter_map(period_of).collect();
+            periods.sort_unstable();
+            periods.dedup();
+
+            for period in periods {
+                let from_item = from_items.iter().find(|item| period_of(item) == Some(period)).unwrap_or(&Value::Null);
+                let to_item = to_items.iter().find(|item| period_of(item) == Some(period)).unwrap_or(&Value::Null);
+                diff_values(format!("{}[{}]", path, period_label(period)), from_item, to_item, changes);
+            }
+        }
+        _ => {
+            if from != to {
+                changes.push(FieldChange {
+                    path,
+                    from: from.clone(),
+                    to: to.clone(),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report_model::tests::{sample_report, with_quarters};
+
+    fn paths(changes: &[FieldChange]) -> Vec<&str> {
+        changes.iter().map(|change| change.path.as_str()).collect()
+    }
+
+    #[test]
+    fn matches_years_not_positions() {
+        let before = sample_report();
+        let mut after = before.clone();
+        after.data.remove(1);
+        after.data[1].income_statement.net_income += 1.0;
+
+        let changes = diff(Some(&before), Some(&after));
+        assert!(paths(&changes).iter().all(|path| path.starts_with("data[2011]") || path.starts_with("data[2012]")));
+        assert!(paths(&changes).contains(&"data[2011]"));
+        assert!(paths(&changes).contains(&"data[2012].income-statement.net-income"));
+    }
+
+    #[test]
+    fn matches_quarters_by_year_and_quarter() {
+        let before = with_quarters();
+        let mut after = before.clone();
+        after.quarters[2].income_statement.revenue += 1.0;
+
+        let changes = diff(Some(&before), Some(&after));
+        assert_eq!(paths(&changes), ["quarters[2012 Q3].income-statement.revenue"]);
+    }
+}
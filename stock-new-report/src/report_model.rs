@@ -1,47 +1,269 @@
-use chrono::Utc;
+use chrono::{NaiveDate, Utc};
+use log::warn;
 use serde::{Deserialize, Serialize};
 
 use actix_web::web;
 use bson::doc;
-use chrono;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::fmt;
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::corporate_actions::{self, CorporateAction};
+use crate::currency::{normalize_currency, UnitScale};
+use crate::metric_math::{growth, growth_opt, safe_div, safe_div_opt};
+use crate::report_computer::{self, Values};
+use crate::report_growth::{self, WindowGrowth};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AnnualStockReport {
     #[serde(rename = "latest-update")]
     pub latest_update: Option<i64>,
     pub ticker: String,
     pub version: i32, // Add the numeric version field
+    /// ISO 4217 code every amount and per-share figure is in, e.g. `USD`.
+    /// Empty for reports stored before it was recorded.
+    #[serde(default)]
+    pub currency: Option<String>,
+    #[serde(rename = "unit-scale", default)]
+    pub unit_scale: Option<UnitScale>,
+    /// Splits and the like, oldest first. Per-share figures in `data` and
+    /// `quarters` are stored as reported and adjusted for these when read.
+    #[serde(rename = "corporate-actions", default)]
+    pub corporate_actions: Vec<CorporateAction>,
+    #[serde(default)] // listings may project the years away
     pub data: Vec<Report>,
+    #[serde(default)]
+    pub quarters: Vec<QuarterlyReport>,
+    /// Computed from the last four quarters; never taken from the client.
+    #[serde(default, skip_deserializing)]
+    pub ttm: Option<Box<TrailingTwelveMonths>>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Report {
     #[serde(rename = "year")]
     pub year: i32,
+
+    #[serde(rename = "fiscal-period", default)]
+    pub fiscal_period: FiscalPeriod,
+
+    /// The year the `*-yoy` blocks were computed against, always `year - 1`.
+    /// Empty when that year is missing, in which case so are the YoY blocks.
+    #[serde(rename = "yoy-base-year")]
+    pub yoy_base_year: Option<i32>,
+
+    /// Set when this year and the base year differ in length, e.g. a 53-week
+    /// year after a 52-week one. Flow items in the YoY blocks are then
+    /// compared per week rather than as reported.
+    #[serde(rename = "yoy-annualized", default)]
+    pub yoy_annualized: bool,
+
     #[serde(rename = "income-statement")]
     pub income_statement: IncomeStatement,
 
     #[serde(rename = "income-statement-yoy")]
-    pub income_statement_yoy: Option<IncomeStatement>,
+    pub income_statement_yoy: Option<IncomeStatementYoy>,
 
     #[serde(rename = "balance-sheet")]
     pub balance_sheet: BalanceSheet,
 
     #[serde(rename = "balance-sheet-yoy")]
-    pub balance_sheet_yoy: Option<BalanceSheet>,
+    pub balance_sheet_yoy: Option<BalanceSheetYoy>,
 
     #[serde(rename = "cash-flow-statement")]
     pub cash_flow_statement: CashFlowStatement,
 
     #[serde(rename = "cash-flow-statement-yoy")]
-    pub cash_flow_statement_yoy: Option<CashFlowStatement>,
+    pub cash_flow_statement_yoy: Option<CashFlowStatementYoy>,
 
     #[serde(rename = "financial-ratios")]
     pub financial_ratios: FinancialRatios,
+
+    /// Custom metrics, by id. Evaluated whenever the report is read, as the
+    /// definitions may change at any time; never taken from the client.
+    #[serde(rename = "custom-metrics", default, skip_deserializing)]
+    pub custom_metrics: BTreeMap<String, Option<f64>>,
+
+    /// Compound annual growth of the configured figures over each configured
+    /// window, by figure. Evaluated when read, like `custom-metrics`.
+    #[serde(default, skip_deserializing)]
+    pub cagr: BTreeMap<String, Vec<WindowGrowth>>,
+
+    /// Registry metrics without a field in `financial-ratios`, by id.
+    #[serde(default)]
+    pub metrics: BTreeMap<String, Option<f64>>,
+
+    /// Change of each of `metrics` against the base year, and of the
+    /// per-share book values and price-to-book in `financial-ratios`.
+    #[serde(rename = "metrics-yoy", default)]
+    pub metrics_yoy: Option<BTreeMap<String, Option<f64>>>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+/// When a period ran and when it was filed. All of it is optional; a period
+/// without dates is taken to be a regular fiscal year or quarter.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct FiscalPeriod {
+    pub start: Option<NaiveDate>,
+    pub end: Option<NaiveDate>,
+    /// Whole weeks, e.g. 53 for a 53-week year. Derived from the dates when not given.
+    pub weeks: Option<u32>,
+    pub filing_date: Option<NaiveDate>,
+}
+
+/// Where a derived figure came from.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Provenance {
+    /// Taken from the filing as sent by the client.
+    Reported,
+    /// Filled in from the other figures of the statement.
+    Computed,
+    /// Deliberately set by the user to something other than the formula gives.
+    /// Clients ask for this by sending `overridden` for the field.
+    Overridden,
+}
+
+/// Provenance of each derived field of a statement, keyed by its JSON name.
+pub type ProvenanceMap = BTreeMap<String, Provenance>;
+
+/// A figure a statement fills in itself when the filing doesn't give it.
+/// The `compute_optional_if_required` of each statement implements these.
+pub struct DerivedFigure {
+    /// Kebab-case, like the statement fields.
+    pub id: &'static str,
+    /// The statement fields `formula` reads.
+    pub inputs: &'static [&'static str],
+    /// How the value is derived, for people auditing it.
+    pub formula: &'static str,
+    /// Accumulates over the period, so it is annualized when compared with a
+    /// period of a different length.
+    pub flow: bool,
+}
+
+const fn derived(id: &'static str, inputs: &'static [&'static str], formula: &'static str, flow: bool) -> DerivedFigure {
+    DerivedFigure { id, inputs, formula, flow }
+}
+
+pub const DERIVED_FIGURES: &[DerivedFigure] = &[
+    // Income statement
+    derived("gross-profit", &["revenue", "total-cogs"], "revenue - total-cogs", true),
+    derived("operating-income", &["gross-profit", "operating-expense"], "gross-profit - operating-expense", true),
+    derived("gross-profit-margin", &["gross-profit", "revenue"], "gross-profit / revenue", false),
+    derived("operating-profit-margin", &["operating-income", "revenue"], "operating-income / revenue", false),
+    derived("net-profit-margin", &["net-income", "revenue"], "net-income / revenue", false),
+    derived(
+        "ebit",
+        &["pretax-income", "interest-expense", "operating-income"],
+        "pretax-income + |interest-expense|, or operating-income without a pretax figure",
+        true,
+    ),
+    derived("ebitda", &["ebit", "depreciation-and-amortization"], "ebit + depreciation-and-amortization", true),
+    derived("effective-tax-rate", &["income-tax", "pretax-income"], "income-tax / pretax-income", false),
+    derived("rd-intensity", &["research-and-development", "revenue"], "research-and-development / revenue", false),
+    derived(
+        "dilution",
+        &["shares-outstanding-diluted", "shares-outstanding-basic"],
+        "shares-outstanding-diluted / shares-outstanding-basic - 1",
+        false,
+    ),
+    // Balance sheet
+    derived("total-debt", &["short-term-debt", "long-term-debt"], "short-term-debt + long-term-debt", false),
+    derived("total-equity", &["total-assets", "total-liabilities"], "total-assets - total-liabilities", false),
+    derived("debt-to-capital", &["total-debt", "total-equity"], "total-debt / (total-debt + total-equity)", false),
+    derived("current-ratio", &["current-assets", "current-liabilities"], "current-assets / current-liabilities", false),
+    derived(
+        "quick-ratio",
+        &["current-assets", "inventory", "current-liabilities"],
+        "(current-assets - inventory) / current-liabilities",
+        false,
+    ),
+    derived("net-debt", &["total-debt", "cash-and-equivalents"], "total-debt - cash-and-equivalents", false),
+    derived(
+        "tangible-book-value",
+        &["total-equity", "goodwill", "intangibles"],
+        "total-equity - goodwill - intangibles",
+        false,
+    ),
+    // Cash flow statement
+    derived(
+        "free-cash-flow",
+        &["operating-cash-flow", "capital-expenditure"],
+        "operating-cash-flow - capital-expenditure",
+        true,
+    ),
+    derived(
+        "fcf-per-share",
+        &["free-cash-flow", "shares-outstanding-basic"],
+        "free-cash-flow / shares-outstanding-basic",
+        true,
+    ),
+];
+
+pub fn derived_figure(id: &str) -> Option<&'static DerivedFigure> {
+    DERIVED_FIGURES.iter().find(|figure| figure.id == id)
+}
+
+/// Whether an income or cash-flow statement item is annualized when compared
+/// across periods of different length. Every reported item of those two is a
+/// flow except the share counts; a derived one says so itself.
+pub fn is_flow(field: &str) -> bool {
+    match derived_figure(field) {
+        Some(figure) => figure.flow,
+        None => !matches!(field, "shares-outstanding-basic" | "shares-outstanding-diluted"),
+    }
+}
+
+/// Settles one derived figure. A value sent by the client always wins over
+/// the formula; only a missing value, or one that was itself computed earlier,
+/// is replaced by `computed`.
+fn settle(value: &mut Option<f64>, provenance: &mut ProvenanceMap, field: &str, computed: Option<f64>) -> Option<f64> {
+    let source = match (*value, provenance.get(field)) {
+        (None, _) | (Some(_), Some(Provenance::Computed)) => Provenance::Computed,
+        (Some(_), Some(Provenance::Overridden)) => Provenance::Overridden,
+        (Some(_), _) => Provenance::Reported,
+    };
+    if source == Provenance::Computed {
+        *value = computed;
+    }
+    provenance.insert(field.to_string(), source);
+    *value
+}
+
+/// Relative difference within which a stored figure counts as a formula's result.
+const LEGACY_MATCH: f64 = 1e-9;
+
+/// Provenance of a figure stored before provenance was recorded: computed
+/// when it equals what one of the formulas of the time gave, reported otherwise.
+fn legacy_source(value: Option<f64>, formulas: &[Option<f64>]) -> Option<Provenance> {
+    let value = value?;
+    let matches = |formula: &Option<f64>| {
+        formula.is_some_and(|formula| (value - formula).abs() <= LEGACY_MATCH * value.abs().max(formula.abs()).max(1.0))
+    };
+    if formulas.iter().any(matches) {
+        Some(Provenance::Computed)
+    } else {
+        Some(Provenance::Reported)
+    }
+}
+
+/// Records `sources` for a statement stored without any provenance.
+fn adopt_legacy(provenance: &mut ProvenanceMap, sources: &[(&str, Option<Provenance>)]) {
+    for (field, source) in sources {
+        if let Some(source) = source {
+            provenance.insert(field.to_string(), *source);
+        }
+    }
+}
+
+/// A derived figure as the client sent it, or `None` if it is to be computed.
+pub fn supplied(value: Option<f64>, provenance: &ProvenanceMap, field: &str) -> Option<f64> {
+    match provenance.get(field) {
+        Some(Provenance::Computed) => None,
+        _ => value,
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct IncomeStatement {
     pub revenue: f64,
 
@@ -77,9 +299,49 @@ pub struct IncomeStatement {
 
     #[serde(rename = "shares-outstanding-basic")]
     pub shares_outstanding_basic: f64,
+
+    // Line items not every filing breaks out
+    #[serde(rename = "research-and-development")]
+    pub research_and_development: Option<f64>,
+
+    #[serde(rename = "selling-general-administrative")]
+    pub selling_general_administrative: Option<f64>,
+
+    #[serde(rename = "depreciation-and-amortization")]
+    pub depreciation_and_amortization: Option<f64>,
+
+    #[serde(rename = "pretax-income")]
+    pub pretax_income: Option<f64>,
+
+    #[serde(rename = "income-tax")]
+    pub income_tax: Option<f64>,
+
+    #[serde(rename = "stock-based-compensation")]
+    pub stock_based_compensation: Option<f64>,
+
+    #[serde(rename = "eps-diluted")]
+    pub eps_diluted: Option<f64>,
+
+    #[serde(rename = "shares-outstanding-diluted")]
+    pub shares_outstanding_diluted: Option<f64>,
+
+    pub ebit: Option<f64>, // pretax_income + |interest_expense|, or operating_income without a pretax figure
+
+    pub ebitda: Option<f64>, // ebit + depreciation_and_amortization
+
+    #[serde(rename = "effective-tax-rate")]
+    pub effective_tax_rate: Option<f64>, // income_tax/pretax_income
+
+    #[serde(rename = "rd-intensity")]
+    pub rd_intensity: Option<f64>, // research_and_development/revenue
+
+    pub dilution: Option<f64>, // shares_outstanding_diluted/shares_outstanding_basic - 1
+
+    #[serde(default)]
+    pub provenance: ProvenanceMap,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct BalanceSheet {
     #[serde(rename = "cash-and-equivalents")]
     pub cash_and_equivalents: f64,
@@ -98,9 +360,34 @@ pub struct BalanceSheet {
     pub total_equity: Option<f64>,
     #[serde(rename = "debt-to-capital")]
     pub debt_to_capital: Option<f64>,
+
+    // Line items not every filing breaks out
+    #[serde(rename = "current-assets")]
+    pub current_assets: Option<f64>,
+    #[serde(rename = "current-liabilities")]
+    pub current_liabilities: Option<f64>,
+    pub inventory: Option<f64>,
+    pub receivables: Option<f64>,
+    pub payables: Option<f64>,
+    pub goodwill: Option<f64>,
+    pub intangibles: Option<f64>,
+    #[serde(rename = "retained-earnings")]
+    pub retained_earnings: Option<f64>,
+
+    #[serde(rename = "current-ratio")]
+    pub current_ratio: Option<f64>, // current_assets/current_liabilities
+    #[serde(rename = "quick-ratio")]
+    pub quick_ratio: Option<f64>, // (current_assets - inventory)/current_liabilities
+    #[serde(rename = "net-debt")]
+    pub net_debt: Option<f64>, // total_debt - cash_and_equivalents
+    #[serde(rename = "tangible-book-value")]
+    pub tangible_book_value: Option<f64>, // total_equity - goodwill - intangibles
+
+    #[serde(default)]
+    pub provenance: ProvenanceMap,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct CashFlowStatement {
     #[serde(rename = "operating-cash-flow")]
     pub operating_cash_flow: f64,
@@ -118,9 +405,146 @@ pub struct CashFlowStatement {
     pub free_cash_flow: Option<f64>,
     #[serde(rename = "fcf-per-share")]
     pub fcf_per_share: Option<f64>,
+
+    // Financing and investing detail not every filing breaks out. Amounts are
+    // taken by size, so outflows may be reported either positive or negative.
+    #[serde(rename = "share-repurchases")]
+    pub share_repurchases: Option<f64>,
+    #[serde(rename = "share-issuance")]
+    pub share_issuance: Option<f64>,
+    #[serde(rename = "debt-issued")]
+    pub debt_issued: Option<f64>,
+    #[serde(rename = "debt-repaid")]
+    pub debt_repaid: Option<f64>,
+    pub acquisitions: Option<f64>,
+
+    #[serde(default)]
+    pub provenance: ProvenanceMap,
+}
+
+/// Year-over-year change of each `IncomeStatement` field. A field is empty
+/// when the change is undefined, e.g. the previous year's value was zero.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct IncomeStatementYoy {
+    pub revenue: Option<f64>,
+    #[serde(rename = "total-cogs")]
+    pub total_cogs: Option<f64>,
+    #[serde(rename = "gross-profit")]
+    pub gross_profit: Option<f64>,
+    #[serde(rename = "gross-profit-margin")]
+    pub gross_profit_margin: Option<f64>,
+    #[serde(rename = "operating-expense")]
+    pub operating_expense: Option<f64>,
+    #[serde(rename = "operating-income")]
+    pub operating_income: Option<f64>,
+    #[serde(rename = "operating-profit-margin")]
+    pub operating_profit_margin: Option<f64>,
+    #[serde(rename = "interest-expense")]
+    pub interest_expense: Option<f64>,
+    #[serde(rename = "net-income")]
+    pub net_income: Option<f64>,
+    #[serde(rename = "net-profit-margin")]
+    pub net_profit_margin: Option<f64>,
+    #[serde(rename = "eps-basic")]
+    pub eps_basic: Option<f64>,
+    #[serde(rename = "shares-outstanding-basic")]
+    pub shares_outstanding_basic: Option<f64>,
+    #[serde(rename = "research-and-development")]
+    pub research_and_development: Option<f64>,
+    #[serde(rename = "selling-general-administrative")]
+    pub selling_general_administrative: Option<f64>,
+    #[serde(rename = "depreciation-and-amortization")]
+    pub depreciation_and_amortization: Option<f64>,
+    #[serde(rename = "pretax-income")]
+    pub pretax_income: Option<f64>,
+    #[serde(rename = "income-tax")]
+    pub income_tax: Option<f64>,
+    #[serde(rename = "stock-based-compensation")]
+    pub stock_based_compensation: Option<f64>,
+    #[serde(rename = "eps-diluted")]
+    pub eps_diluted: Option<f64>,
+    #[serde(rename = "shares-outstanding-diluted")]
+    pub shares_outstanding_diluted: Option<f64>,
+    pub ebit: Option<f64>,
+    pub ebitda: Option<f64>,
+    #[serde(rename = "effective-tax-rate")]
+    pub effective_tax_rate: Option<f64>,
+    #[serde(rename = "rd-intensity")]
+    pub rd_intensity: Option<f64>,
+    pub dilution: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct BalanceSheetYoy {
+    #[serde(rename = "cash-and-equivalents")]
+    pub cash_and_equivalents: Option<f64>,
+    #[serde(rename = "total-assets")]
+    pub total_assets: Option<f64>,
+    #[serde(rename = "short-term-debt")]
+    pub short_term_debt: Option<f64>,
+    #[serde(rename = "long-term-debt")]
+    pub long_term_debt: Option<f64>,
+    #[serde(rename = "total-liabilities")]
+    pub total_liabilities: Option<f64>,
+    #[serde(rename = "total-debt")]
+    pub total_debt: Option<f64>,
+    #[serde(rename = "total-equity")]
+    pub total_equity: Option<f64>,
+    #[serde(rename = "debt-to-capital")]
+    pub debt_to_capital: Option<f64>,
+    #[serde(rename = "current-assets")]
+    pub current_assets: Option<f64>,
+    #[serde(rename = "current-liabilities")]
+    pub current_liabilities: Option<f64>,
+    pub inventory: Option<f64>,
+    pub receivables: Option<f64>,
+    pub payables: Option<f64>,
+    pub goodwill: Option<f64>,
+    pub intangibles: Option<f64>,
+    #[serde(rename = "retained-earnings")]
+    pub retained_earnings: Option<f64>,
+    #[serde(rename = "current-ratio")]
+    pub current_ratio: Option<f64>,
+    #[serde(rename = "quick-ratio")]
+    pub quick_ratio: Option<f64>,
+    #[serde(rename = "net-debt")]
+    pub net_debt: Option<f64>,
+    #[serde(rename = "tangible-book-value")]
+    pub tangible_book_value: Option<f64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct CashFlowStatementYoy {
+    #[serde(rename = "operating-cash-flow")]
+    pub operating_cash_flow: Option<f64>,
+    #[serde(rename = "investing-cash-flow")]
+    pub investing_cash_flow: Option<f64>,
+    #[serde(rename = "capital-expenditure")]
+    pub capital_expenditure: Option<f64>,
+    #[serde(rename = "financing-cash-flow")]
+    pub financing_cash_flow: Option<f64>,
+    #[serde(rename = "dividends-paid")]
+    pub dividends_paid: Option<f64>,
+    #[serde(rename = "dividends-per-share")]
+    pub dividends_per_share: Option<f64>,
+    #[serde(rename = "free-cash-flow")]
+    pub free_cash_flow: Option<f64>,
+    #[serde(rename = "fcf-per-share")]
+    pub fcf_per_share: Option<f64>,
+    #[serde(rename = "share-repurchases")]
+    pub share_repurchases: Option<f64>,
+    #[serde(rename = "share-issuance")]
+    pub share_issuance: Option<f64>,
+    #[serde(rename = "debt-issued")]
+    pub debt_issued: Option<f64>,
+    #[serde(rename = "debt-repaid")]
+    pub debt_repaid: Option<f64>,
+    pub acquisitions: Option<f64>,
+}
+
+/// Market ratios are always computed; unlike the statements they take no
+/// reported values.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
 pub struct FinancialRatios {
     #[serde(rename = "avg-share-price")]
     pub avg_share_price: f64,
@@ -128,6 +552,7 @@ pub struct FinancialRatios {
     #[serde(rename = "avg-yield")]
     pub avg_yield: Option<f64>,
 
+    /// Change in dividends per share against the year before, e.g. 0.05 for +5%.
     #[serde(rename = "dividend-growth-rate")]
     pub dividend_growth_rate: Option<f64>,
 
@@ -137,6 +562,15 @@ pub struct FinancialRatios {
     #[serde(rename = "fcf-payout-ratio")]
     pub fcf_payout_ratio: Option<f64>,
 
+    #[serde(rename = "buyback-yield")]
+    pub buyback_yield: Option<f64>, // net buybacks / market cap
+
+    #[serde(rename = "net-payout-yield")]
+    pub net_payout_yield: Option<f64>, // (dividends + net buybacks) / market cap
+
+    #[serde(rename = "cash-returned-to-fcf")]
+    pub cash_returned_to_fcf: Option<f64>, // (dividends + net buybacks) / free cash flow
+
     #[serde(rename = "pe-ratio")]
     pub pe_ratio: Option<f64>,
 
@@ -155,34 +589,220 @@ pub struct FinancialRatios {
     #[serde(rename = "fcf-yield")]
     pub fcf_yield: Option<f64>,
 
+    #[serde(rename = "book-value-per-share")]
+    pub book_value_per_share: Option<f64>,
+
+    #[serde(rename = "tangible-book-value-per-share")]
+    pub tangible_book_value_per_share: Option<f64>,
+
+    #[serde(rename = "price-to-book")]
+    pub price_to_book: Option<f64>,
+
     pub dgr1: Option<f64>,
     pub dgr3: Option<f64>,
     pub dgr5: Option<f64>,
     pub dgr10: Option<f64>,
     pub dgr15: Option<f64>,
     pub dgr20: Option<f64>,
+
+    // The year each dgr was computed against, i.e. `year - N` when that year exists
+    #[serde(rename = "dgr1-base-year")]
+    pub dgr1_base_year: Option<i32>,
+    #[serde(rename = "dgr3-base-year")]
+    pub dgr3_base_year: Option<i32>,
+    #[serde(rename = "dgr5-base-year")]
+    pub dgr5_base_year: Option<i32>,
+    #[serde(rename = "dgr10-base-year")]
+    pub dgr10_base_year: Option<i32>,
+    #[serde(rename = "dgr15-base-year")]
+    pub dgr15_base_year: Option<i32>,
+    #[serde(rename = "dgr20-base-year")]
+    pub dgr20_base_year: Option<i32>,
+}
+
+/// One fiscal quarter. Quarters have no YoY blocks or dividend growth; they
+/// exist to keep the trailing-twelve-month figures current between filings.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct QuarterlyReport {
+    pub year: i32,
+
+    /// 1 to 4
+    pub quarter: u8,
+
+    #[serde(rename = "income-statement")]
+    pub income_statement: IncomeStatement,
+
+    #[serde(rename = "balance-sheet")]
+    pub balance_sheet: BalanceSheet,
+
+    #[serde(rename = "cash-flow-statement")]
+    pub cash_flow_statement: CashFlowStatement,
+
+    #[serde(rename = "avg-share-price")]
+    pub avg_share_price: f64,
+
+    #[serde(rename = "fiscal-period", default)]
+    pub fiscal_period: FiscalPeriod,
+}
+
+/// Flow items summed over the last four quarters and balance items as of the
+/// last of them. Every derived figure is recomputed from those totals.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TrailingTwelveMonths {
+    #[serde(rename = "end-year")]
+    pub end_year: i32,
+
+    #[serde(rename = "end-quarter")]
+    pub end_quarter: u8,
+
+    #[serde(rename = "income-statement")]
+    pub income_statement: IncomeStatement,
+
+    #[serde(rename = "balance-sheet")]
+    pub balance_sheet: BalanceSheet,
+
+    #[serde(rename = "cash-flow-statement")]
+    pub cash_flow_statement: CashFlowStatement,
+
+    #[serde(rename = "financial-ratios")]
+    pub financial_ratios: TrailingRatios,
+
+    #[serde(default)]
+    pub metrics: BTreeMap<String, Option<f64>>,
+}
+
+/// The market ratios that make sense on trailing figures, priced at the
+/// average share price of the latest quarter.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct TrailingRatios {
+    #[serde(rename = "avg-share-price")]
+    pub avg_share_price: f64,
+
+    #[serde(rename = "avg-yield")]
+    pub avg_yield: Option<f64>,
+
+    #[serde(rename = "eps-payout-ratio")]
+    pub eps_payout_ratio: Option<f64>,
+
+    #[serde(rename = "fcf-payout-ratio")]
+    pub fcf_payout_ratio: Option<f64>,
+
+    #[serde(rename = "buyback-yield")]
+    pub buyback_yield: Option<f64>, // net buybacks / market cap
+
+    #[serde(rename = "net-payout-yield")]
+    pub net_payout_yield: Option<f64>, // (dividends + net buybacks) / market cap
+
+    #[serde(rename = "cash-returned-to-fcf")]
+    pub cash_returned_to_fcf: Option<f64>, // (dividends + net buybacks) / free cash flow
+
+    #[serde(rename = "pe-ratio")]
+    pub pe_ratio: Option<f64>,
+
+    #[serde(rename = "fcf-yield")]
+    pub fcf_yield: Option<f64>,
+}
+
+/// What to do when a report arrives for a year that is already stored.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicateYearPolicy {
+    #[default]
+    Reject,
+    Replace,
+}
+
+#[derive(Debug)]
+pub enum ReportError {
+    DuplicateYear(i32),
+    DuplicateQuarter { year: i32, quarter: u8 },
+    InvalidQuarter(u8),
+    InvalidCurrency(String),
+    InvalidCorporateAction(String),
+}
+
+impl fmt::Display for ReportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReportError::DuplicateYear(year) => write!(f, "A report for {} already exists", year),
+            ReportError::DuplicateQuarter { year, quarter } => {
+                write!(f, "A report for {} Q{} already exists", year, quarter)
+            }
+            ReportError::InvalidQuarter(quarter) => write!(f, "Quarter {} is not between 1 and 4", quarter),
+            ReportError::InvalidCurrency(code) => write!(f, "{:?} is not a three-letter currency code", code),
+            ReportError::InvalidCorporateAction(reason) => write!(f, "Invalid corporate action: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ReportError {}
+
+/// Finds the report for exactly `year`. Reports are matched by year rather than
+/// by position so a missing year never gets silently replaced by an older one.
+fn report_for_year<'a>(prev_reports: &[&'a Report], year: i32) -> Option<&'a Report> {
+    prev_reports.iter().rev().find(|report| report.year == year).copied()
 }
 
 impl Report {
+    /// Statements stored before provenance was recorded carry every derived
+    /// figure but no provenance at all, which would make them all look
+    /// reported, including the ones early versions computed wrongly. Since
+    /// then a stored statement always has an entry for a figure it keeps.
+    fn adopt_legacy_provenance(&mut self) {
+        self.income_statement.adopt_legacy_provenance();
+        self.balance_sheet.adopt_legacy_provenance();
+        self.cash_flow_statement.adopt_legacy_provenance();
+    }
+
+    /// What `metrics-yoy` compares: the listed metrics plus the ratios that
+    /// track the balance sheet rather than the year's results.
+    fn yoy_metrics(&self) -> BTreeMap<String, Option<f64>> {
+        let ratios = &self.financial_ratios;
+        let book_values = [
+            ("book-value-per-share", ratios.book_value_per_share),
+            ("tangible-book-value-per-share", ratios.tangible_book_value_per_share),
+            ("price-to-book", ratios.price_to_book),
+        ];
+        let mut metrics = self.metrics.clone();
+        metrics.extend(book_values.map(|(id, value)| (id.to_string(), value)));
+        metrics
+    }
+
     fn compute_optional_if_required(&mut self, prev_reports: &[&Report]) {
+        self.fiscal_period.compute_optional_if_required();
         self.income_statement.compute_optional_if_required();
         self.balance_sheet.compute_optional_if_required();
         self.cash_flow_statement
             .compute_optional_if_required(&self.income_statement);
-        self.financial_ratios.compute_optional_if_required(
-            &self.cash_flow_statement,
+
+        let registry = report_computer::registry();
+        let mut values = Values::of_period(
             &self.income_statement,
             &self.balance_sheet,
-            &prev_reports,
-            self.year,
+            &self.cash_flow_statement,
+            self.financial_ratios.avg_share_price,
         );
+        registry.evaluate(&mut values);
+        self.financial_ratios.compute_optional_if_required(
+            &values,
+            &self.cash_flow_statement,
+            prev_reports,
+            (self.year, &self.fiscal_period),
+        );
+        self.metrics = registry.listed(&values);
 
-        if let Some(last) = prev_reports.last() {
+        let last_year = report_for_year(prev_reports, self.year - 1);
+        self.yoy_base_year = last_year.map(|last| last.year);
+
+        if let Some(last) = last_year {
+            let length_ratio = self.fiscal_period.length_ratio(&last.fiscal_period);
+            self.yoy_annualized = length_ratio.is_some();
             let _ = self
                 .income_statement_yoy
                 .insert(IncomeStatement::from_as_yoy(
                     &self.income_statement,
                     &last.income_statement,
+                    length_ratio.unwrap_or(1.0),
                 ));
             let _ = self.balance_sheet_yoy.insert(BalanceSheet::from_as_yoy(
                 &self.balance_sheet,
@@ -193,206 +813,654 @@ impl Report {
                 .insert(CashFlowStatement::from_as_yoy(
                     &self.cash_flow_statement,
                     &last.cash_flow_statement,
+                    length_ratio.unwrap_or(1.0),
                 ));
+            let _ = self
+                .metrics_yoy
+                .insert(registry.yoy(&self.yoy_metrics(), &last.yoy_metrics(), length_ratio.unwrap_or(1.0)));
+        } else {
+            // No report for the previous year, so nothing to compare against
+            self.yoy_annualized = false;
+            self.metrics_yoy = None;
+            self.income_statement_yoy = None;
+            self.balance_sheet_yoy = None;
+            self.cash_flow_statement_yoy = None;
         }
     }
 }
 
+impl FiscalPeriod {
+    fn compute_optional_if_required(&mut self) {
+        if self.weeks.is_none() {
+            self.weeks = self.weeks_between_dates();
+        }
+    }
+
+    /// Length implied by `start` and `end`, both inclusive, rounded to whole weeks.
+    pub fn weeks_between_dates(&self) -> Option<u32> {
+        let days = (self.end? - self.start?).num_days() + 1;
+        if days <= 0 {
+            return None;
+        }
+        Some((days as f64 / 7.0).round() as u32)
+    }
+
+    /// `base` weeks over this period's weeks, when both are known and differ.
+    /// Multiplying a flow by it scales it to the length of the base period.
+    pub fn length_ratio(&self, base: &FiscalPeriod) -> Option<f64> {
+        match (self.weeks, base.weeks) {
+            (Some(weeks), Some(base_weeks)) if weeks != base_weeks => safe_div(base_weeks as f64, weeks as f64),
+            _ => None,
+        }
+    }
+
+    /// Years from the end of `base` to the end of this period, if both are dated.
+    pub fn years_since(&self, base: &FiscalPeriod) -> Option<f64> {
+        let days = (self.end? - base.end?).num_days();
+        Some(days as f64 / 365.25)
+    }
+}
+
 impl IncomeStatement {
+    fn adopt_legacy_provenance(&mut self) {
+        if !self.provenance.is_empty() {
+            return;
+        }
+        let revenue = Some(self.revenue);
+        let gross_profit = self.revenue - self.total_cogs;
+        let operating_income = gross_profit - self.operating_expense;
+        adopt_legacy(
+            &mut self.provenance,
+            &[
+                // Always overwritten, never taken from the client
+                ("gross-profit", self.gross_profit.map(|_| Provenance::Computed)),
+                // The first version subtracted the cogs a second time
+                (
+                    "gross-profit-margin",
+                    legacy_source(
+                        self.gross_profit_margin,
+                        &[Some(gross_profit - self.total_cogs), safe_div(gross_profit, self.revenue)],
+                    ),
+                ),
+                ("operating-income", legacy_source(self.operating_income, &[Some(operating_income)])),
+                (
+                    "operating-profit-margin",
+                    legacy_source(
+                        self.operating_profit_margin,
+                        &[safe_div_opt(self.operating_income, revenue), safe_div(operating_income, self.revenue)],
+                    ),
+                ),
+                (
+                    "net-profit-margin",
+                    legacy_source(self.net_profit_margin, &[safe_div(self.net_income, self.revenue)]),
+                ),
+            ],
+        );
+    }
+
     fn compute_optional_if_required(&mut self) {
-        let _ = self.gross_profit.insert(self.revenue - self.total_cogs);
-
-        let _ = self
-            .gross_profit_margin
-            .get_or_insert(self.gross_profit.unwrap() - self.total_cogs);
-        let _ = self
-            .operating_income
-            .get_or_insert(self.gross_profit.unwrap() - self.operating_expense)
-            .clone();
-        let _ = self
-            .operating_profit_margin
-            .get_or_insert(self.operating_income.unwrap() / self.revenue);
-        let _ = self
-            .net_profit_margin
-            .get_or_insert(self.net_income / self.revenue);
-    }
-
-    fn from_as_yoy(current: &IncomeStatement, last: &IncomeStatement) -> IncomeStatement {
-        IncomeStatement {
-            revenue: current.revenue / last.revenue - 1.0,
-            total_cogs: current.total_cogs / last.total_cogs - 1.0,
-            gross_profit: Some(current.gross_profit.unwrap() / last.gross_profit.unwrap() - 1.0),
-            gross_profit_margin: Some(
-                current.gross_profit_margin.unwrap() / last.gross_profit_margin.unwrap() - 1.0,
+        let provenance = &mut self.provenance;
+        let gross_profit = settle(
+            &mut self.gross_profit,
+            provenance,
+            "gross-profit",
+            Some(self.revenue - self.total_cogs),
+        );
+        let operating_income = settle(
+            &mut self.operating_income,
+            provenance,
+            "operating-income",
+            gross_profit.map(|gross_profit| gross_profit - self.operating_expense),
+        );
+
+        // Margins stay empty for a year without revenue
+        let revenue = Some(self.revenue);
+        settle(
+            &mut self.gross_profit_margin,
+            provenance,
+            "gross-profit-margin",
+            safe_div_opt(gross_profit, revenue),
+        );
+        settle(
+            &mut self.operating_profit_margin,
+            provenance,
+            "operating-profit-margin",
+            safe_div_opt(operating_income, revenue),
+        );
+        settle(
+            &mut self.net_profit_margin,
+            provenance,
+            "net-profit-margin",
+            safe_div_opt(Some(self.net_income), revenue),
+        );
+
+        // Interest is added back whichever sign the filing gives it
+        let ebit = settle(
+            &mut self.ebit,
+            provenance,
+            "ebit",
+            match self.pretax_income {
+                Some(pretax_income) => Some(pretax_income + self.interest_expense.abs()),
+                None => operating_income,
+            },
+        );
+        settle(
+            &mut self.ebitda,
+            provenance,
+            "ebitda",
+            ebit.zip(self.depreciation_and_amortization).map(|(ebit, d_and_a)| ebit + d_and_a),
+        );
+        settle(
+            &mut self.effective_tax_rate,
+            provenance,
+            "effective-tax-rate",
+            safe_div_opt(self.income_tax, self.pretax_income),
+        );
+        settle(
+            &mut self.rd_intensity,
+            provenance,
+            "rd-intensity",
+            safe_div_opt(self.research_and_development, revenue),
+        );
+        settle(
+            &mut self.dilution,
+            provenance,
+            "dilution",
+            growth_opt(self.shares_outstanding_diluted, Some(self.shares_outstanding_basic)),
+        );
+    }
+
+    /// Sums quarterly flows. Shares outstanding are taken from the latest
+    /// quarter and EPS is the sum of the quarterly EPS, as filings report it.
+    fn trailing(quarters: Vec<&IncomeStatement>) -> IncomeStatement {
+        let sum = |field: fn(&IncomeStatement) -> f64| quarters.iter().map(|quarter| field(quarter)).sum::<f64>();
+        // Optional items only add up when every quarter has them
+        let sum_opt = |field: fn(&IncomeStatement) -> Option<f64>| {
+            quarters.iter().map(|quarter| field(quarter)).sum::<Option<f64>>()
+        };
+        let mut ttm = IncomeStatement {
+            revenue: sum(|s| s.revenue),
+            total_cogs: sum(|s| s.total_cogs),
+            gross_profit: None,
+            gross_profit_margin: None,
+            operating_expense: sum(|s| s.operating_expense),
+            operating_income: None,
+            operating_profit_margin: None,
+            interest_expense: sum(|s| s.interest_expense),
+            net_income: sum(|s| s.net_income),
+            net_profit_margin: None,
+            eps_basic: sum(|s| s.eps_basic),
+            shares_outstanding_basic: quarters.last().map_or(0.0, |s| s.shares_outstanding_basic),
+            research_and_development: sum_opt(|s| s.research_and_development),
+            selling_general_administrative: sum_opt(|s| s.selling_general_administrative),
+            depreciation_and_amortization: sum_opt(|s| s.depreciation_and_amortization),
+            pretax_income: sum_opt(|s| s.pretax_income),
+            income_tax: sum_opt(|s| s.income_tax),
+            stock_based_compensation: sum_opt(|s| s.stock_based_compensation),
+            eps_diluted: sum_opt(|s| s.eps_diluted),
+            shares_outstanding_diluted: quarters.last().and_then(|s| s.shares_outstanding_diluted),
+            ebit: None,
+            ebitda: None,
+            effective_tax_rate: None,
+            rd_intensity: None,
+            dilution: None,
+            provenance: ProvenanceMap::new(),
+        };
+        ttm.compute_optional_if_required();
+        ttm
+    }
+
+    /// `length_ratio` scales this year's flows to the length of the base year;
+    /// margins, rates and share counts don't depend on the length and are
+    /// compared as is. `is_flow` tells the two apart.
+    fn from_as_yoy(current: &IncomeStatement, last: &IncomeStatement, length_ratio: f64) -> IncomeStatementYoy {
+        let yoy_opt = |field: &str, current: Option<f64>, last: Option<f64>| {
+            let scale = if is_flow(field) { length_ratio } else { 1.0 };
+            growth_opt(current.map(|current| current * scale), last)
+        };
+        let yoy = |field: &str, current: f64, last: f64| yoy_opt(field, Some(current), Some(last));
+        IncomeStatementYoy {
+            revenue: yoy("revenue", current.revenue, last.revenue),
+            total_cogs: yoy("total-cogs", current.total_cogs, last.total_cogs),
+            gross_profit: yoy_opt("gross-profit", current.gross_profit, last.gross_profit),
+            gross_profit_margin: yoy_opt("gross-profit-margin", current.gross_profit_margin, last.gross_profit_margin),
+            operating_expense: yoy("operating-expense", current.operating_expense, last.operating_expense),
+            operating_income: yoy_opt("operating-income", current.operating_income, last.operating_income),
+            operating_profit_margin: yoy_opt(
+                "operating-profit-margin",
+                current.operating_profit_margin,
+                last.operating_profit_margin,
+            ),
+            interest_expense: yoy("interest-expense", current.interest_expense, last.interest_expense),
+            net_income: yoy("net-income", current.net_income, last.net_income),
+            net_profit_margin: yoy_opt("net-profit-margin", current.net_profit_margin, last.net_profit_margin),
+            eps_basic: yoy("eps-basic", current.eps_basic, last.eps_basic),
+            shares_outstanding_basic: yoy(
+                "shares-outstanding-basic",
+                current.shares_outstanding_basic,
+                last.shares_outstanding_basic,
             ),
-            operating_expense: current.operating_expense / last.operating_expense - 1.0,
-            operating_income: Some(
-                current.operating_income.unwrap() / last.operating_income.unwrap() - 1.0,
+            research_and_development: yoy_opt(
+                "research-and-development",
+                current.research_and_development,
+                last.research_and_development,
             ),
-            operating_profit_margin: Some(
-                current.operating_profit_margin.unwrap() / last.operating_profit_margin.unwrap()
-                    - 1.0,
+            selling_general_administrative: yoy_opt(
+                "selling-general-administrative",
+                current.selling_general_administrative,
+                last.selling_general_administrative,
             ),
-            interest_expense: current.interest_expense / last.interest_expense - 1.0,
-            net_income: current.net_income / last.net_income - 1.0,
-            net_profit_margin: Some(
-                current.net_profit_margin.unwrap() / last.net_profit_margin.unwrap() - 1.,
+            depreciation_and_amortization: yoy_opt(
+                "depreciation-and-amortization",
+                current.depreciation_and_amortization,
+                last.depreciation_and_amortization,
             ),
-            eps_basic: current.eps_basic / last.eps_basic - 1.0,
-            shares_outstanding_basic: current.shares_outstanding_basic
-                / last.shares_outstanding_basic
-                - 1.0,
+            pretax_income: yoy_opt("pretax-income", current.pretax_income, last.pretax_income),
+            income_tax: yoy_opt("income-tax", current.income_tax, last.income_tax),
+            stock_based_compensation: yoy_opt(
+                "stock-based-compensation",
+                current.stock_based_compensation,
+                last.stock_based_compensation,
+            ),
+            eps_diluted: yoy_opt("eps-diluted", current.eps_diluted, last.eps_diluted),
+            shares_outstanding_diluted: yoy_opt(
+                "shares-outstanding-diluted",
+                current.shares_outstanding_diluted,
+                last.shares_outstanding_diluted,
+            ),
+            ebit: yoy_opt("ebit", current.ebit, last.ebit),
+            ebitda: yoy_opt("ebitda", current.ebitda, last.ebitda),
+            effective_tax_rate: yoy_opt("effective-tax-rate", current.effective_tax_rate, last.effective_tax_rate),
+            rd_intensity: yoy_opt("rd-intensity", current.rd_intensity, last.rd_intensity),
+            dilution: yoy_opt("dilution", current.dilution, last.dilution),
         }
     }
 }
 
 impl BalanceSheet {
+    /// Every derived figure was always overwritten, so all of them are computed.
+    fn adopt_legacy_provenance(&mut self) {
+        if !self.provenance.is_empty() {
+            return;
+        }
+        let computed = |value: Option<f64>| value.map(|_| Provenance::Computed);
+        adopt_legacy(
+            &mut self.provenance,
+            &[
+                ("total-debt", computed(self.total_debt)),
+                ("total-equity", computed(self.total_equity)),
+                ("debt-to-capital", computed(self.debt_to_capital)),
+            ],
+        );
+    }
+
     fn compute_optional_if_required(&mut self) {
-        let _ = self
-            .total_debt
-            .insert(self.short_term_debt + self.long_term_debt);
+        let provenance = &mut self.provenance;
+        let total_debt = settle(
+            &mut self.total_debt,
+            provenance,
+            "total-debt",
+            Some(self.short_term_debt + self.long_term_debt),
+        );
+        let total_equity = settle(
+            &mut self.total_equity,
+            provenance,
+            "total-equity",
+            Some(self.total_assets - self.total_liabilities),
+        );
 
-        let _ = self
-            .total_equity
-            .insert(self.total_assets + self.total_liabilities)
-            .clone();
+        // Undefined for a company with neither debt nor equity
+        let capital = total_debt.zip(total_equity).map(|(debt, equity)| debt + equity);
+        settle(
+            &mut self.debt_to_capital,
+            provenance,
+            "debt-to-capital",
+            safe_div_opt(total_debt, capital),
+        );
 
-        let _ = self.debt_to_capital.insert(
-            self.total_debt.unwrap() / (self.total_debt.unwrap() + self.total_equity.unwrap()),
+        settle(
+            &mut self.current_ratio,
+            provenance,
+            "current-ratio",
+            safe_div_opt(self.current_assets, self.current_liabilities),
+        );
+        // Without an inventory figure the quick ratio would just repeat the current ratio
+        let quick_assets = self.current_assets.zip(self.inventory).map(|(assets, inventory)| assets - inventory);
+        settle(
+            &mut self.quick_ratio,
+            provenance,
+            "quick-ratio",
+            safe_div_opt(quick_assets, self.current_liabilities),
+        );
+        settle(
+            &mut self.net_debt,
+            provenance,
+            "net-debt",
+            total_debt.map(|debt| debt - self.cash_and_equivalents),
+        );
+        // A missing goodwill or intangibles figure counts as none, but with
+        // neither there is nothing to tell tangible book from book
+        let intangible_assets = match (self.goodwill, self.intangibles) {
+            (None, None) => None,
+            (goodwill, intangibles) => Some(goodwill.unwrap_or(0.0) + intangibles.unwrap_or(0.0)),
+        };
+        settle(
+            &mut self.tangible_book_value,
+            provenance,
+            "tangible-book-value",
+            total_equity.zip(intangible_assets).map(|(equity, intangible)| equity - intangible),
         );
     }
 
-    fn from_as_yoy(current: &BalanceSheet, last: &BalanceSheet) -> BalanceSheet {
-        BalanceSheet {
-            cash_and_equivalents: (current.cash_and_equivalents / last.cash_and_equivalents - 1.0),
-            total_assets: current.total_assets / last.total_assets - 1.0,
-            short_term_debt: current.short_term_debt / last.short_term_debt - 1.0,
-            long_term_debt: current.long_term_debt / last.long_term_debt - 1.0,
-            total_liabilities: current.total_liabilities / last.total_liabilities - 1.0,
-            total_debt: Some(current.total_debt.unwrap() / last.total_debt.unwrap() - 1.0),
-            total_equity: Some(current.total_equity.unwrap() / last.total_equity.unwrap() - 1.0),
-            debt_to_capital: Some(
-                current.debt_to_capital.unwrap() / last.debt_to_capital.unwrap() - 1.0,
-            ),
+    fn from_as_yoy(current: &BalanceSheet, last: &BalanceSheet) -> BalanceSheetYoy {
+        BalanceSheetYoy {
+            cash_and_equivalents: growth(current.cash_and_equivalents, last.cash_and_equivalents),
+            total_assets: growth(current.total_assets, last.total_assets),
+            short_term_debt: growth(current.short_term_debt, last.short_term_debt),
+            long_term_debt: growth(current.long_term_debt, last.long_term_debt),
+            total_liabilities: growth(current.total_liabilities, last.total_liabilities),
+            total_debt: growth_opt(current.total_debt, last.total_debt),
+            total_equity: growth_opt(current.total_equity, last.total_equity),
+            debt_to_capital: growth_opt(current.debt_to_capital, last.debt_to_capital),
+            current_assets: growth_opt(current.current_assets, last.current_assets),
+            current_liabilities: growth_opt(current.current_liabilities, last.current_liabilities),
+            inventory: growth_opt(current.inventory, last.inventory),
+            receivables: growth_opt(current.receivables, last.receivables),
+            payables: growth_opt(current.payables, last.payables),
+            goodwill: growth_opt(current.goodwill, last.goodwill),
+            intangibles: growth_opt(current.intangibles, last.intangibles),
+            retained_earnings: growth_opt(current.retained_earnings, last.retained_earnings),
+            current_ratio: growth_opt(current.current_ratio, last.current_ratio),
+            quick_ratio: growth_opt(current.quick_ratio, last.quick_ratio),
+            net_debt: growth_opt(current.net_debt, last.net_debt),
+            tangible_book_value: growth_opt(current.tangible_book_value, last.tangible_book_value),
         }
     }
 }
 
 impl CashFlowStatement {
+    /// Both derived figures were always overwritten, so both are computed.
+    fn adopt_legacy_provenance(&mut self) {
+        if !self.provenance.is_empty() {
+            return;
+        }
+        let computed = |value: Option<f64>| value.map(|_| Provenance::Computed);
+        adopt_legacy(
+            &mut self.provenance,
+            &[
+                ("free-cash-flow", computed(self.free_cash_flow)),
+                ("fcf-per-share", computed(self.fcf_per_share)),
+            ],
+        );
+    }
+
     fn compute_optional_if_required(&mut self, in_state: &IncomeStatement) {
-        let fcf: f64 = self
-            .free_cash_flow
-            .insert(self.operating_cash_flow - self.capital_expenditure)
-            .clone();
+        let provenance = &mut self.provenance;
+        let fcf = settle(
+            &mut self.free_cash_flow,
+            provenance,
+            "free-cash-flow",
+            Some(self.operating_cash_flow - self.capital_expenditure),
+        );
+        settle(
+            &mut self.fcf_per_share,
+            provenance,
+            "fcf-per-share",
+            safe_div_opt(fcf, Some(in_state.shares_outstanding_basic)),
+        );
+    }
 
-        let _ = self
-            .fcf_per_share
-            .insert(fcf / in_state.shares_outstanding_basic);
+    fn trailing(quarters: Vec<&CashFlowStatement>, in_state: &IncomeStatement) -> CashFlowStatement {
+        let sum = |field: fn(&CashFlowStatement) -> f64| quarters.iter().map(|quarter| field(quarter)).sum::<f64>();
+        let sum_opt = |field: fn(&CashFlowStatement) -> Option<f64>| {
+            quarters.iter().map(|quarter| field(quarter)).sum::<Option<f64>>()
+        };
+        let mut ttm = CashFlowStatement {
+            operating_cash_flow: sum(|s| s.operating_cash_flow),
+            investing_cash_flow: sum(|s| s.investing_cash_flow),
+            capital_expenditure: sum(|s| s.capital_expenditure),
+            financing_cash_flow: sum(|s| s.financing_cash_flow),
+            dividends_paid: sum(|s| s.dividends_paid),
+            dividends_per_share: sum(|s| s.dividends_per_share),
+            free_cash_flow: None,
+            fcf_per_share: None,
+            share_repurchases: sum_opt(|s| s.share_repurchases),
+            share_issuance: sum_opt(|s| s.share_issuance),
+            debt_issued: sum_opt(|s| s.debt_issued),
+            debt_repaid: sum_opt(|s| s.debt_repaid),
+            acquisitions: sum_opt(|s| s.acquisitions),
+            provenance: ProvenanceMap::new(),
+        };
+        ttm.compute_optional_if_required(in_state);
+        ttm
     }
 
-    fn from_as_yoy(current: &CashFlowStatement, last: &CashFlowStatement) -> CashFlowStatement {
-        CashFlowStatement {
-            operating_cash_flow: (current.operating_cash_flow / last.operating_cash_flow),
-            investing_cash_flow: (current.investing_cash_flow / last.investing_cash_flow),
-            capital_expenditure: (current.capital_expenditure / last.capital_expenditure),
-            financing_cash_flow: (current.financing_cash_flow / last.financing_cash_flow),
-            dividends_paid: (current.dividends_paid / last.dividends_paid),
-            dividends_per_share: (current.dividends_per_share / last.dividends_per_share),
-            free_cash_flow: (current.free_cash_flow.unwrap() / last.free_cash_flow.unwrap()).into(),
-            fcf_per_share: (current.fcf_per_share.unwrap() / last.fcf_per_share.unwrap()).into(),
+    /// Every cash flow item is a flow, so `is_flow` has all of them scaled by `length_ratio`.
+    fn from_as_yoy(current: &CashFlowStatement, last: &CashFlowStatement, length_ratio: f64) -> CashFlowStatementYoy {
+        let yoy_opt = |field: &str, current: Option<f64>, last: Option<f64>| {
+            let scale = if is_flow(field) { length_ratio } else { 1.0 };
+            growth_opt(current.map(|current| current * scale), last)
+        };
+        let yoy = |field: &str, current: f64, last: f64| yoy_opt(field, Some(current), Some(last));
+        CashFlowStatementYoy {
+            operating_cash_flow: yoy("operating-cash-flow", current.operating_cash_flow, last.operating_cash_flow),
+            investing_cash_flow: yoy("investing-cash-flow", current.investing_cash_flow, last.investing_cash_flow),
+            capital_expenditure: yoy("capital-expenditure", current.capital_expenditure, last.capital_expenditure),
+            financing_cash_flow: yoy("financing-cash-flow", current.financing_cash_flow, last.financing_cash_flow),
+            dividends_paid: yoy("dividends-paid", current.dividends_paid, last.dividends_paid),
+            dividends_per_share: yoy("dividends-per-share", current.dividends_per_share, last.dividends_per_share),
+            free_cash_flow: yoy_opt("free-cash-flow", current.free_cash_flow, last.free_cash_flow),
+            fcf_per_share: yoy_opt("fcf-per-share", current.fcf_per_share, last.fcf_per_share),
+            share_repurchases: yoy_opt("share-repurchases", current.share_repurchases, last.share_repurchases),
+            share_issuance: yoy_opt("share-issuance", current.share_issuance, last.share_issuance),
+            debt_issued: yoy_opt("debt-issued", current.debt_issued, last.debt_issued),
+            debt_repaid: yoy_opt("debt-repaid", current.debt_repaid, last.debt_repaid),
+            acquisitions: yoy_opt("acquisitions", current.acquisitions, last.acquisitions),
         }
     }
 }
 
 impl FinancialRatios {
+    /// The market ratios are taken from the registry evaluation in `values`;
+    /// only the dividend history below needs earlier years.
     fn compute_optional_if_required(
         &mut self,
+        values: &Values,
         current_cfs: &CashFlowStatement,
-        in_state: &IncomeStatement,
-        bl_sheet: &BalanceSheet,
         prev_reports: &[&Report],
-        current_report_year: i32,
+        (current_report_year, fiscal_period): (i32, &FiscalPeriod),
     ) {
-        let _ = self
-            .avg_yield
-            .insert(current_cfs.dividends_per_share / self.avg_share_price);
-
-        if let Some(last) = prev_reports.last() {
-            let _ = self.dividend_growth_rate.insert(
-                current_cfs.dividends_per_share / last.cash_flow_statement.dividends_per_share,
-            );
-        }
-
-        let _ = self
-            .eps_payout_ratio
-            .insert(current_cfs.dividends_per_share / in_state.eps_basic);
-
-        let _ = self
-            .fcf_payout_ratio
-            .insert(current_cfs.dividends_per_share / current_cfs.free_cash_flow.unwrap());
-        let _ = self
-            .pe_ratio
-            .insert(self.avg_share_price / in_state.eps_basic);
-        let _ = self
-            .return_on_equity
-            .insert(in_state.net_income / bl_sheet.total_equity.unwrap());
-        let _ = self
-            .price_to_ebit
-            .insert(self.avg_share_price / in_state.operating_income.unwrap());
-        let _ = self
-            .price_to_opcf
-            .insert(self.avg_share_price / current_cfs.operating_cash_flow);
-        let _ = self
-            .price_to_fcf
-            .insert(self.avg_share_price / current_cfs.free_cash_flow.unwrap());
-        let _ = self
-            .fcf_yield
-            .insert(current_cfs.fcf_per_share.unwrap() / self.avg_share_price);
-
-        println!("[{}] {}/{} {}", current_report_year, current_cfs.fcf_per_share.unwrap(), self.avg_share_price, self.fcf_yield.unwrap());
-
-        // Compute dgrs
-        let mut map: HashMap<usize, &mut Option<f64>> = HashMap::new();
-        map.insert(1, &mut self.dgr1);
-        map.insert(3, &mut self.dgr3);
-        map.insert(5, &mut self.dgr5);
-        map.insert(10, &mut self.dgr10);
-        map.insert(15, &mut self.dgr15);
-        map.insert(20, &mut self.dgr20);
-
-        for (key, dgr) in map.iter_mut() {
-            let years: usize = key.clone();
-            if prev_reports.len() >= years {
-                let idx = prev_reports.len() - years;
-                let old_report: &Report = prev_reports.get(idx).unwrap();
-
-                let old_dividend = old_report.cash_flow_statement.dividends_per_share;
-                let dgr_val = (current_cfs.dividends_per_share / old_dividend)
-                    .powf(1.0 / (years as f64))
-                    - 1.0;
-
-                // println!(
-                //     "{}/{} {}/{} dgr{}={}",
-                //     current_report_year,
-                //     old_report.year,
-                //     current_cfs.dividends_per_share,
-                //     old_report.cash_flow_statement.dividends_per_share,
-                //     years,
-                //     dgr_val
-                // );
-
-                let _ = dgr.insert(dgr_val);
+        self.avg_yield = values.get("avg-yield");
+        self.eps_payout_ratio = values.get("eps-payout-ratio");
+        self.fcf_payout_ratio = values.get("fcf-payout-ratio");
+        self.buyback_yield = values.get("buyback-yield");
+        self.net_payout_yield = values.get("net-payout-yield");
+        self.cash_returned_to_fcf = values.get("cash-returned-to-fcf");
+        self.pe_ratio = values.get("pe-ratio");
+        self.return_on_equity = values.get("return-on-equity");
+        self.price_to_ebit = values.get("price-to-ebit");
+        self.price_to_opcf = values.get("price-to-opcf");
+        self.price_to_fcf = values.get("price-to-fcf");
+        self.fcf_yield = values.get("fcf-yield");
+        self.book_value_per_share = values.get("book-value-per-share");
+        self.tangible_book_value_per_share = values.get("tangible-book-value-per-share");
+        self.price_to_book = values.get("price-to-book");
+
+        // Left empty rather than NaN or infinite when there was no dividend in the base year
+        let dividends_per_share = current_cfs.dividends_per_share;
+        self.dividend_growth_rate = report_for_year(prev_reports, current_report_year - 1)
+            .and_then(|last| growth(dividends_per_share, last.cash_flow_statement.dividends_per_share));
+
+        // The fixed dividend windows; `report_growth` does the same for any figure
+        let dgrs = [
+            (1, &mut self.dgr1, &mut self.dgr1_base_year),
+            (3, &mut self.dgr3, &mut self.dgr3_base_year),
+            (5, &mut self.dgr5, &mut self.dgr5_base_year),
+            (10, &mut self.dgr10, &mut self.dgr10_base_year),
+            (15, &mut self.dgr15, &mut self.dgr15_base_year),
+            (20, &mut self.dgr20, &mut self.dgr20_base_year),
+        ];
+        for (years, dgr, base_year) in dgrs {
+            let old_report = report_for_year(prev_reports, current_report_year - years);
+            // The base year is kept even when the rate itself is undefined,
+            // e.g. a dividend started within the window
+            *base_year = old_report.map(|old_report| old_report.year);
+            *dgr = old_report.and_then(|old_report| {
+                let elapsed = report_growth::elapsed_years(
+                    (current_report_year, fiscal_period),
+                    (old_report.year, &old_report.fiscal_period),
+                );
+                report_growth::compound(
+                    Some(dividends_per_share),
+                    Some(old_report.cash_flow_statement.dividends_per_share),
+                    elapsed,
+                )
+                .ok()
+            });
+        }
+    }
+}
+
+impl QuarterlyReport {
+    /// Sort key: `(year, quarter)`.
+    pub fn period(&self) -> (i32, u8) {
+        (self.year, self.quarter)
+    }
+
+    /// The quarter before this one, e.g. 2022 Q4 for 2023 Q1.
+    fn previous_period(&self) -> (i32, u8) {
+        match self.quarter {
+            1 => (self.year - 1, 4),
+            quarter => (self.year, quarter - 1),
+        }
+    }
+
+    fn compute_optional_if_required(&mut self) {
+        self.fiscal_period.compute_optional_if_required();
+        self.income_statement.compute_optional_if_required();
+        self.balance_sheet.compute_optional_if_required();
+        self.cash_flow_statement
+            .compute_optional_if_required(&self.income_statement);
+    }
+}
+
+impl TrailingTwelveMonths {
+    /// Needs the last four quarters to be consecutive; a gap would make the
+    /// sums cover less than a year, so there are no TTM figures until it is filled.
+    fn from_quarters(quarters: &[QuarterlyReport]) -> Option<TrailingTwelveMonths> {
+        let window = quarters.get(quarters.len().checked_sub(4)?..)?;
+        if window.windows(2).any(|pair| pair[1].previous_period() != pair[0].period()) {
+            return None;
+        }
+        let latest = &window[3];
+
+        let income_statement = IncomeStatement::trailing(window.iter().map(|q| &q.income_statement).collect());
+        let balance_sheet = latest.balance_sheet.clone();
+        let cash_flow_statement = CashFlowStatement::trailing(
+            window.iter().map(|q| &q.cash_flow_statement).collect(),
+            &income_statement,
+        );
+        let registry = report_computer::registry();
+        let mut values = Values::of_period(&income_statement, &balance_sheet, &cash_flow_statement, latest.avg_share_price);
+        registry.evaluate(&mut values);
+        let financial_ratios = TrailingRatios::compute(latest.avg_share_price, &values);
+
+        Some(TrailingTwelveMonths {
+            end_year: latest.year,
+            end_quarter: latest.quarter,
+            income_statement,
+            balance_sheet,
+            cash_flow_statement,
+            financial_ratios,
+            metrics: registry.listed(&values),
+        })
+    }
+}
+
+impl TrailingRatios {
+    fn compute(avg_share_price: f64, values: &Values) -> TrailingRatios {
+        TrailingRatios {
+            avg_share_price,
+            avg_yield: values.get("avg-yield"),
+            eps_payout_ratio: values.get("eps-payout-ratio"),
+            fcf_payout_ratio: values.get("fcf-payout-ratio"),
+            buyback_yield: values.get("buyback-yield"),
+            net_payout_yield: values.get("net-payout-yield"),
+            cash_returned_to_fcf: values.get("cash-returned-to-fcf"),
+            pe_ratio: values.get("pe-ratio"),
+            fcf_yield: values.get("fcf-yield"),
+        }
+    }
+}
+
+/// Sorts `items` by `key`, keeping their relative order otherwise, and drops
+/// all but the last of items with the same key. Returns the repeated keys.
+fn keep_last_per_key<T, K: Ord + Copy>(items: &mut Vec<T>, key: impl Fn(&T) -> K) -> Vec<K> {
+    items.sort_by_key(|item| key(item));
+    let mut kept: Vec<T> = Vec::with_capacity(items.len());
+    let mut repeated = Vec::new();
+    for item in items.drain(..) {
+        match kept.last_mut() {
+            Some(last) if key(last) == key(&item) => {
+                repeated.push(key(&item));
+                *last = item;
             }
+            _ => kept.push(item),
         }
     }
+    *items = kept;
+    repeated.dedup();
+    repeated
 }
 
 impl AnnualStockReport {
-    pub fn from(json: web::Json<AnnualStockReport>) -> AnnualStockReport {
+    pub fn from(json: web::Json<AnnualStockReport>) -> Result<AnnualStockReport, ReportError> {
         let mut report = json.into_inner();
         let _ = report.latest_update.get_or_insert(Utc::now().timestamp());
+
+        if let Some(code) = report.currency.take() {
+            report.currency = Some(normalize_currency(&code).ok_or(ReportError::InvalidCurrency(code))?);
+        }
+        corporate_actions::normalize(&mut report.corporate_actions)?;
+
+        // Everything downstream treats position in `data` as time
+        report.data.sort_by_key(|year| year.year);
+        if let Some(pair) = report.data.windows(2).find(|pair| pair[0].year == pair[1].year) {
+            return Err(ReportError::DuplicateYear(pair[0].year));
+        }
+
+        if let Some(quarter) = report.quarters.iter().find(|quarter| !(1..=4).contains(&quarter.quarter)) {
+            return Err(ReportError::InvalidQuarter(quarter.quarter));
+        }
+        report.quarters.sort_by_key(QuarterlyReport::period);
+        if let Some(pair) = report.quarters.windows(2).find(|pair| pair[0].period() == pair[1].period()) {
+            return Err(ReportError::DuplicateQuarter {
+                year: pair[0].year,
+                quarter: pair[0].quarter,
+            });
+        }
+
         report.compute_optional_if_required();
-        return report;
+        Ok(report)
+    }
+
+    /// Brings a report read from a store into the shape everything else
+    /// relies on: years and quarters in order, one report per period, the
+    /// provenance of legacy figures settled and derived data computed.
+    /// Documents written before `data` was kept sorted may hold the years in
+    /// any order, even twice; of two copies the later one wins, as the old
+    /// append-only `add_report` wrote it last.
+    pub fn normalize_loaded(&mut self) {
+        for year in self.data.iter_mut() {
+            year.adopt_legacy_provenance();
+        }
+        for year in keep_last_per_key(&mut self.data, |report| report.year) {
+            warn!("{} has more than one report for {}; keeping the last one stored", self.ticker, year);
+        }
+        for (year, quarter) in keep_last_per_key(&mut self.quarters, QuarterlyReport::period) {
+            warn!("{} has more than one report for {} Q{}; keeping the last one stored", self.ticker, year, quarter);
+        }
+        self.compute_optional_if_required();
     }
 
     pub fn compute_optional_if_required(&mut self) {
@@ -401,15 +1469,281 @@ impl AnnualStockReport {
             report.compute_optional_if_required(&prev_reports);
             prev_reports.push(report);
         }
+
+        for quarter in self.quarters.iter_mut() {
+            quarter.compute_optional_if_required();
+        }
+        self.ttm = TrailingTwelveMonths::from_quarters(&self.quarters).map(Box::new);
     }
 
-    pub fn add_new_report(&mut self, mut report: Report) {
-        let mut prev_reports: Vec<&Report> = Vec::new();
-        for report in self.data.iter() {
-            prev_reports.push(report);
+    pub fn get_quarter(&self, year: i32, quarter: u8) -> Option<&QuarterlyReport> {
+        self.quarters.iter().find(|existing| existing.period() == (year, quarter))
+    }
+
+    /// Inserts the quarter in period order and refreshes the TTM figures.
+    pub fn add_quarter(&mut self, mut report: QuarterlyReport, policy: DuplicateYearPolicy) -> Result<(), ReportError> {
+        if !(1..=4).contains(&report.quarter) {
+            return Err(ReportError::InvalidQuarter(report.quarter));
+        }
+        report.compute_optional_if_required();
+
+        match self.quarters.binary_search_by_key(&report.period(), QuarterlyReport::period) {
+            Ok(idx) => match policy {
+                DuplicateYearPolicy::Reject => {
+                    return Err(ReportError::DuplicateQuarter {
+                        year: report.year,
+                        quarter: report.quarter,
+                    })
+                }
+                DuplicateYearPolicy::Replace => self.quarters[idx] = report,
+            },
+            Err(idx) => self.quarters.insert(idx, report),
+        }
+        self.ttm = TrailingTwelveMonths::from_quarters(&self.quarters).map(Box::new);
+        Ok(())
+    }
+
+    /// Swaps in a corrected quarter. Returns false if that quarter isn't present.
+    pub fn replace_quarter(&mut self, mut report: QuarterlyReport) -> bool {
+        match self.quarters.iter().position(|existing| existing.period() == report.period()) {
+            Some(idx) => {
+                report.compute_optional_if_required();
+                self.quarters[idx] = report;
+                self.ttm = TrailingTwelveMonths::from_quarters(&self.quarters).map(Box::new);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove_quarter(&mut self, year: i32, quarter: u8) -> Option<QuarterlyReport> {
+        let idx = self.quarters.iter().position(|existing| existing.period() == (year, quarter))?;
+        let removed = self.quarters.remove(idx);
+        self.ttm = TrailingTwelveMonths::from_quarters(&self.quarters).map(Box::new);
+        Some(removed)
+    }
+
+    pub fn get_year(&self, year: i32) -> Option<&Report> {
+        self.data.iter().find(|report| report.year == year)
+    }
+
+    /// Swaps in a corrected report for an existing year and recomputes it and
+    /// every later year. Returns false if that year isn't present.
+    pub fn replace_year(&mut self, report: Report) -> bool {
+        match self.data.iter().position(|existing| existing.year == report.year) {
+            Some(idx) => {
+                self.data[idx] = report;
+                self.recompute_from(idx);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Drops a year and recomputes every year that came after it.
+    pub fn remove_year(&mut self, year: i32) -> Option<Report> {
+        let idx = self.data.iter().position(|report| report.year == year)?;
+        let removed = self.data.remove(idx);
+        self.recompute_from(idx);
+        Some(removed)
+    }
+
+    fn recompute_from(&mut self, start: usize) {
+        for idx in start..self.data.len() {
+            let (before, rest) = self.data.split_at_mut(idx);
+            let prev_reports: Vec<&Report> = before.iter().collect();
+            rest[0].compute_optional_if_required(&prev_reports);
+        }
+    }
+
+    /// Inserts the report at its place in year order and recomputes from there
+    /// onward, so a late-arriving older year also fixes the years after it.
+    pub fn add_new_report(&mut self, report: Report, policy: DuplicateYearPolicy) -> Result<(), ReportError> {
+        match self.data.binary_search_by_key(&report.year, |existing| existing.year) {
+            Ok(idx) => match policy {
+                DuplicateYearPolicy::Reject => return Err(ReportError::DuplicateYear(report.year)),
+                DuplicateYearPolicy::Replace => {
+                    self.data[idx] = report;
+                    self.recompute_from(idx);
+                }
+            },
+            Err(idx) => {
+                self.data.insert(idx, report);
+                self.recompute_from(idx);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// The bundled PEP sample: three years, 2010 to 2012, in USD millions.
+    pub(crate) fn sample_report() -> AnnualStockReport {
+        let report = serde_json::from_str(include_str!("../scripts/report.json")).expect("sample parses");
+        AnnualStockReport::from(web::Json(report)).expect("sample is valid")
+    }
+
+    /// The sample with four quarters of 2012, each a copy of the full year.
+    pub(crate) fn with_quarters() -> AnnualStockReport {
+        let mut report = sample_report();
+        let year = report.data[2].clone();
+        report.quarters = (1..=4)
+            .map(|quarter| QuarterlyReport {
+                year: year.year,
+                quarter,
+                income_statement: year.income_statement.clone(),
+                balance_sheet: year.balance_sheet.clone(),
+                cash_flow_statement: year.cash_flow_statement.clone(),
+                avg_share_price: year.financial_ratios.avg_share_price,
+                fiscal_period: FiscalPeriod::default(),
+            })
+            .collect();
+        report.compute_optional_if_required();
+        report
+    }
+
+    fn year(report: &AnnualStockReport, year: i32) -> &Report {
+        report.get_year(year).expect("year on file")
+    }
+
+    #[test]
+    fn computes_missing_derived_figures() {
+        let report = sample_report();
+        let first = year(&report, 2010);
+        assert_eq!(first.income_statement.gross_profit, Some(57838.0 - 26575.0));
+        assert_eq!(first.balance_sheet.total_debt, Some(4898.0 + 19999.0));
+        assert_eq!(first.cash_flow_statement.free_cash_flow, Some(8448.0 - 3253.0));
+        assert_eq!(first.income_statement.provenance.get("gross-profit"), Some(&Provenance::Computed));
+    }
+
+    #[test]
+    fn keeps_reported_derived_figures() {
+        let mut report = sample_report();
+        let mut changed = year(&report, 2011).clone();
+        changed.income_statement.gross_profit = Some(1.0);
+        changed.income_statement.provenance.remove("gross-profit");
+        assert!(report.replace_year(changed));
+
+        let stored = year(&report, 2011);
+        assert_eq!(stored.income_statement.gross_profit, Some(1.0));
+        assert_eq!(stored.income_statement.provenance.get("gross-profit"), Some(&Provenance::Reported));
+        assert_eq!(stored.income_statement.gross_profit_margin, safe_div(1.0, 66504.0));
+    }
+
+    #[test]
+    fn fcf_payout_ratio_is_per_share() {
+        let report = with_quarters();
+        let last = &year(&report, 2012).cash_flow_statement;
+        let ratio = year(&report, 2012).financial_ratios.fcf_payout_ratio.unwrap();
+        assert!((ratio - last.dividends_per_share / last.fcf_per_share.unwrap()).abs() < 1e-12);
+
+        let ttm = report.ttm.as_ref().expect("four quarters");
+        let trailing = &ttm.cash_flow_statement;
+        let ratio = ttm.financial_ratios.fcf_payout_ratio.unwrap();
+        assert!((ratio - trailing.dividends_per_share / trailing.fcf_per_share.unwrap()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn dividend_growth_rate_is_a_rate() {
+        let report = sample_report();
+        assert_eq!(year(&report, 2010).financial_ratios.dividend_growth_rate, None);
+        let rate = year(&report, 2011).financial_ratios.dividend_growth_rate.unwrap();
+        assert!((rate - (2.03 - 1.89) / 1.89).abs() < 1e-12);
+    }
+
+    #[test]
+    fn book_values_have_yoy() {
+        let mut report = sample_report();
+        for (year, goodwill) in report.data.iter_mut().zip([14000.0, 16000.0, 16500.0]) {
+            year.balance_sheet.goodwill = Some(goodwill);
         }
+        report.compute_optional_if_required();
+        assert_eq!(year(&report, 2010).metrics_yoy, None);
+
+        let (last, current) = (year(&report, 2011), year(&report, 2012));
+        let yoy = current.metrics_yoy.as_ref().expect("2011 is the base year");
+        for (id, value, base) in [
+            (
+                "book-value-per-share",
+                current.financial_ratios.book_value_per_share,
+                last.financial_ratios.book_value_per_share,
+            ),
+            (
+                "tangible-book-value-per-share",
+                current.financial_ratios.tangible_book_value_per_share,
+                last.financial_ratios.tangible_book_value_per_share,
+            ),
+            ("price-to-book", current.financial_ratios.price_to_book, last.financial_ratios.price_to_book),
+        ] {
+            let (value, base) = (value.unwrap(), base.unwrap());
+            assert!((yoy[id].unwrap() - (value - base) / base.abs()).abs() < 1e-12, "{}", id);
+        }
+    }
+
+    #[test]
+    fn rejects_duplicate_years_on_create() {
+        let mut report: AnnualStockReport = serde_json::from_str(include_str!("../scripts/report.json")).unwrap();
+        report.data.push(report.data[0].clone());
+        assert!(matches!(AnnualStockReport::from(web::Json(report)), Err(ReportError::DuplicateYear(2010))));
+    }
+
+    #[test]
+    fn add_new_report_inserts_in_year_order() {
+        let mut report = sample_report();
+        let mut earlier = year(&report, 2010).clone();
+        earlier.year = 2009;
+        report.add_new_report(earlier, DuplicateYearPolicy::Reject).unwrap();
+        let years: Vec<i32> = report.data.iter().map(|year| year.year).collect();
+        assert_eq!(years, [2009, 2010, 2011, 2012]);
+        assert!(year(&report, 2010).financial_ratios.dividend_growth_rate.is_some());
+
+        let again = year(&report, 2011).clone();
+        assert!(matches!(
+            report.add_new_report(again.clone(), DuplicateYearPolicy::Reject),
+            Err(ReportError::DuplicateYear(2011))
+        ));
+        assert!(report.add_new_report(again, DuplicateYearPolicy::Replace).is_ok());
+    }
+
+    #[test]
+    fn normalize_loaded_sorts_and_keeps_the_last_copy() {
+        let mut report = sample_report();
+        let mut copy = year(&report, 2010).clone();
+        copy.income_statement.net_income = 1.0;
+        report.data.reverse();
+        report.data.push(copy);
+
+        report.normalize_loaded();
+        let years: Vec<i32> = report.data.iter().map(|year| year.year).collect();
+        assert_eq!(years, [2010, 2011, 2012]);
+        assert_eq!(year(&report, 2010).income_statement.net_income, 1.0);
+    }
+
+    #[test]
+    fn legacy_figures_are_settled_by_their_formula() {
+        let mut report = sample_report();
+        let legacy = &mut report.data[0].income_statement;
+        legacy.provenance.clear();
+        // What the statement computes itself, and a figure no formula gives
+        legacy.operating_income = Some(57838.0 - 26575.0 - 22931.0);
+        legacy.net_profit_margin = Some(0.5);
+
+        report.normalize_loaded();
+        let provenance = &report.data[0].income_statement.provenance;
+        assert_eq!(provenance.get("operating-income"), Some(&Provenance::Computed));
+        assert_eq!(provenance.get("net-profit-margin"), Some(&Provenance::Reported));
+        assert_eq!(report.data[0].income_statement.net_profit_margin, Some(0.5));
+    }
 
-        report.compute_optional_if_required(&prev_reports);
-        self.data.push(report);
+    #[test]
+    fn flows_follow_the_derived_figures() {
+        assert!(is_flow("revenue"));
+        assert!(is_flow("free-cash-flow"));
+        assert!(!is_flow("gross-profit-margin"));
+        assert!(!is_flow("shares-outstanding-basic"));
+        assert!(DERIVED_FIGURES.iter().all(|figure| Values::is_known(figure.id)));
     }
 }
@@ -0,0 +1,258 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::report_computer::CustomMetric;
+use crate::report_history::Revision;
+use crate::report_model::AnnualStockReport;
+
+#[derive(Debug)]
+pub enum StoreError {
+    AlreadyExists(String),
+    VersionConflict { ticker: String, expected_version: i32 },
+    Backend(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::AlreadyExists(ticker) => write!(f, "Ticker {} already exists", ticker),
+            StoreError::VersionConflict { ticker, expected_version } => write!(
+                f,
+                "Report for {} was modified concurrently (expected version {})",
+                ticker, expected_version
+            ),
+            StoreError::Backend(err) => write!(f, "Storage error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum SortKey {
+    #[default]
+    Ticker,
+    LatestUpdate,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// How much of each report a listing returns.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Projection {
+    #[default]
+    Full,
+    /// Only `ticker`, `latest-update` and `version`; `data` and `quarters` come back empty.
+    Summary,
+    /// `data` holds just the most recent year and `quarters` is left out; `ttm` is kept.
+    LatestYear,
+}
+
+impl Projection {
+    /// What a store should load for this projection. Growth rates, YoY blocks
+    /// and lagged custom metrics of the latest year look back at the earlier
+    /// ones, so `LatestYear` is only trimmed after the report has been presented.
+    pub fn stored(self) -> Projection {
+        match self {
+            Projection::LatestYear => Projection::Full,
+            projection => projection,
+        }
+    }
+}
+
+/// Position right after the last item of a page. Ties on the sort key are
+/// broken by ticker, which is unique, so pages never overlap or skip.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    pub sort: SortKey,
+    pub order: SortOrder,
+    pub latest_update: Option<i64>,
+    pub ticker: String,
+}
+
+impl Cursor {
+    pub fn after(report: &AnnualStockReport, sort: SortKey, order: SortOrder) -> Cursor {
+        Cursor {
+            sort,
+            order,
+            latest_update: report.latest_update,
+            ticker: report.ticker.clone(),
+        }
+    }
+
+    /// Opaque to clients: hex-encoded JSON, so it is safe to put in a URL as-is.
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).unwrap_or_default();
+        json.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    pub fn decode(encoded: &str) -> Option<Cursor> {
+        if !encoded.len().is_multiple_of(2) || !encoded.is_ascii() {
+            return None;
+        }
+        let bytes = (0..encoded.len())
+            .step_by(2)
+            .map(|idx| u8::from_str_radix(&encoded[idx..idx + 2], 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+}
+
+/// Filters, ordering, paging and projection for listing reports. Every store
+/// applies these in its own query language rather than in memory.
+#[derive(Debug, Clone, Default)]
+pub struct ListQuery {
+    pub ticker_prefix: Option<String>,
+    /// Keep tickers with at least one report in `[year_from, year_to]`.
+    pub year_from: Option<i32>,
+    pub year_to: Option<i32>,
+    /// Keep tickers that have a report for exactly this year.
+    pub has_year: Option<i32>,
+    pub sort: SortKey,
+    pub order: SortOrder,
+    pub after: Option<Cursor>,
+    /// `None` returns every match.
+    pub limit: Option<usize>,
+    pub projection: Projection,
+}
+
+#[derive(Debug)]
+pub struct ReportPage {
+    pub items: Vec<AnnualStockReport>,
+    pub next_cursor: Option<Cursor>,
+}
+
+impl ReportPage {
+    /// Stores fetch one item past the limit; its presence means there is a next page.
+    pub fn from_overfetch(mut items: Vec<AnnualStockReport>, query: &ListQuery) -> ReportPage {
+        let next_cursor = match query.limit {
+            Some(limit) if items.len() > limit => {
+                items.truncate(limit);
+                items.last().map(|last| Cursor::after(last, query.sort, query.order))
+            }
+            _ => None,
+        };
+        ReportPage { items, next_cursor }
+    }
+}
+
+/// Drops what `projection` leaves out of a report.
+pub fn apply_projection(report: &mut AnnualStockReport, projection: Projection) {
+    match projection {
+        Projection::Full => {}
+        Projection::Summary => {
+            report.data.clear();
+            report.quarters.clear();
+            report.ttm = None;
+        }
+        Projection::LatestYear => {
+            let keep_from = report.data.len().saturating_sub(1);
+            report.data.drain(..keep_from);
+            report.quarters.clear();
+        }
+    }
+}
+
+/// Persistence for `AnnualStockReport` documents, one per ticker.
+#[async_trait]
+pub trait ReportStore: Send + Sync {
+    async fn get(&self, ticker: &str) -> Result<Option<AnnualStockReport>, StoreError>;
+
+    async fn list(&self, query: &ListQuery) -> Result<ReportPage, StoreError>;
+
+    /// Stores a new ticker. Every write below also appends `revision` to the
+    /// ticker's history, and either both happen or neither does, so a change
+    /// is never saved unrecorded. The store numbers the revision one past the
+    /// ticker's latest; `revision.revision` is ignored.
+    async fn create(&self, report: &AnnualStockReport, revision: &Revision) -> Result<(), StoreError>;
+
+    /// Replaces the stored report only if its version still equals `expected_version`.
+    async fn replace(&self, report: &AnnualStockReport, expected_version: i32, revision: &Revision) -> Result<(), StoreError>;
+
+    /// Removes the report and hands back what was stored, if anything. Nothing
+    /// is recorded when there was no report. History is kept per ticker and
+    /// outlives the report itself, so a deleted ticker can still be restored.
+    async fn delete(&self, ticker: &str, revision: &Revision) -> Result<Option<AnnualStockReport>, StoreError>;
+
+    /// All revisions of `ticker`, oldest first.
+    async fn list_revisions(&self, ticker: &str) -> Result<Vec<Revision>, StoreError>;
+
+    async fn get_revision(&self, ticker: &str, revision: i32) -> Result<Option<Revision>, StoreError>;
+
+    /// Custom metric definitions, which apply to every ticker, ordered by id.
+    async fn list_custom_metrics(&self) -> Result<Vec<CustomMetric>, StoreError>;
+
+    /// Adds the metric, or replaces the one with the same id.
+    async fn put_custom_metric(&self, metric: &CustomMetric) -> Result<(), StoreError>;
+
+    /// Removes the metric and hands back what was stored, if anything.
+    async fn delete_custom_metric(&self, id: &str) -> Result<Option<CustomMetric>, StoreError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report_model::tests::sample_report;
+
+    #[test]
+    fn cursor_round_trips() {
+        let cursor = Cursor {
+            sort: SortKey::LatestUpdate,
+            order: SortOrder::Desc,
+            latest_update: Some(1_700_000_000),
+            ticker: String::from("BRK.B"),
+        };
+        let encoded = cursor.encode();
+        assert!(encoded.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(Cursor::decode(&encoded), Some(cursor));
+    }
+
+    #[test]
+    fn cursor_rejects_garbage() {
+        assert_eq!(Cursor::decode("abc"), None);
+        assert_eq!(Cursor::decode("zz"), None);
+        assert_eq!(Cursor::decode("7b7d"), None);
+        assert_eq!(Cursor::decode("ééé"), None);
+    }
+
+    #[test]
+    fn overfetch_sets_the_next_cursor() {
+        let tickers = ["A", "B", "C"].map(|ticker| {
+            let mut report = sample_report();
+            report.ticker = ticker.to_string();
+            report
+        });
+        let query = ListQuery {
+            limit: Some(2),
+            ..ListQuery::default()
+        };
+
+        let page = ReportPage::from_overfetch(tickers.to_vec(), &query);
+        assert_eq!(page.items.len(), 2);
+        assert_eq!(page.next_cursor.map(|cursor| cursor.ticker), Some(String::from("B")));
+
+        let last = ReportPage::from_overfetch(tickers[..2].to_vec(), &query);
+        assert!(last.next_cursor.is_none());
+    }
+
+    #[test]
+    fn projections_trim_the_report() {
+        let mut latest = sample_report();
+        apply_projection(&mut latest, Projection::LatestYear);
+        assert_eq!(latest.data.iter().map(|year| year.year).collect::<Vec<_>>(), [2012]);
+
+        let mut summary = sample_report();
+        apply_projection(&mut summary, Projection::Summary);
+        assert!(summary.data.is_empty() && summary.ttm.is_none());
+        assert_eq!(Projection::LatestYear.stored(), Projection::Full);
+    }
+}
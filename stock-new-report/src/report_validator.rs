@@ -0,0 +1,505 @@
+use serde::Serialize;
+use std::ops::RangeInclusive;
+
+use crate::report_model::{
+    supplied, AnnualStockReport, BalanceSheet, CashFlowStatement, FiscalPeriod, IncomeStatement, Provenance,
+    ProvenanceMap, QuarterlyReport, Report,
+};
+
+/// Amounts are compared allowing for rounding in the filing: one unit, or
+/// half a percent of the larger side for big figures.
+const AMOUNT_TOLERANCE: f64 = 1.0;
+const RATIO_TOLERANCE: f64 = 0.001;
+const RELATIVE_TOLERANCE: f64 = 0.005;
+
+/// Regular period lengths in weeks. Anything else is a transition period,
+/// e.g. after a change of fiscal year end.
+const YEAR_WEEKS: RangeInclusive<u32> = 52..=53;
+const QUARTER_WEEKS: RangeInclusive<u32> = 12..=14;
+
+/// Errors break an accounting identity and are rejected on ingest. Warnings
+/// are figures that are possible but unusual enough to be worth a second look.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Finding {
+    pub rule: &'static str,
+    pub severity: Severity,
+    /// JSON path of the offending figure within the year, e.g. `balance-sheet.total-equity`
+    pub field: &'static str,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct YearFindings {
+    pub year: i32,
+    /// Set for findings about a quarterly report.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quarter: Option<u8>,
+    pub findings: Vec<Finding>,
+}
+
+impl YearFindings {
+    fn for_year(report: &Report) -> YearFindings {
+        YearFindings {
+            year: report.year,
+            quarter: None,
+            findings: validate_year(report),
+        }
+    }
+
+    fn for_quarter(report: &QuarterlyReport) -> YearFindings {
+        YearFindings {
+            year: report.year,
+            quarter: Some(report.quarter),
+            findings: validate_quarter(report),
+        }
+    }
+
+    /// `2023` or `2023 Q2`, for messages.
+    pub fn period(&self) -> String {
+        match self.quarter {
+            Some(quarter) => format!("{} Q{}", self.year, quarter),
+            None => self.year.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ValidationReport {
+    pub ticker: String,
+    /// False when any year has an error; warnings alone keep a report valid.
+    pub valid: bool,
+    pub years: Vec<YearFindings>,
+}
+
+pub fn has_errors(years: &[YearFindings]) -> bool {
+    years
+        .iter()
+        .flat_map(|year| year.findings.iter())
+        .any(|finding| finding.severity == Severity::Error)
+}
+
+/// Checks every year and quarter of a stored report.
+pub fn validate_report(report: &AnnualStockReport) -> ValidationReport {
+    let years: Vec<YearFindings> = report
+        .data
+        .iter()
+        .map(YearFindings::for_year)
+        .chain(report.quarters.iter().map(YearFindings::for_quarter))
+        .collect();
+
+    ValidationReport {
+        ticker: report.ticker.clone(),
+        valid: !has_errors(&years),
+        years,
+    }
+}
+
+/// Checks incoming years before they are computed, so identities are tested
+/// against the figures the client actually sent. Only years with findings
+/// are returned.
+pub fn validate_input<'a>(years: impl IntoIterator<Item = &'a Report>) -> Vec<YearFindings> {
+    years
+        .into_iter()
+        .map(YearFindings::for_year)
+        .filter(|year| !year.findings.is_empty())
+        .collect()
+}
+
+pub fn validate_quarter_input<'a>(quarters: impl IntoIterator<Item = &'a QuarterlyReport>) -> Vec<YearFindings> {
+    quarters
+        .into_iter()
+        .map(YearFindings::for_quarter)
+        .filter(|quarter| !quarter.findings.is_empty())
+        .collect()
+}
+
+pub fn validate_year(report: &Report) -> Vec<Finding> {
+    let mut findings = validate_statements(
+        &report.income_statement,
+        &report.balance_sheet,
+        &report.cash_flow_statement,
+        ("financial-ratios.avg-share-price", report.financial_ratios.avg_share_price),
+    );
+    findings.extend(validate_period(&report.fiscal_period, YEAR_WEEKS));
+    findings
+}
+
+pub fn validate_quarter(report: &QuarterlyReport) -> Vec<Finding> {
+    let mut findings = validate_statements(
+        &report.income_statement,
+        &report.balance_sheet,
+        &report.cash_flow_statement,
+        ("avg-share-price", report.avg_share_price),
+    );
+    findings.extend(validate_period(&report.fiscal_period, QUARTER_WEEKS));
+    findings
+}
+
+/// Dates out of order are an error; an unusual length or a filing date that
+/// looks early are only warnings.
+fn validate_period(period: &FiscalPeriod, regular_weeks: RangeInclusive<u32>) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    if let (Some(start), Some(end)) = (period.start, period.end) {
+        if end < start {
+            findings.push(Finding {
+                rule: "period-dates-reversed",
+                severity: Severity::Error,
+                field: "fiscal-period.end",
+                message: format!("period ends on {} before it starts on {}", end, start),
+            });
+        }
+    }
+
+    let from_dates = period.weeks_between_dates();
+    if let (Some(weeks), Some(from_dates)) = (period.weeks, from_dates) {
+        if weeks.abs_diff(from_dates) > 1 {
+            findings.push(Finding {
+                rule: "period-weeks-mismatch",
+                severity: Severity::Warning,
+                field: "fiscal-period.weeks",
+                message: format!("{} weeks does not match the {} weeks between start and end", weeks, from_dates),
+            });
+        }
+    }
+
+    if let Some(weeks) = period.weeks.or(from_dates).filter(|weeks| !regular_weeks.contains(weeks)) {
+        findings.push(Finding {
+            rule: "irregular-period-length",
+            severity: Severity::Warning,
+            field: "fiscal-period.weeks",
+            message: format!(
+                "period is {} weeks, outside the usual {}..{}",
+                weeks,
+                regular_weeks.start(),
+                regular_weeks.end()
+            ),
+        });
+    }
+
+    if let (Some(filed), Some(end)) = (period.filing_date, period.end) {
+        if filed < end {
+            findings.push(Finding {
+                rule: "filed-before-period-end",
+                severity: Severity::Warning,
+                field: "fiscal-period.filing-date",
+                message: format!("filed on {}, before the period ended on {}", filed, end),
+            });
+        }
+    }
+
+    findings
+}
+
+/// Runs every rule against the statements of one period. Only reported and overridden figures are
+/// checked against the identities; computed ones are consistent by
+/// construction. A figure the user overrode is expected to disagree with the
+/// formula, so for those a mismatch is only a warning.
+fn validate_statements(
+    is: &IncomeStatement,
+    bs: &BalanceSheet,
+    cfs: &CashFlowStatement,
+    (price_field, avg_share_price): (&'static str, f64),
+) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    let gross_profit = supplied(is.gross_profit, &is.provenance, "gross-profit").unwrap_or(is.revenue - is.total_cogs);
+    let operating_income = supplied(is.operating_income, &is.provenance, "operating-income")
+        .unwrap_or(gross_profit - is.operating_expense);
+
+    check_identity(
+        &mut findings,
+        &is.provenance,
+        "gross-profit-identity",
+        "income-statement.gross-profit",
+        is.gross_profit,
+        is.revenue - is.total_cogs,
+        AMOUNT_TOLERANCE,
+        "revenue - total-cogs",
+    );
+    check_identity(
+        &mut findings,
+        &is.provenance,
+        "operating-income-identity",
+        "income-statement.operating-income",
+        is.operating_income,
+        gross_profit - is.operating_expense,
+        AMOUNT_TOLERANCE,
+        "gross-profit - operating-expense",
+    );
+    if is.revenue != 0.0 {
+        check_identity(
+            &mut findings,
+            &is.provenance,
+            "gross-profit-margin-identity",
+            "income-statement.gross-profit-margin",
+            is.gross_profit_margin,
+            gross_profit / is.revenue,
+            RATIO_TOLERANCE,
+            "gross-profit / revenue",
+        );
+        check_identity(
+            &mut findings,
+            &is.provenance,
+            "operating-profit-margin-identity",
+            "income-statement.operating-profit-margin",
+            is.operating_profit_margin,
+            operating_income / is.revenue,
+            RATIO_TOLERANCE,
+            "operating-income / revenue",
+        );
+        check_identity(
+            &mut findings,
+            &is.provenance,
+            "net-profit-margin-identity",
+            "income-statement.net-profit-margin",
+            is.net_profit_margin,
+            is.net_income / is.revenue,
+            RATIO_TOLERANCE,
+            "net-income / revenue",
+        );
+    }
+
+    if let Some(d_and_a) = is.depreciation_and_amortization {
+        let ebit = supplied(is.ebit, &is.provenance, "ebit").or(match is.pretax_income {
+            Some(pretax_income) => Some(pretax_income + is.interest_expense.abs()),
+            None => Some(operating_income),
+        });
+        if let Some(ebit) = ebit {
+            check_identity(
+                &mut findings,
+                &is.provenance,
+                "ebitda-identity",
+                "income-statement.ebitda",
+                is.ebitda,
+                ebit + d_and_a,
+                AMOUNT_TOLERANCE,
+                "ebit + depreciation-and-amortization",
+            );
+        }
+    }
+
+    check_identity(
+        &mut findings,
+        &bs.provenance,
+        "total-debt-identity",
+        "balance-sheet.total-debt",
+        bs.total_debt,
+        bs.short_term_debt + bs.long_term_debt,
+        AMOUNT_TOLERANCE,
+        "short-term-debt + long-term-debt",
+    );
+    // assets = liabilities + equity
+    check_identity(
+        &mut findings,
+        &bs.provenance,
+        "balance-sheet-identity",
+        "balance-sheet.total-equity",
+        bs.total_equity,
+        bs.total_assets - bs.total_liabilities,
+        AMOUNT_TOLERANCE,
+        "total-assets - total-liabilities",
+    );
+    check_identity(
+        &mut findings,
+        &cfs.provenance,
+        "free-cash-flow-identity",
+        "cash-flow-statement.free-cash-flow",
+        cfs.free_cash_flow,
+        cfs.operating_cash_flow - cfs.capital_expenditure,
+        AMOUNT_TOLERANCE,
+        "operating-cash-flow - capital-expenditure",
+    );
+
+    for (field, margin) in [
+        ("income-statement.gross-profit-margin", is.gross_profit_margin),
+        ("income-statement.operating-profit-margin", is.operating_profit_margin),
+        ("income-statement.net-profit-margin", is.net_profit_margin),
+    ] {
+        if let Some(margin) = margin.filter(|margin| !(-1.0..=1.0).contains(margin)) {
+            findings.push(Finding {
+                rule: "margin-out-of-range",
+                severity: Severity::Warning,
+                field,
+                message: format!("margin {} is outside -1..1", margin),
+            });
+        }
+    }
+
+    for (field, value) in [
+        ("income-statement.revenue", is.revenue),
+        ("balance-sheet.total-assets", bs.total_assets),
+        ("balance-sheet.cash-and-equivalents", bs.cash_and_equivalents),
+        ("cash-flow-statement.dividends-per-share", cfs.dividends_per_share),
+        (price_field, avg_share_price),
+    ] {
+        if value < 0.0 {
+            findings.push(Finding {
+                rule: "negative-value",
+                severity: Severity::Warning,
+                field,
+                message: format!("{} is negative", value),
+            });
+        }
+    }
+
+    let current_assets = bs.current_assets.unwrap_or(bs.total_assets);
+    for (field, part, whole_field, whole) in [
+        ("balance-sheet.current-assets", bs.current_assets, "total-assets", bs.total_assets),
+        ("balance-sheet.current-liabilities", bs.current_liabilities, "total-liabilities", bs.total_liabilities),
+        ("balance-sheet.inventory", bs.inventory, "current-assets", current_assets),
+        ("balance-sheet.receivables", bs.receivables, "current-assets", current_assets),
+    ] {
+        if let Some(part) = part.filter(|part| *part > whole + AMOUNT_TOLERANCE) {
+            findings.push(Finding {
+                rule: "part-exceeds-total",
+                severity: Severity::Warning,
+                field,
+                message: format!("{} is more than {} {}", part, whole_field, whole),
+            });
+        }
+    }
+
+    if let Some(rate) = is.effective_tax_rate.filter(|rate| !(0.0..=1.0).contains(rate)) {
+        findings.push(Finding {
+            rule: "tax-rate-out-of-range",
+            severity: Severity::Warning,
+            field: "income-statement.effective-tax-rate",
+            message: format!("effective tax rate {} is outside 0..1", rate),
+        });
+    }
+
+    if let Some(diluted) = is.shares_outstanding_diluted.filter(|diluted| *diluted < is.shares_outstanding_basic) {
+        findings.push(Finding {
+            rule: "diluted-below-basic",
+            severity: Severity::Warning,
+            field: "income-statement.shares-outstanding-diluted",
+            message: format!(
+                "{} diluted shares is less than {} basic shares",
+                diluted, is.shares_outstanding_basic
+            ),
+        });
+    }
+
+    if is.shares_outstanding_basic <= 0.0 {
+        findings.push(Finding {
+            rule: "non-positive-shares",
+            severity: Severity::Warning,
+            field: "income-statement.shares-outstanding-basic",
+            message: format!("{} shares outstanding, per-share figures are undefined", is.shares_outstanding_basic),
+        });
+    }
+
+    let total_debt = supplied(bs.total_debt, &bs.provenance, "total-debt").unwrap_or(bs.short_term_debt + bs.long_term_debt);
+    if total_debt > bs.total_liabilities + AMOUNT_TOLERANCE {
+        findings.push(Finding {
+            rule: "debt-exceeds-liabilities",
+            severity: Severity::Warning,
+            field: "balance-sheet.total-debt",
+            message: format!("total debt {} is more than total liabilities {}", total_debt, bs.total_liabilities),
+        });
+    }
+
+    findings
+}
+
+#[allow(clippy::too_many_arguments)]
+fn check_identity(
+    findings: &mut Vec<Finding>,
+    provenance: &ProvenanceMap,
+    rule: &'static str,
+    field: &'static str,
+    actual: Option<f64>,
+    expected: f64,
+    tolerance: f64,
+    formula: &str,
+) {
+    // Provenance is keyed by the name within the statement
+    let key = field.rsplit('.').next().unwrap_or(field);
+    let Some(actual) = supplied(actual, provenance, key) else {
+        return;
+    };
+    let tolerance = tolerance.max(RELATIVE_TOLERANCE * actual.abs().max(expected.abs()));
+    if (actual - expected).abs() > tolerance {
+        let (severity, note) = match provenance.get(key) {
+            Some(Provenance::Overridden) => (Severity::Warning, " (overridden)"),
+            _ => (Severity::Error, ""),
+        };
+        findings.push(Finding {
+            rule,
+            severity,
+            field,
+            message: format!("{}{} does not match {} = {}", actual, note, formula, expected),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report_model::tests::sample_report;
+
+    fn rules(findings: &[Finding]) -> Vec<(&'static str, Severity)> {
+        findings.iter().map(|finding| (finding.rule, finding.severity)).collect()
+    }
+
+    #[test]
+    fn the_sample_is_clean() {
+        let report = validate_report(&sample_report());
+        assert!(report.valid);
+        assert!(report.years.iter().all(|year| year.findings.is_empty()));
+    }
+
+    #[test]
+    fn reported_figures_must_match_their_identity() {
+        let mut year = sample_report().data[0].clone();
+        year.income_statement.gross_profit = Some(1.0);
+        year.income_statement.provenance.insert(String::from("gross-profit"), Provenance::Reported);
+        assert_eq!(rules(&validate_year(&year)), [("gross-profit-identity", Severity::Error)]);
+
+        // Within rounding is fine
+        year.income_statement.gross_profit = Some(57838.0 - 26575.0 + 0.5);
+        assert!(validate_year(&year).is_empty());
+    }
+
+    #[test]
+    fn overridden_figures_only_warn() {
+        let mut year = sample_report().data[0].clone();
+        year.balance_sheet.total_equity = Some(1.0);
+        year.balance_sheet.provenance.insert(String::from("total-equity"), Provenance::Overridden);
+        assert_eq!(rules(&validate_year(&year)), [("balance-sheet-identity", Severity::Warning)]);
+    }
+
+    #[test]
+    fn computed_figures_are_not_checked() {
+        let mut year = sample_report().data[0].clone();
+        year.cash_flow_statement.free_cash_flow = Some(1.0);
+        assert!(validate_year(&year).is_empty());
+    }
+
+    #[test]
+    fn flags_negative_values() {
+        let mut year = sample_report().data[0].clone();
+        year.income_statement.revenue = -1.0;
+        assert_eq!(rules(&validate_year(&year)), [("negative-value", Severity::Warning)]);
+    }
+
+    #[test]
+    fn flags_reversed_period_dates() {
+        let mut year = sample_report().data[0].clone();
+        year.fiscal_period.start = "2010-12-31".parse().ok();
+        year.fiscal_period.end = "2010-01-01".parse().ok();
+        assert_eq!(rules(&validate_year(&year)), [("period-dates-reversed", Severity::Error)]);
+
+        let findings = validate_input([&year]);
+        assert!(has_errors(&findings));
+        assert_eq!(findings[0].period(), "2010");
+    }
+}
@@ -0,0 +1,1064 @@
+use async_trait::async_trait;
+use log::error;
+use rusqlite::{params, params_from_iter, types::Value, Connection, OptionalExtension, Row, Transaction};
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::corporate_actions::{ActionKind, CorporateAction};
+use crate::currency::UnitScale;
+use crate::report_computer::CustomMetric;
+use crate::report_history::Revision;
+use crate::report_model::{
+    supplied, AnnualStockReport, BalanceSheet, CashFlowStatement, FinancialRatios, FiscalPeriod, IncomeStatement, Provenance,
+    ProvenanceMap, QuarterlyReport, Report,
+};
+use crate::report_store::{
+    ListQuery, Projection, ReportPage, ReportStore, SortKey, SortOrder, StoreError,
+};
+
+/// Each entry moves the schema one `user_version` forward. Append, never edit.
+const MIGRATIONS: &[&str] = &[r#"
+    CREATE TABLE stock_reports (
+        ticker          TEXT PRIMARY KEY,
+        latest_update   INTEGER,
+        version         INTEGER NOT NULL
+    );
+
+    CREATE TABLE income_statements (
+        ticker                      TEXT NOT NULL REFERENCES stock_reports(ticker) ON DELETE CASCADE,
+        year                        INTEGER NOT NULL,
+        revenue                     REAL NOT NULL,
+        total_cogs                  REAL NOT NULL,
+        gross_profit_margin         REAL,
+        operating_expense           REAL NOT NULL,
+        operating_income            REAL,
+        operating_profit_margin     REAL,
+        interest_expense            REAL NOT NULL,
+        net_income                  REAL NOT NULL,
+        net_profit_margin           REAL,
+        eps_basic                   REAL NOT NULL,
+        shares_outstanding_basic    REAL NOT NULL,
+        PRIMARY KEY (ticker, year)
+    );
+
+    CREATE TABLE balance_sheets (
+        ticker                  TEXT NOT NULL REFERENCES stock_reports(ticker) ON DELETE CASCADE,
+        year                    INTEGER NOT NULL,
+        cash_and_equivalents    REAL NOT NULL,
+        total_assets            REAL NOT NULL,
+        short_term_debt         REAL NOT NULL,
+        long_term_debt          REAL NOT NULL,
+        total_liabilities       REAL NOT NULL,
+        PRIMARY KEY (ticker, year)
+    );
+
+    CREATE TABLE cash_flow_statements (
+        ticker                  TEXT NOT NULL REFERENCES stock_reports(ticker) ON DELETE CASCADE,
+        year                    INTEGER NOT NULL,
+        operating_cash_flow     REAL NOT NULL,
+        investing_cash_flow     REAL NOT NULL,
+        capital_expenditure     REAL NOT NULL,
+        financing_cash_flow     REAL NOT NULL,
+        dividends_paid          REAL NOT NULL,
+        dividends_per_share     REAL NOT NULL,
+        PRIMARY KEY (ticker, year)
+    );
+
+    CREATE TABLE financial_ratios (
+        ticker              TEXT NOT NULL REFERENCES stock_reports(ticker) ON DELETE CASCADE,
+        year                INTEGER NOT NULL,
+        avg_share_price     REAL NOT NULL,
+        PRIMARY KEY (ticker, year)
+    );
+"#, r#"
+    CREATE TABLE report_revisions (
+        ticker          TEXT NOT NULL,
+        revision        INTEGER NOT NULL,
+        timestamp       INTEGER NOT NULL,
+        description     TEXT NOT NULL,
+        snapshot        TEXT,
+        PRIMARY KEY (ticker, revision)
+    );
+"#, r#"
+    -- Derived figures are now kept only when they were reported or overridden,
+    -- with their provenance. Rows written before this stored computed margins
+    -- indistinguishably from reported ones, so those are recomputed from now on.
+    UPDATE income_statements
+    SET gross_profit_margin = NULL, operating_income = NULL, operating_profit_margin = NULL, net_profit_margin = NULL;
+
+    ALTER TABLE income_statements ADD COLUMN gross_profit REAL;
+    ALTER TABLE income_statements ADD COLUMN provenance TEXT NOT NULL DEFAULT '{}';
+
+    ALTER TABLE balance_sheets ADD COLUMN total_debt REAL;
+    ALTER TABLE balance_sheets ADD COLUMN total_equity REAL;
+    ALTER TABLE balance_sheets ADD COLUMN debt_to_capital REAL;
+    ALTER TABLE balance_sheets ADD COLUMN provenance TEXT NOT NULL DEFAULT '{}';
+
+    ALTER TABLE cash_flow_statements ADD COLUMN free_cash_flow REAL;
+    ALTER TABLE cash_flow_statements ADD COLUMN fcf_per_share REAL;
+    ALTER TABLE cash_flow_statements ADD COLUMN provenance TEXT NOT NULL DEFAULT '{}';
+"#, r#"
+    -- Quarterly reports share the statement tables with the annual ones, keyed
+    -- by quarter as well; quarter 0 is the full fiscal year. SQLite can't change
+    -- a primary key in place, so each table is rebuilt.
+    CREATE TABLE income_statements_new (
+        ticker                      TEXT NOT NULL REFERENCES stock_reports(ticker) ON DELETE CASCADE,
+        year                        INTEGER NOT NULL,
+        quarter                     INTEGER NOT NULL,
+        revenue                     REAL NOT NULL,
+        total_cogs                  REAL NOT NULL,
+        gross_profit_margin         REAL,
+        operating_expense           REAL NOT NULL,
+        operating_income            REAL,
+        operating_profit_margin     REAL,
+        interest_expense            REAL NOT NULL,
+        net_income                  REAL NOT NULL,
+        net_profit_margin           REAL,
+        eps_basic                   REAL NOT NULL,
+        shares_outstanding_basic    REAL NOT NULL,
+        gross_profit                REAL,
+        provenance                  TEXT NOT NULL DEFAULT '{}',
+        PRIMARY KEY (ticker, year, quarter)
+    );
+    INSERT INTO income_statements_new
+    SELECT ticker, year, 0, revenue, total_cogs, gross_profit_margin, operating_expense, operating_income,
+           operating_profit_margin, interest_expense, net_income, net_profit_margin, eps_basic,
+           shares_outstanding_basic, gross_profit, provenance
+    FROM income_statements;
+    DROP TABLE income_statements;
+    ALTER TABLE income_statements_new RENAME TO income_statements;
+
+    CREATE TABLE balance_sheets_new (
+        ticker                  TEXT NOT NULL REFERENCES stock_reports(ticker) ON DELETE CASCADE,
+        year                    INTEGER NOT NULL,
+        quarter                 INTEGER NOT NULL,
+        cash_and_equivalents    REAL NOT NULL,
+        total_assets            REAL NOT NULL,
+        short_term_debt         REAL NOT NULL,
+        long_term_debt          REAL NOT NULL,
+        total_liabilities       REAL NOT NULL,
+        total_debt              REAL,
+        total_equity            REAL,
+        debt_to_capital         REAL,
+        provenance              TEXT NOT NULL DEFAULT '{}',
+        PRIMARY KEY (ticker, year, quarter)
+    );
+    INSERT INTO balance_sheets_new
+    SELECT ticker, year, 0, cash_and_equivalents, total_assets, short_term_debt, long_term_debt,
+           total_liabilities, total_debt, total_equity, debt_to_capital, provenance
+    FROM balance_sheets;
+    DROP TABLE balance_sheets;
+    ALTER TABLE balance_sheets_new RENAME TO balance_sheets;
+
+    CREATE TABLE cash_flow_statements_new (
+        ticker                  TEXT NOT NULL REFERENCES stock_reports(ticker) ON DELETE CASCADE,
+        year                    INTEGER NOT NULL,
+        quarter                 INTEGER NOT NULL,
+        operating_cash_flow     REAL NOT NULL,
+        investing_cash_flow     REAL NOT NULL,
+        capital_expenditure     REAL NOT NULL,
+        financing_cash_flow     REAL NOT NULL,
+        dividends_paid          REAL NOT NULL,
+        dividends_per_share     REAL NOT NULL,
+        free_cash_flow          REAL,
+        fcf_per_share           REAL,
+        provenance              TEXT NOT NULL DEFAULT '{}',
+        PRIMARY KEY (ticker, year, quarter)
+    );
+    INSERT INTO cash_flow_statements_new
+    SELECT ticker, year, 0, operating_cash_flow, investing_cash_flow, capital_expenditure,
+           financing_cash_flow, dividends_paid, dividends_per_share, free_cash_flow, fcf_per_share, provenance
+    FROM cash_flow_statements;
+    DROP TABLE cash_flow_statements;
+    ALTER TABLE cash_flow_statements_new RENAME TO cash_flow_statements;
+
+    CREATE TABLE financial_ratios_new (
+        ticker              TEXT NOT NULL REFERENCES stock_reports(ticker) ON DELETE CASCADE,
+        year                INTEGER NOT NULL,
+        quarter             INTEGER NOT NULL,
+        avg_share_price     REAL NOT NULL,
+        PRIMARY KEY (ticker, year, quarter)
+    );
+    INSERT INTO financial_ratios_new SELECT ticker, year, 0, avg_share_price FROM financial_ratios;
+    DROP TABLE financial_ratios;
+    ALTER TABLE financial_ratios_new RENAME TO financial_ratios;
+"#, r#"
+    ALTER TABLE income_statements ADD COLUMN research_and_development REAL;
+    ALTER TABLE income_statements ADD COLUMN selling_general_administrative REAL;
+    ALTER TABLE income_statements ADD COLUMN depreciation_and_amortization REAL;
+    ALTER TABLE income_statements ADD COLUMN pretax_income REAL;
+    ALTER TABLE income_statements ADD COLUMN income_tax REAL;
+    ALTER TABLE income_statements ADD COLUMN stock_based_compensation REAL;
+    ALTER TABLE income_statements ADD COLUMN eps_diluted REAL;
+    ALTER TABLE income_statements ADD COLUMN shares_outstanding_diluted REAL;
+    ALTER TABLE income_statements ADD COLUMN ebit REAL;
+    ALTER TABLE income_statements ADD COLUMN ebitda REAL;
+    ALTER TABLE income_statements ADD COLUMN effective_tax_rate REAL;
+    ALTER TABLE income_statements ADD COLUMN rd_intensity REAL;
+    ALTER TABLE income_statements ADD COLUMN dilution REAL;
+"#, r#"
+    ALTER TABLE balance_sheets ADD COLUMN current_assets REAL;
+    ALTER TABLE balance_sheets ADD COLUMN current_liabilities REAL;
+    ALTER TABLE balance_sheets ADD COLUMN inventory REAL;
+    ALTER TABLE balance_sheets ADD COLUMN receivables REAL;
+    ALTER TABLE balance_sheets ADD COLUMN payables REAL;
+    ALTER TABLE balance_sheets ADD COLUMN goodwill REAL;
+    ALTER TABLE balance_sheets ADD COLUMN intangibles REAL;
+    ALTER TABLE balance_sheets ADD COLUMN retained_earnings REAL;
+    ALTER TABLE balance_sheets ADD COLUMN current_ratio REAL;
+    ALTER TABLE balance_sheets ADD COLUMN quick_ratio REAL;
+    ALTER TABLE balance_sheets ADD COLUMN net_debt REAL;
+    ALTER TABLE balance_sheets ADD COLUMN tangible_book_value REAL;
+"#, r#"
+    ALTER TABLE cash_flow_statements ADD COLUMN share_repurchases REAL;
+    ALTER TABLE cash_flow_statements ADD COLUMN share_issuance REAL;
+    ALTER TABLE cash_flow_statements ADD COLUMN debt_issued REAL;
+    ALTER TABLE cash_flow_statements ADD COLUMN debt_repaid REAL;
+    ALTER TABLE cash_flow_statements ADD COLUMN acquisitions REAL;
+"#, r#"
+    ALTER TABLE stock_reports ADD COLUMN currency TEXT;
+    ALTER TABLE stock_reports ADD COLUMN unit_scale TEXT;
+"#, r#"
+    -- Dates and length of each period; kept with the share price as the rest
+    -- of the table is statement data
+    ALTER TABLE financial_ratios ADD COLUMN period_start TEXT;
+    ALTER TABLE financial_ratios ADD COLUMN period_end TEXT;
+    ALTER TABLE financial_ratios ADD COLUMN period_weeks INTEGER;
+    ALTER TABLE financial_ratios ADD COLUMN filing_date TEXT;
+"#, r#"
+    CREATE TABLE corporate_actions (
+        ticker      TEXT NOT NULL REFERENCES stock_reports(ticker) ON DELETE CASCADE,
+        position    INTEGER NOT NULL,
+        date        TEXT NOT NULL,
+        kind        TEXT NOT NULL,
+        ratio       REAL NOT NULL,
+        note        TEXT,
+        PRIMARY KEY (ticker, position)
+    );
+"#, r#"
+    CREATE TABLE custom_metrics (
+        id          TEXT PRIMARY KEY,
+        formula     TEXT NOT NULL,
+        description TEXT
+    );
+"#, r#"
+    -- Some builds ran migration 2 in a form that kept the figures matching no
+    -- formula, i.e. reported ones, but left their provenance empty. Record them
+    -- as reported so the row no longer depends on how it was migrated.
+    UPDATE income_statements SET provenance = (
+        SELECT json_group_object(field, 'reported') FROM (
+            SELECT 'gross-profit-margin' AS field WHERE gross_profit_margin IS NOT NULL
+            UNION ALL SELECT 'operating-income' WHERE operating_income IS NOT NULL
+            UNION ALL SELECT 'operating-profit-margin' WHERE operating_profit_margin IS NOT NULL
+            UNION ALL SELECT 'net-profit-margin' WHERE net_profit_margin IS NOT NULL
+        )
+    )
+    WHERE provenance = '{}';
+"#];
+
+const SELECT_HEADER: &str = "SELECT ticker, latest_update, version, currency, unit_scale FROM stock_reports";
+
+const SELECT_REPORTS: &str = r#"
+    SELECT i.year,
+           i.revenue, i.total_cogs, i.gross_profit_margin, i.operating_expense, i.operating_income,
+           i.operating_profit_margin, i.interest_expense, i.net_income, i.net_profit_margin,
+           i.eps_basic, i.shares_outstanding_basic,
+           b.cash_and_equivalents, b.total_assets, b.short_term_debt, b.long_term_debt, b.total_liabilities,
+           c.operating_cash_flow, c.investing_cash_flow, c.capital_expenditure, c.financing_cash_flow,
+           c.dividends_paid, c.dividends_per_share,
+           f.avg_share_price,
+           i.gross_profit, i.provenance,
+           b.total_debt, b.total_equity, b.debt_to_capital, b.provenance,
+           c.free_cash_flow, c.fcf_per_share, c.provenance,
+           i.quarter,
+           i.research_and_development, i.selling_general_administrative, i.depreciation_and_amortization, i.pretax_income, i.income_tax,
+           i.stock_based_compensation, i.eps_diluted, i.shares_outstanding_diluted, i.ebit, i.ebitda,
+           i.effective_tax_rate, i.rd_intensity, i.dilution,
+           b.current_assets, b.current_liabilities, b.inventory, b.receivables,
+           b.payables, b.goodwill, b.intangibles, b.retained_earnings,
+           b.current_ratio, b.quick_ratio, b.net_debt, b.tangible_book_value,
+           c.share_repurchases, c.share_issuance, c.debt_issued, c.debt_repaid, c.acquisitions,
+           f.period_start, f.period_end, f.period_weeks, f.filing_date
+    FROM income_statements i
+    JOIN balance_sheets b ON b.ticker = i.ticker AND b.year = i.year AND b.quarter = i.quarter
+    JOIN cash_flow_statements c ON c.ticker = i.ticker AND c.year = i.year AND c.quarter = i.quarter
+    JOIN financial_ratios f ON f.ticker = i.ticker AND f.year = i.year AND f.quarter = i.quarter
+    WHERE i.ticker = ?1
+    ORDER BY i.year, i.quarter
+"#;
+
+/// Stores reports in a single local SQLite file. Only the statement inputs and
+/// reported or overridden figures are persisted, one row per ticker and period
+/// (a year, or a quarter of it); everything computed is recomputed on load.
+pub struct SqliteReportStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteReportStore {
+    pub fn open(path: &str) -> rusqlite::Result<SqliteReportStore> {
+        let mut connection = Connection::open(path)?;
+        connection.pragma_update(None, "foreign_keys", true)?;
+        migrate(&mut connection)?;
+
+        Ok(SqliteReportStore {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Connection>, StoreError> {
+        self.connection
+            .lock()
+            .map_err(|_| StoreError::Backend(String::from("sqlite connection lock poisoned")))
+    }
+}
+
+fn migrate(connection: &mut Connection) -> rusqlite::Result<()> {
+    let current: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+
+    for (idx, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+        let tx = connection.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", idx + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+/// Appends `revision` inside the transaction of the change it records, numbered
+/// one past the ticker's latest.
+fn insert_revision(tx: &Transaction, revision: &Revision) -> Result<(), StoreError> {
+    // Revisions are write-once snapshots, so they are kept as JSON rather than normalized
+    let snapshot = revision
+        .report
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()
+        .map_err(|err| StoreError::Backend(err.to_string()))?;
+
+    // A single statement, so the number is picked and taken in one step
+    tx.execute(
+        "INSERT INTO report_revisions
+         SELECT ?1, COALESCE(MAX(revision), 0) + 1, ?2, ?3, ?4 FROM report_revisions WHERE ticker = ?1",
+        params![revision.ticker, revision.timestamp, revision.description, snapshot],
+    )
+    .map_err(backend_error)?;
+    Ok(())
+}
+
+fn backend_error(err: rusqlite::Error) -> StoreError {
+    error!("SQLite error: {}", err);
+    StoreError::Backend(err.to_string())
+}
+
+fn revision_from_row(row: &Row) -> rusqlite::Result<Revision> {
+    let snapshot: Option<String> = row.get(4)?;
+    let report = snapshot
+        .map(|json| serde_json::from_str(&json))
+        .transpose()
+        .map_err(|err| rusqlite::Error::FromSqlConversionFailure(4, rusqlite::types::Type::Text, Box::new(err)))?;
+
+    Ok(Revision {
+        ticker: row.get(0)?,
+        revision: row.get(1)?,
+        timestamp: row.get(2)?,
+        description: row.get(3)?,
+        report,
+    })
+}
+
+fn custom_metric_from_row(row: &Row) -> rusqlite::Result<CustomMetric> {
+    Ok(CustomMetric {
+        id: row.get(0)?,
+        formula: row.get(1)?,
+        description: row.get(2)?,
+    })
+}
+
+fn provenance_from_row(row: &Row, idx: usize) -> rusqlite::Result<ProvenanceMap> {
+    let json: String = row.get(idx)?;
+    serde_json::from_str(&json)
+        .map_err(|err| rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Text, Box::new(err)))
+}
+
+/// Computed entries are left out; they are recomputed on load anyway.
+fn provenance_to_json(provenance: &ProvenanceMap) -> String {
+    let kept: ProvenanceMap = provenance
+        .iter()
+        .filter(|(_, source)| **source != Provenance::Computed)
+        .map(|(field, source)| (field.clone(), *source))
+        .collect();
+    serde_json::to_string(&kept).unwrap_or_else(|_| String::from("{}"))
+}
+
+/// The quarter (0 for a full year) and the statements of one period.
+fn period_from_row(row: &Row) -> rusqlite::Result<(u8, Report)> {
+    Ok((row.get(33)?, report_from_row(row)?))
+}
+
+fn report_from_row(row: &Row) -> rusqlite::Result<Report> {
+    Ok(Report {
+        year: row.get(0)?,
+        fiscal_period: FiscalPeriod {
+            start: row.get(64)?,
+            end: row.get(65)?,
+            weeks: row.get(66)?,
+            filing_date: row.get(67)?,
+        },
+        yoy_base_year: None,
+        yoy_annualized: false,
+        income_statement: IncomeStatement {
+            revenue: row.get(1)?,
+            total_cogs: row.get(2)?,
+            gross_profit: row.get(24)?,
+            gross_profit_margin: row.get(3)?,
+            operating_expense: row.get(4)?,
+            operating_income: row.get(5)?,
+            operating_profit_margin: row.get(6)?,
+            interest_expense: row.get(7)?,
+            net_income: row.get(8)?,
+            net_profit_margin: row.get(9)?,
+            eps_basic: row.get(10)?,
+            shares_outstanding_basic: row.get(11)?,
+            research_and_development: row.get(34)?,
+            selling_general_administrative: row.get(35)?,
+            depreciation_and_amortization: row.get(36)?,
+            pretax_income: row.get(37)?,
+            income_tax: row.get(38)?,
+            stock_based_compensation: row.get(39)?,
+            eps_diluted: row.get(40)?,
+            shares_outstanding_diluted: row.get(41)?,
+            ebit: row.get(42)?,
+            ebitda: row.get(43)?,
+            effective_tax_rate: row.get(44)?,
+            rd_intensity: row.get(45)?,
+            dilution: row.get(46)?,
+            provenance: provenance_from_row(row, 25)?,
+        },
+        income_statement_yoy: None,
+        balance_sheet: BalanceSheet {
+            cash_and_equivalents: row.get(12)?,
+            total_assets: row.get(13)?,
+            short_term_debt: row.get(14)?,
+            long_term_debt: row.get(15)?,
+            total_liabilities: row.get(16)?,
+            total_debt: row.get(26)?,
+            total_equity: row.get(27)?,
+            debt_to_capital: row.get(28)?,
+            current_assets: row.get(47)?,
+            current_liabilities: row.get(48)?,
+            inventory: row.get(49)?,
+            receivables: row.get(50)?,
+            payables: row.get(51)?,
+            goodwill: row.get(52)?,
+            intangibles: row.get(53)?,
+            retained_earnings: row.get(54)?,
+            current_ratio: row.get(55)?,
+            quick_ratio: row.get(56)?,
+            net_debt: row.get(57)?,
+            tangible_book_value: row.get(58)?,
+            provenance: provenance_from_row(row, 29)?,
+        },
+        balance_sheet_yoy: None,
+        cash_flow_statement: CashFlowStatement {
+            operating_cash_flow: row.get(17)?,
+            investing_cash_flow: row.get(18)?,
+            capital_expenditure: row.get(19)?,
+            financing_cash_flow: row.get(20)?,
+            dividends_paid: row.get(21)?,
+            dividends_per_share: row.get(22)?,
+            free_cash_flow: row.get(30)?,
+            fcf_per_share: row.get(31)?,
+            share_repurchases: row.get(59)?,
+            share_issuance: row.get(60)?,
+            debt_issued: row.get(61)?,
+            debt_repaid: row.get(62)?,
+            acquisitions: row.get(63)?,
+            provenance: provenance_from_row(row, 32)?,
+        },
+        cash_flow_statement_yoy: None,
+        financial_ratios: FinancialRatios {
+            avg_share_price: row.get(23)?,
+            avg_yield: None,
+            dividend_growth_rate: None,
+            eps_payout_ratio: None,
+            fcf_payout_ratio: None,
+            buyback_yield: None,
+            net_payout_yield: None,
+            cash_returned_to_fcf: None,
+            pe_ratio: None,
+            return_on_equity: None,
+            price_to_ebit: None,
+            price_to_opcf: None,
+            price_to_fcf: None,
+            fcf_yield: None,
+            book_value_per_share: None,
+            tangible_book_value_per_share: None,
+            price_to_book: None,
+            dgr1: None,
+            dgr3: None,
+            dgr5: None,
+            dgr10: None,
+            dgr15: None,
+            dgr20: None,
+            dgr1_base_year: None,
+            dgr3_base_year: None,
+            dgr5_base_year: None,
+            dgr10_base_year: None,
+            dgr15_base_year: None,
+            dgr20_base_year: None,
+        },
+        metrics: BTreeMap::new(),
+        metrics_yoy: None,
+        custom_metrics: BTreeMap::new(),
+        cagr: BTreeMap::new(),
+    })
+}
+
+/// A report with everything but its periods, from a `SELECT_HEADER` row.
+fn header_from_row(row: &Row) -> rusqlite::Result<AnnualStockReport> {
+    let unit_scale: Option<String> = row.get(4)?;
+    Ok(AnnualStockReport {
+        latest_update: row.get(1)?,
+        ticker: row.get(0)?,
+        version: row.get(2)?,
+        currency: row.get(3)?,
+        // Written by this store, so an unknown name can only come from a newer build
+        unit_scale: unit_scale.as_deref().and_then(UnitScale::parse),
+        data: Vec::new(),
+        quarters: Vec::new(),
+        ttm: None,
+        corporate_actions: Vec::new(),
+    })
+}
+
+fn corporate_action_from_row(row: &Row) -> rusqlite::Result<CorporateAction> {
+    let kind: String = row.get(1)?;
+    Ok(CorporateAction {
+        date: row.get(0)?,
+        kind: ActionKind::parse(&kind).ok_or_else(|| {
+            rusqlite::Error::FromSqlConversionFailure(1, rusqlite::types::Type::Text, format!("unknown kind {}", kind).into())
+        })?,
+        ratio: row.get(2)?,
+        note: row.get(3)?,
+    })
+}
+
+fn load_report(connection: &Connection, ticker: &str) -> rusqlite::Result<Option<AnnualStockReport>> {
+    let header = connection
+        .query_row(&format!("{} WHERE ticker = ?1", SELECT_HEADER), params![ticker], header_from_row)
+        .optional()?;
+
+    match header {
+        Some(header) => load_years(connection, header).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn load_years(connection: &Connection, mut report: AnnualStockReport) -> rusqlite::Result<AnnualStockReport> {
+    report.corporate_actions = connection
+        .prepare_cached("SELECT date, kind, ratio, note FROM corporate_actions WHERE ticker = ?1 ORDER BY position")?
+        .query_map(params![report.ticker], corporate_action_from_row)?
+        .collect::<rusqlite::Result<Vec<CorporateAction>>>()?;
+
+    let mut statement = connection.prepare_cached(SELECT_REPORTS)?;
+    let periods = statement
+        .query_map(params![report.ticker], period_from_row)?
+        .collect::<rusqlite::Result<Vec<(u8, Report)>>>()?;
+
+    for (quarter, period) in periods {
+        match quarter {
+            0 => report.data.push(period),
+            quarter => report.quarters.push(QuarterlyReport {
+                year: period.year,
+                quarter,
+                income_statement: period.income_statement,
+                balance_sheet: period.balance_sheet,
+                cash_flow_statement: period.cash_flow_statement,
+                avg_share_price: period.financial_ratios.avg_share_price,
+                fiscal_period: period.fiscal_period,
+            }),
+        }
+    }
+
+    report.compute_optional_if_required();
+    Ok(report)
+}
+
+/// Builds the header query for a listing. Nulls in `latest_update` are
+/// coalesced to the smallest value so they sort first, as in the other stores.
+fn list_sql(query: &ListQuery) -> (String, Vec<Value>) {
+    const SORT_UPDATE: &str = "COALESCE(latest_update, -9223372036854775808)";
+
+    let mut sql = format!("{} s WHERE 1 = 1", SELECT_HEADER);
+    let mut values: Vec<Value> = Vec::new();
+
+    if let Some(prefix) = &query.ticker_prefix {
+        sql.push_str(" AND substr(ticker, 1, length(?)) = ?");
+        values.push(Value::Text(prefix.clone()));
+        values.push(Value::Text(prefix.clone()));
+    }
+    if query.year_from.is_some() || query.year_to.is_some() {
+        sql.push_str(" AND EXISTS (SELECT 1 FROM income_statements i WHERE i.ticker = s.ticker AND i.quarter = 0 AND i.year BETWEEN ? AND ?)");
+        values.push(Value::Integer(query.year_from.unwrap_or(i32::MIN).into()));
+        values.push(Value::Integer(query.year_to.unwrap_or(i32::MAX).into()));
+    }
+    if let Some(year) = query.has_year {
+        sql.push_str(" AND EXISTS (SELECT 1 FROM income_statements i WHERE i.ticker = s.ticker AND i.quarter = 0 AND i.year = ?)");
+        values.push(Value::Integer(year.into()));
+    }
+
+    let (comparison, direction) = match query.order {
+        SortOrder::Asc => (">", "ASC"),
+        SortOrder::Desc => ("<", "DESC"),
+    };
+    if let Some(cursor) = &query.after {
+        match cursor.sort {
+            SortKey::Ticker => sql.push_str(&format!(" AND ticker {} ?", comparison)),
+            SortKey::LatestUpdate => {
+                sql.push_str(&format!(" AND ({}, ticker) {} (?, ?)", SORT_UPDATE, comparison));
+                values.push(Value::Integer(cursor.latest_update.unwrap_or(i64::MIN)));
+            }
+        }
+        values.push(Value::Text(cursor.ticker.clone()));
+    }
+
+    match query.sort {
+        SortKey::Ticker => sql.push_str(&format!(" ORDER BY ticker {}", direction)),
+        SortKey::LatestUpdate => {
+            sql.push_str(&format!(" ORDER BY {} {}, ticker {}", SORT_UPDATE, direction, direction))
+        }
+    }
+    if let Some(limit) = query.limit {
+        // One extra row tells us whether there is a next page
+        sql.push_str(" LIMIT ?");
+        values.push(Value::Integer(limit as i64 + 1));
+    }
+
+    (sql, values)
+}
+
+fn insert_years(tx: &Transaction, report: &AnnualStockReport) -> rusqlite::Result<()> {
+    let ticker = report.ticker.as_str();
+    for year in report.data.iter() {
+        insert_period(
+            tx,
+            ticker,
+            (year.year, 0),
+            &year.income_statement,
+            &year.balance_sheet,
+            &year.cash_flow_statement,
+            (year.financial_ratios.avg_share_price, &year.fiscal_period),
+        )?;
+    }
+    for quarter in report.quarters.iter() {
+        insert_period(
+            tx,
+            ticker,
+            quarter.period(),
+            &quarter.income_statement,
+            &quarter.balance_sheet,
+            &quarter.cash_flow_statement,
+            (quarter.avg_share_price, &quarter.fiscal_period),
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes the rows of one period; `quarter` is 0 for a full year.
+fn insert_period(
+    tx: &Transaction,
+    ticker: &str,
+    (year, quarter): (i32, u8),
+    in_state: &IncomeStatement,
+    bl_sheet: &BalanceSheet,
+    cfs: &CashFlowStatement,
+    (avg_share_price, fiscal_period): (f64, &FiscalPeriod),
+) -> rusqlite::Result<()> {
+    let kept = |value: Option<f64>, field: &str| supplied(value, &in_state.provenance, field);
+    tx.execute(
+        "INSERT INTO income_statements VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16,
+         ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27, ?28, ?29)",
+        params![
+            ticker,
+            year,
+            quarter,
+            in_state.revenue,
+            in_state.total_cogs,
+            kept(in_state.gross_profit_margin, "gross-profit-margin"),
+            in_state.operating_expense,
+            kept(in_state.operating_income, "operating-income"),
+            kept(in_state.operating_profit_margin, "operating-profit-margin"),
+            in_state.interest_expense,
+            in_state.net_income,
+            kept(in_state.net_profit_margin, "net-profit-margin"),
+            in_state.eps_basic,
+            in_state.shares_outstanding_basic,
+            kept(in_state.gross_profit, "gross-profit"),
+            provenance_to_json(&in_state.provenance),
+            in_state.research_and_development,
+            in_state.selling_general_administrative,
+            in_state.depreciation_and_amortization,
+            in_state.pretax_income,
+            in_state.income_tax,
+            in_state.stock_based_compensation,
+            in_state.eps_diluted,
+            in_state.shares_outstanding_diluted,
+            kept(in_state.ebit, "ebit"),
+            kept(in_state.ebitda, "ebitda"),
+            kept(in_state.effective_tax_rate, "effective-tax-rate"),
+            kept(in_state.rd_intensity, "rd-intensity"),
+            kept(in_state.dilution, "dilution"),
+        ],
+    )?;
+
+    let kept = |value: Option<f64>, field: &str| supplied(value, &bl_sheet.provenance, field);
+    tx.execute(
+        "INSERT INTO balance_sheets VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12,
+         ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24)",
+        params![
+            ticker,
+            year,
+            quarter,
+            bl_sheet.cash_and_equivalents,
+            bl_sheet.total_assets,
+            bl_sheet.short_term_debt,
+            bl_sheet.long_term_debt,
+            bl_sheet.total_liabilities,
+            kept(bl_sheet.total_debt, "total-debt"),
+            kept(bl_sheet.total_equity, "total-equity"),
+            kept(bl_sheet.debt_to_capital, "debt-to-capital"),
+            provenance_to_json(&bl_sheet.provenance),
+            bl_sheet.current_assets,
+            bl_sheet.current_liabilities,
+            bl_sheet.inventory,
+            bl_sheet.receivables,
+            bl_sheet.payables,
+            bl_sheet.goodwill,
+            bl_sheet.intangibles,
+            bl_sheet.retained_earnings,
+            kept(bl_sheet.current_ratio, "current-ratio"),
+            kept(bl_sheet.quick_ratio, "quick-ratio"),
+            kept(bl_sheet.net_debt, "net-debt"),
+            kept(bl_sheet.tangible_book_value, "tangible-book-value"),
+        ],
+    )?;
+
+    let kept = |value: Option<f64>, field: &str| supplied(value, &cfs.provenance, field);
+    tx.execute(
+        "INSERT INTO cash_flow_statements VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12,
+         ?13, ?14, ?15, ?16, ?17)",
+        params![
+            ticker,
+            year,
+            quarter,
+            cfs.operating_cash_flow,
+            cfs.investing_cash_flow,
+            cfs.capital_expenditure,
+            cfs.financing_cash_flow,
+            cfs.dividends_paid,
+            cfs.dividends_per_share,
+            kept(cfs.free_cash_flow, "free-cash-flow"),
+            kept(cfs.fcf_per_share, "fcf-per-share"),
+            provenance_to_json(&cfs.provenance),
+            cfs.share_repurchases,
+            cfs.share_issuance,
+            cfs.debt_issued,
+            cfs.debt_repaid,
+            cfs.acquisitions,
+        ],
+    )?;
+
+    tx.execute(
+        "INSERT INTO financial_ratios VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            ticker,
+            year,
+            quarter,
+            avg_share_price,
+            fiscal_period.start,
+            fiscal_period.end,
+            fiscal_period.weeks,
+            fiscal_period.filing_date
+        ],
+    )?;
+    Ok(())
+}
+
+fn replace_corporate_actions(tx: &Transaction, report: &AnnualStockReport) -> rusqlite::Result<()> {
+    tx.execute("DELETE FROM corporate_actions WHERE ticker = ?1", params![report.ticker])?;
+    for (position, action) in report.corporate_actions.iter().enumerate() {
+        tx.execute(
+            "INSERT INTO corporate_actions VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![report.ticker, position, action.date, action.kind.as_str(), action.ratio, action.note],
+        )?;
+    }
+    Ok(())
+}
+
+fn delete_years(tx: &Transaction, ticker: &str) -> rusqlite::Result<()> {
+    for table in ["income_statements", "balance_sheets", "cash_flow_statements", "financial_ratios"] {
+        tx.execute(&format!("DELETE FROM {} WHERE ticker = ?1", table), params![ticker])?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl ReportStore for SqliteReportStore {
+    async fn get(&self, ticker: &str) -> Result<Option<AnnualStockReport>, StoreError> {
+        let connection = self.lock()?;
+        load_report(&connection, ticker).map_err(backend_error)
+    }
+
+    async fn list(&self, query: &ListQuery) -> Result<ReportPage, StoreError> {
+        let connection = self.lock()?;
+        let (sql, values) = list_sql(query);
+        let headers = connection
+            .prepare(&sql)
+            .and_then(|mut statement| {
+                statement
+                    .query_map(params_from_iter(values.iter()), header_from_row)?
+                    .collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .map_err(backend_error)?;
+
+        let mut reports = Vec::new();
+        for header in headers {
+            let report = match query.projection.stored() {
+                Projection::Summary => header,
+                _ => load_years(&connection, header).map_err(backend_error)?,
+            };
+            reports.push(report);
+        }
+        Ok(ReportPage::from_overfetch(reports, query))
+    }
+
+    async fn create(&self, report: &AnnualStockReport, revision: &Revision) -> Result<(), StoreError> {
+        let mut connection = self.lock()?;
+        let tx = connection.transaction().map_err(backend_error)?;
+
+        let inserted = tx
+            .execute(
+                "INSERT OR IGNORE INTO stock_reports (ticker, latest_update, version, currency, unit_scale)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    report.ticker,
+                    report.latest_update,
+                    report.version,
+                    report.currency,
+                    report.unit_scale.map(UnitScale::as_str)
+                ],
+            )
+            .map_err(backend_error)?;
+        if inserted == 0 {
+            return Err(StoreError::AlreadyExists(report.ticker.clone()));
+        }
+
+        insert_years(&tx, report).map_err(backend_error)?;
+        replace_corporate_actions(&tx, report).map_err(backend_error)?;
+        insert_revision(&tx, revision)?;
+        tx.commit().map_err(backend_error)
+    }
+
+    async fn replace(&self, report: &AnnualStockReport, expected_version: i32, revision: &Revision) -> Result<(), StoreError> {
+        let mut connection = self.lock()?;
+        let tx = connection.transaction().map_err(backend_error)?;
+
+        let updated = tx
+            .execute(
+                "UPDATE stock_reports SET latest_update = ?1, version = ?2, currency = ?3, unit_scale = ?4
+                 WHERE ticker = ?5 AND version = ?6",
+                params![
+                    report.latest_update,
+                    report.version,
+                    report.currency,
+                    report.unit_scale.map(UnitScale::as_str),
+                    report.ticker,
+                    expected_version
+                ],
+            )
+            .map_err(backend_error)?;
+        if updated == 0 {
+            return Err(StoreError::VersionConflict {
+                ticker: report.ticker.clone(),
+                expected_version,
+            });
+        }
+
+        delete_years(&tx, &report.ticker).map_err(backend_error)?;
+        insert_years(&tx, report).map_err(backend_error)?;
+        replace_corporate_actions(&tx, report).map_err(backend_error)?;
+        insert_revision(&tx, revision)?;
+        tx.commit().map_err(backend_error)
+    }
+
+    async fn delete(&self, ticker: &str, revision: &Revision) -> Result<Option<AnnualStockReport>, StoreError> {
+        let mut connection = self.lock()?;
+        let tx = connection.transaction().map_err(backend_error)?;
+
+        let report = load_report(&tx, ticker).map_err(backend_error)?;
+        if report.is_some() {
+            tx.execute("DELETE FROM stock_reports WHERE ticker = ?1", params![ticker])
+                .map_err(backend_error)?;
+            insert_revision(&tx, revision)?;
+        }
+        tx.commit().map_err(backend_error)?;
+        Ok(report)
+    }
+
+    async fn list_revisions(&self, ticker: &str) -> Result<Vec<Revision>, StoreError> {
+        let connection = self.lock()?;
+        let mut statement = connection
+            .prepare_cached("SELECT * FROM report_revisions WHERE ticker = ?1 ORDER BY revision")
+            .map_err(backend_error)?;
+        let revisions = statement
+            .query_map(params![ticker], revision_from_row)
+            .and_then(|rows| rows.collect::<rusqlite::Result<Vec<Revision>>>())
+            .map_err(backend_error)?;
+        Ok(revisions)
+    }
+
+    async fn get_revision(&self, ticker: &str, revision: i32) -> Result<Option<Revision>, StoreError> {
+        let connection = self.lock()?;
+        connection
+            .query_row(
+                "SELECT * FROM report_revisions WHERE ticker = ?1 AND revision = ?2",
+                params![ticker, revision],
+                revision_from_row,
+            )
+            .optional()
+            .map_err(backend_error)
+    }
+
+    async fn list_custom_metrics(&self) -> Result<Vec<CustomMetric>, StoreError> {
+        let connection = self.lock()?;
+        let mut statement = connection
+            .prepare_cached("SELECT id, formula, description FROM custom_metrics ORDER BY id")
+            .map_err(backend_error)?;
+        let metrics = statement
+            .query_map([], custom_metric_from_row)
+            .and_then(|rows| rows.collect::<rusqlite::Result<Vec<CustomMetric>>>())
+            .map_err(backend_error)?;
+        Ok(metrics)
+    }
+
+    async fn put_custom_metric(&self, metric: &CustomMetric) -> Result<(), StoreError> {
+        let connection = self.lock()?;
+        connection
+            .execute(
+                "INSERT OR REPLACE INTO custom_metrics VALUES (?1, ?2, ?3)",
+                params![metric.id, metric.formula, metric.description],
+            )
+            .map(|_| ())
+            .map_err(backend_error)
+    }
+
+    async fn delete_custom_metric(&self, id: &str) -> Result<Option<CustomMetric>, StoreError> {
+        let mut connection = self.lock()?;
+        let tx = connection.transaction().map_err(backend_error)?;
+        let metric = tx
+            .query_row(
+                "SELECT id, formula, description FROM custom_metrics WHERE id = ?1",
+                params![id],
+                custom_metric_from_row,
+            )
+            .optional()
+            .map_err(backend_error)?;
+        tx.execute("DELETE FROM custom_metrics WHERE id = ?1", params![id])
+            .map_err(backend_error)?;
+        tx.commit().map_err(backend_error)?;
+        Ok(metric)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report_history;
+    use crate::report_model::tests::sample_report;
+
+    /// A database at the schema before the last migration.
+    fn before_last_migration() -> Connection {
+        let connection = Connection::open_in_memory().unwrap();
+        let last = MIGRATIONS.len() - 1;
+        for migration in &MIGRATIONS[..last] {
+            connection.execute_batch(migration).unwrap();
+        }
+        connection.pragma_update(None, "user_version", last).unwrap();
+        connection
+    }
+
+    fn insert_year(connection: &Connection, year: i32, gross_profit_margin: Option<f64>, provenance: &str) {
+        connection
+            .execute(
+                "INSERT INTO income_statements (ticker, year, quarter, revenue, total_cogs, gross_profit_margin,
+                     operating_expense, interest_expense, net_income, eps_basic, shares_outstanding_basic, provenance)
+                 VALUES ('PEP', ?1, 0, 100, 40, ?2, 30, 1, 20, 1, 20, ?3)",
+                params![year, gross_profit_margin, provenance],
+            )
+            .unwrap();
+    }
+
+    fn provenance(connection: &Connection, year: i32) -> String {
+        connection
+            .query_row("SELECT provenance FROM income_statements WHERE year = ?1", params![year], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn records_reported_figures_left_without_provenance() {
+        let mut connection = before_last_migration();
+        connection
+            .execute("INSERT INTO stock_reports (ticker, latest_update, version) VALUES ('PEP', 0, 1)", [])
+            .unwrap();
+        // Kept by the lenient migration 2, cleared by the original, and written since
+        insert_year(&connection, 2010, Some(0.7), "{}");
+        insert_year(&connection, 2011, None, "{}");
+        insert_year(&connection, 2012, Some(0.5), r#"{"gross-profit-margin":"overridden"}"#);
+
+        migrate(&mut connection).unwrap();
+        assert_eq!(provenance(&connection, 2010), r#"{"gross-profit-margin":"reported"}"#);
+        assert_eq!(provenance(&connection, 2011), "{}");
+        assert_eq!(provenance(&connection, 2012), r#"{"gross-profit-margin":"overridden"}"#);
+    }
+
+    #[test]
+    fn migrates_a_new_database() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        migrate(&mut connection).unwrap();
+        let version: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0)).unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+    }
+
+    #[actix_web::test]
+    async fn changes_are_not_saved_when_their_revision_is_not() {
+        let store = SqliteReportStore::open(":memory:").unwrap();
+        let report = sample_report();
+        let created = report_history::revision("PEP", Some(&report), String::from("created"));
+        store.create(&report, &created).await.unwrap();
+        store
+            .lock()
+            .unwrap()
+            .execute_batch(
+                "CREATE TRIGGER no_history BEFORE INSERT ON report_revisions
+                 BEGIN SELECT RAISE(FAIL, 'history unavailable'); END;",
+            )
+            .unwrap();
+
+        let mut changed = report.clone();
+        changed.version += 1;
+        let edited = report_history::revision("PEP", Some(&changed), String::from("edited"));
+        assert!(store.replace(&changed, report.version, &edited).await.is_err());
+        assert_eq!(store.get("PEP").await.unwrap().unwrap().version, report.version);
+
+        let deleted = report_history::revision("PEP", None, String::from("deleted"));
+        assert!(store.delete("PEP", &deleted).await.is_err());
+        assert!(store.get("PEP").await.unwrap().is_some());
+
+        let mut other = report.clone();
+        other.ticker = String::from("KO");
+        let created = report_history::revision("KO", Some(&other), String::from("created"));
+        assert!(store.create(&other, &created).await.is_err());
+        assert!(store.get("KO").await.unwrap().is_none());
+        assert_eq!(store.list_revisions("PEP").await.unwrap().len(), 1);
+    }
+}
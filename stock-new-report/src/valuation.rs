@@ -0,0 +1,488 @@
+//! Discounted cash flow valuation of one ticker.
+//!
+//! Free cash flow of a year is projected through one or more growth stages,
+//! then capitalized at the end either as a growing perpetuity or at an exit
+//! multiple. Everything is discounted at a single rate. Free cash flow here is
+//! operating cash flow less capital expenditure, i.e. after interest, so the
+//! discounted sum is already the value of the equity and no debt is netted.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::currency::UnitScale;
+use crate::report_computer::{self, Values};
+use crate::report_growth::{self, Undefined};
+use crate::report_model::{AnnualStockReport, Report};
+
+/// Longest projection accepted, over all stages, in years.
+const MAX_PROJECTION_YEARS: u32 = 100;
+
+/// A stretch of years with one growth rate.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Stage {
+    pub years: u32,
+    /// Yearly growth of free cash flow, e.g. 0.08 for 8%.
+    pub growth: f64,
+}
+
+/// Derives a single stage from the historical CAGR of a figure.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct HistoryGrowth {
+    #[serde(default = "HistoryGrowth::default_metric")]
+    pub metric: String,
+    /// Years of history the CAGR is measured over.
+    #[serde(default = "HistoryGrowth::default_years")]
+    pub window: u32,
+    /// Years the historical rate is projected for.
+    #[serde(default = "HistoryGrowth::default_years")]
+    pub years: u32,
+}
+
+impl HistoryGrowth {
+    fn default_metric() -> String {
+        String::from("free-cash-flow")
+    }
+
+    fn default_years() -> u32 {
+        5
+    }
+}
+
+impl Default for HistoryGrowth {
+    fn default() -> HistoryGrowth {
+        HistoryGrowth {
+            metric: HistoryGrowth::default_metric(),
+            window: HistoryGrowth::default_years(),
+            years: HistoryGrowth::default_years(),
+        }
+    }
+}
+
+/// What the client asks for. Growth comes from `stages` or, when those are
+/// left out, from `growth-from-history`; the end value from exactly one of
+/// `terminal-growth` and `exit-multiple`.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct DcfRequest {
+    /// Fiscal year to start from; the latest year when left out.
+    pub year: Option<i32>,
+    pub discount_rate: f64,
+    pub stages: Option<Vec<Stage>>,
+    pub growth_from_history: Option<HistoryGrowth>,
+    /// Perpetual growth after the last stage; must be below the discount rate.
+    pub terminal_growth: Option<f64>,
+    /// Multiple of the last projected free cash flow the business is sold at.
+    pub exit_multiple: Option<f64>,
+}
+
+#[derive(Debug)]
+pub enum ValuationError {
+    NoYears(String),
+    InvalidDiscountRate(f64),
+    InvalidStage(Stage),
+    TooManyYears(u64),
+    ConflictingGrowth,
+    /// Neither or both of the terminal assumptions were given.
+    TerminalChoice,
+    InvalidTerminalGrowth { terminal_growth: f64, discount_rate: f64 },
+    InvalidExitMultiple(f64),
+    UnknownMetric(String),
+    HistoryUndefined { metric: String, window: u32, reason: Undefined },
+    NonPositiveCashFlow { year: i32, free_cash_flow: Option<f64> },
+    NoShares(i32),
+}
+
+impl fmt::Display for ValuationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValuationError::NoYears(ticker) => write!(f, "Ticker {} has no annual reports to value", ticker),
+            ValuationError::InvalidDiscountRate(rate) => {
+                write!(f, "discount-rate {} must be a positive fraction, e.g. 0.09", rate)
+            }
+            ValuationError::InvalidStage(stage) => write!(
+                f,
+                "Stage of {} years at {} needs 1 to {} years and growth above -100%",
+                stage.years, stage.growth, MAX_PROJECTION_YEARS
+            ),
+            ValuationError::TooManyYears(years) => write!(
+                f,
+                "The stages add up to {} years; at most {} can be projected",
+                years, MAX_PROJECTION_YEARS
+            ),
+            ValuationError::ConflictingGrowth => {
+                write!(f, "Give either stages or growth-from-history, not both")
+            }
+            ValuationError::TerminalChoice => write!(f, "Give exactly one of terminal-growth and exit-multiple"),
+            ValuationError::InvalidTerminalGrowth { terminal_growth, discount_rate } => write!(
+                f,
+                "terminal-growth {} must be above -100% and below the discount rate {}",
+                terminal_growth, discount_rate
+            ),
+            ValuationError::InvalidExitMultiple(multiple) => {
+                write!(f, "exit-multiple {} must be a positive number", multiple)
+            }
+            ValuationError::UnknownMetric(metric) => write!(f, "There is no figure or metric called {}", metric),
+            ValuationError::HistoryUndefined { metric, window, reason } => write!(
+                f,
+                "The {}-year CAGR of {} is undefined: {}; give the stages instead",
+                window, metric, reason
+            ),
+            ValuationError::NonPositiveCashFlow { year, free_cash_flow } => write!(
+                f,
+                "Free cash flow of {} is {:?}; a DCF needs a positive starting cash flow",
+                year, free_cash_flow
+            ),
+            ValuationError::NoShares(year) => write!(f, "The report for {} has no shares outstanding", year),
+        }
+    }
+}
+
+impl std::error::Error for ValuationError {}
+
+/// Where the growth stages came from.
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "kebab-case", tag = "source")]
+pub enum GrowthSource {
+    Supplied,
+    History {
+        metric: String,
+        window: u32,
+        #[serde(rename = "base-year")]
+        base_year: i32,
+    },
+}
+
+/// Every assumption the valuation ran with, defaults and derived growth included.
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct Assumptions {
+    pub year: i32,
+    pub discount_rate: f64,
+    pub stages: Vec<Stage>,
+    pub growth: GrowthSource,
+    pub terminal_growth: Option<f64>,
+    pub exit_multiple: Option<f64>,
+}
+
+/// The starting figures, as presented: split-adjusted and converted if asked.
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct Starting {
+    pub free_cash_flow: f64,
+    pub shares_outstanding_basic: f64,
+    pub avg_share_price: f64,
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct ProjectedYear {
+    pub year: i32,
+    pub growth: f64,
+    pub free_cash_flow: f64,
+    pub discount_factor: f64,
+    pub present_value: f64,
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct TerminalValue {
+    /// Value at the end of the last projected year.
+    pub value: f64,
+    pub present_value: f64,
+    /// Part of the equity value that comes from the terminal value.
+    pub share_of_value: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct DcfValuation {
+    pub ticker: String,
+    pub currency: Option<String>,
+    pub unit_scale: Option<UnitScale>,
+    pub assumptions: Assumptions,
+    pub starting: Starting,
+    pub projections: Vec<ProjectedYear>,
+    pub terminal: TerminalValue,
+    /// In the report's unit scale, like the statements.
+    pub equity_value: f64,
+    /// In plain currency units, like the share price.
+    pub fair_value_per_share: f64,
+    /// How far the price is below the fair value, e.g. 0.25 when the shares
+    /// trade at 75% of it. Negative when they trade above it.
+    pub margin_of_safety: Option<f64>,
+}
+
+impl DcfRequest {
+    fn check(&self) -> Result<(), ValuationError> {
+        if !(self.discount_rate.is_finite() && self.discount_rate > 0.0) {
+            return Err(ValuationError::InvalidDiscountRate(self.discount_rate));
+        }
+        if self.stages.is_some() && self.growth_from_history.is_some() {
+            return Err(ValuationError::ConflictingGrowth);
+        }
+        match (self.terminal_growth, self.exit_multiple) {
+            (Some(terminal_growth), None) => {
+                if !terminal_growth.is_finite() || terminal_growth <= -1.0 || terminal_growth >= self.discount_rate {
+                    return Err(ValuationError::InvalidTerminalGrowth {
+                        terminal_growth,
+                        discount_rate: self.discount_rate,
+                    });
+                }
+            }
+            (None, Some(multiple)) => {
+                if !(multiple.is_finite() && multiple > 0.0) {
+                    return Err(ValuationError::InvalidExitMultiple(multiple));
+                }
+            }
+            _ => return Err(ValuationError::TerminalChoice),
+        }
+        Ok(())
+    }
+}
+
+fn check_stages(stages: &[Stage]) -> Result<(), ValuationError> {
+    if let Some(stage) = stages.iter().find(|stage| {
+        !(1..=MAX_PROJECTION_YEARS).contains(&stage.years) || !stage.growth.is_finite() || stage.growth <= -1.0
+    }) {
+        return Err(ValuationError::InvalidStage(*stage));
+    }
+    // Wide enough that client input cannot overflow it
+    let years: u64 = stages.iter().map(|stage| u64::from(stage.years)).sum();
+    if years == 0 || years > u64::from(MAX_PROJECTION_YEARS) {
+        return Err(ValuationError::TooManyYears(years));
+    }
+    Ok(())
+}
+
+/// A single stage at the historical CAGR of `history.metric`.
+fn stages_from_history(
+    report: &AnnualStockReport,
+    year: &Report,
+    history: &HistoryGrowth,
+) -> Result<(Vec<Stage>, GrowthSource), ValuationError> {
+    if !Values::is_known(&history.metric) {
+        return Err(ValuationError::UnknownMetric(history.metric.clone()));
+    }
+    let undefined = |reason| ValuationError::HistoryUndefined {
+        metric: history.metric.clone(),
+        window: history.window,
+        reason,
+    };
+    let base = report
+        .get_year(year.year - history.window as i32)
+        .ok_or_else(|| undefined(Undefined::MissingBaseYear))?;
+
+    let values = report_computer::history(report);
+    let value_in = |report: &Report| values.get(&report.year).and_then(|values| values.get(&history.metric));
+    let elapsed = report_growth::elapsed_years(
+        (year.year, &year.fiscal_period),
+        (base.year, &base.fiscal_period),
+    );
+    let growth = report_growth::compound(value_in(year), value_in(base), elapsed).map_err(undefined)?;
+
+    Ok((
+        vec![Stage {
+            years: history.years,
+            growth,
+        }],
+        GrowthSource::History {
+            metric: history.metric.clone(),
+            window: history.window,
+            base_year: base.year,
+        },
+    ))
+}
+
+/// Values `report`, which should already be presented the way the client
+/// asked for it.
+pub fn dcf(report: &AnnualStockReport, request: DcfRequest) -> Result<DcfValuation, ValuationError> {
+    request.check()?;
+
+    // The handler already turned a year that is not on file into a 404
+    let year = match request.year {
+        Some(year) => report.get_year(year),
+        None => report.data.last(),
+    }
+    .ok_or_else(|| ValuationError::NoYears(report.ticker.clone()))?;
+
+    let (stages, growth) = match request.stages.clone() {
+        Some(stages) => (stages, GrowthSource::Supplied),
+        None => stages_from_history(report, year, &request.growth_from_history.clone().unwrap_or_default())?,
+    };
+    check_stages(&stages)?;
+
+    let free_cash_flow = match year.cash_flow_statement.free_cash_flow {
+        Some(fcf) if fcf > 0.0 => fcf,
+        fcf => {
+            return Err(ValuationError::NonPositiveCashFlow {
+                year: year.year,
+                free_cash_flow: fcf,
+            })
+        }
+    };
+    let shares = year.income_statement.shares_outstanding_basic;
+    if shares <= 0.0 {
+        return Err(ValuationError::NoShares(year.year));
+    }
+
+    let rate = request.discount_rate;
+    let mut projections = Vec::new();
+    let mut cash_flow = free_cash_flow;
+    for stage in stages.iter() {
+        for _ in 0..stage.years {
+            cash_flow *= 1.0 + stage.growth;
+            let offset = projections.len() as i32 + 1;
+            let discount_factor = 1.0 / (1.0 + rate).powi(offset);
+            projections.push(ProjectedYear {
+                year: year.year + offset,
+                growth: stage.growth,
+                free_cash_flow: cash_flow,
+                discount_factor,
+                present_value: cash_flow * discount_factor,
+            });
+        }
+    }
+
+    // `check` made sure exactly one of the two is set
+    let terminal = match (request.terminal_growth, request.exit_multiple) {
+        (Some(terminal_growth), _) => cash_flow * (1.0 + terminal_growth) / (rate - terminal_growth),
+        (_, Some(multiple)) => cash_flow * multiple,
+        (None, None) => 0.0,
+    };
+    let last_factor = projections.last().map_or(1.0, |last| last.discount_factor);
+    let terminal_present_value = terminal * last_factor;
+
+    let equity_value = projections.iter().map(|projected| projected.present_value).sum::<f64>() + terminal_present_value;
+    let fair_value_per_share = equity_value / shares;
+    let price = year.financial_ratios.avg_share_price;
+    let margin_of_safety = if fair_value_per_share > 0.0 {
+        Some(1.0 - price / fair_value_per_share)
+    } else {
+        None
+    };
+
+    Ok(DcfValuation {
+        ticker: report.ticker.clone(),
+        currency: report.currency.clone(),
+        unit_scale: report.unit_scale,
+        assumptions: Assumptions {
+            year: year.year,
+            discount_rate: rate,
+            stages,
+            growth,
+            terminal_growth: request.terminal_growth,
+            exit_multiple: request.exit_multiple,
+        },
+        starting: Starting {
+            free_cash_flow,
+            shares_outstanding_basic: shares,
+            avg_share_price: price,
+        },
+        projections,
+        terminal: TerminalValue {
+            value: terminal,
+            present_value: terminal_present_value,
+            share_of_value: (equity_value > 0.0).then(|| terminal_present_value / equity_value),
+        },
+        equity_value,
+        fair_value_per_share,
+        margin_of_safety,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report_model::tests::sample_report;
+
+    fn request(stages: &[(u32, f64)]) -> DcfRequest {
+        DcfRequest {
+            year: None,
+            discount_rate: 0.1,
+            stages: Some(stages.iter().map(|&(years, growth)| Stage { years, growth }).collect()),
+            growth_from_history: None,
+            terminal_growth: None,
+            exit_multiple: Some(10.0),
+        }
+    }
+
+    fn close(actual: f64, expected: f64) -> bool {
+        (actual - expected).abs() < 1e-6 * expected.abs().max(1.0)
+    }
+
+    #[test]
+    fn discounts_each_year_and_the_exit_value() {
+        let valuation = dcf(&sample_report(), request(&[(1, 0.1)])).unwrap();
+        // 2012 free cash flow is 8479 - 2714
+        assert_eq!(valuation.starting.free_cash_flow, 5765.0);
+        assert_eq!(valuation.projections.len(), 1);
+        assert!(close(valuation.projections[0].present_value, 5765.0));
+        assert!(close(valuation.terminal.present_value, 57650.0));
+        assert!(close(valuation.equity_value, 63415.0));
+        assert!(close(valuation.fair_value_per_share, 63415.0 / 1557.0));
+    }
+
+    #[test]
+    fn gordon_growth_terminal_value() {
+        let mut request = request(&[(2, 0.0)]);
+        request.exit_multiple = None;
+        request.terminal_growth = Some(0.02);
+        let valuation = dcf(&sample_report(), request).unwrap();
+        assert!(close(valuation.terminal.value, 5765.0 * 1.02 / 0.08));
+        assert!(close(valuation.terminal.present_value, valuation.terminal.value / 1.21));
+    }
+
+    #[test]
+    fn growth_from_history() {
+        let mut request = request(&[]);
+        request.stages = None;
+        request.growth_from_history = Some(HistoryGrowth {
+            metric: String::from("revenue"),
+            window: 2,
+            years: 3,
+        });
+        let valuation = dcf(&sample_report(), request).unwrap();
+        assert_eq!(valuation.projections.len(), 3);
+        assert!(close(valuation.assumptions.stages[0].growth, (65492.0_f64 / 57838.0).sqrt() - 1.0));
+    }
+
+    #[test]
+    fn rejects_bad_stages() {
+        let report = sample_report();
+        let err = |stages: &[(u32, f64)]| dcf(&report, request(stages)).unwrap_err();
+        assert!(matches!(err(&[(0, 0.1)]), ValuationError::InvalidStage(_)));
+        assert!(matches!(err(&[(1, -1.0)]), ValuationError::InvalidStage(_)));
+        assert!(matches!(err(&[(u32::MAX, 0.1), (2, 0.1)]), ValuationError::InvalidStage(_)));
+        assert!(matches!(err(&[(60, 0.1), (60, 0.1)]), ValuationError::TooManyYears(120)));
+        assert!(matches!(err(&[]), ValuationError::TooManyYears(0)));
+    }
+
+    #[test]
+    fn rejects_bad_terminal_assumptions() {
+        let report = sample_report();
+        let with = |terminal_growth: Option<f64>, exit_multiple: Option<f64>| {
+            let mut request = request(&[(5, 0.05)]);
+            request.terminal_growth = terminal_growth;
+            request.exit_multiple = exit_multiple;
+            dcf(&report, request)
+        };
+        assert!(matches!(with(None, None), Err(ValuationError::TerminalChoice)));
+        assert!(matches!(with(Some(0.02), Some(10.0)), Err(ValuationError::TerminalChoice)));
+        assert!(matches!(with(Some(0.1), None), Err(ValuationError::InvalidTerminalGrowth { .. })));
+        assert!(matches!(with(Some(-1.0), None), Err(ValuationError::InvalidTerminalGrowth { .. })));
+        assert!(matches!(with(None, Some(0.0)), Err(ValuationError::InvalidExitMultiple(_))));
+        assert!(with(Some(-0.5), None).is_ok());
+    }
+
+    #[test]
+    fn needs_positive_free_cash_flow() {
+        let mut report = sample_report();
+        report.data[2].cash_flow_statement.free_cash_flow = Some(-1.0);
+        assert!(matches!(
+            dcf(&report, request(&[(1, 0.1)])),
+            Err(ValuationError::NonPositiveCashFlow { year: 2012, .. })
+        ));
+    }
+}